@@ -0,0 +1,5 @@
+DROP TABLE user_settings;
+DROP TABLE users_roles;
+DROP TABLE roles;
+DROP TABLE profiles;
+DROP TABLE users;
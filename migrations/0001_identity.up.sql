@@ -0,0 +1,70 @@
+-- Accounts, their profiles, roles and per-user settings
+CREATE TABLE users (
+    id TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    email TEXT UNIQUE,
+    password TEXT NOT NULL,
+    telephone TEXT,
+    salutation TEXT,
+    first_name TEXT,
+    middle_name TEXT,
+    last_name TEXT,
+    gender TEXT,
+    address_line_1 TEXT,
+    address_line_2 TEXT,
+    city TEXT,
+    state TEXT,
+    country TEXT,
+    date_of_birth DATE NOT NULL,
+    configuration {json},
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_users_created_at ON users (created_at);
+CREATE INDEX idx_users_updated_at ON users (updated_at);
+CREATE INDEX idx_users_country_city ON users (country, city);
+
+CREATE TABLE profiles (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL UNIQUE REFERENCES users (id) ON DELETE CASCADE,
+    telephone TEXT,
+    salutation TEXT,
+    first_name TEXT,
+    middle_name TEXT,
+    last_name TEXT,
+    gender TEXT,
+    address_line_1 TEXT,
+    address_line_2 TEXT,
+    city TEXT,
+    state TEXT,
+    country TEXT,
+    date_of_birth DATE NOT NULL,
+    configuration {json},
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE roles (
+    id {serial_pk},
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    description TEXT,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE users_roles (
+    user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role_slug TEXT NOT NULL REFERENCES roles (slug) ON UPDATE CASCADE,
+    PRIMARY KEY (user_id, role_slug)
+);
+
+CREATE INDEX idx_users_roles_role_slug ON users_roles (role_slug);
+
+CREATE TABLE user_settings (
+    user_id TEXT PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    theme TEXT NOT NULL DEFAULT 'light',
+    language TEXT NOT NULL DEFAULT 'en',
+    notifications BOOLEAN NOT NULL DEFAULT TRUE
+);
@@ -0,0 +1,3 @@
+DROP TABLE user_token_cutoffs;
+DROP TABLE revoked_tokens;
+DROP TABLE refresh_tokens;
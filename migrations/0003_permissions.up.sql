@@ -0,0 +1,11 @@
+CREATE TABLE permissions (
+    slug TEXT PRIMARY KEY,
+    description TEXT,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE role_permissions (
+    role_slug TEXT NOT NULL REFERENCES roles (slug) ON UPDATE CASCADE ON DELETE CASCADE,
+    permission_slug TEXT NOT NULL REFERENCES permissions (slug) ON DELETE CASCADE,
+    PRIMARY KEY (role_slug, permission_slug)
+);
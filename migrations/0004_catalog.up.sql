@@ -0,0 +1,28 @@
+-- Amounts are stored in minor units next to their ISO 4217 currency code
+CREATE TABLE tax_rates (
+    id TEXT PRIMARY KEY,
+    country TEXT NOT NULL,
+    state TEXT,
+    tax_class TEXT NOT NULL,
+    name TEXT NOT NULL,
+    rate_bps INTEGER NOT NULL CHECK (rate_bps >= 0)
+);
+
+-- A country-wide rate has no state, which a plain UNIQUE constraint would let repeat
+CREATE UNIQUE INDEX idx_tax_rates_jurisdiction ON tax_rates (UPPER(country), COALESCE(UPPER(state), ''), tax_class);
+
+CREATE TABLE products (
+    id TEXT PRIMARY KEY,
+    sku TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    description TEXT,
+    price BIGINT NOT NULL CHECK (price >= 0),
+    currency TEXT NOT NULL,
+    tax_class TEXT NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    stock_quantity INTEGER NOT NULL DEFAULT 0 CHECK (stock_quantity >= 0),
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_products_name ON products (name);
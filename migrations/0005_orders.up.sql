@@ -0,0 +1,38 @@
+CREATE TABLE orders (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users (id),
+    status TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    subtotal BIGINT NOT NULL,
+    tax_total BIGINT NOT NULL,
+    total BIGINT NOT NULL,
+    prices_include_tax BOOLEAN NOT NULL DEFAULT FALSE,
+    tax_rounding TEXT NOT NULL DEFAULT 'per_line',
+    notes TEXT,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_orders_user_id ON orders (user_id);
+CREATE INDEX idx_orders_created_at ON orders (created_at);
+
+CREATE TABLE order_lines (
+    id TEXT PRIMARY KEY,
+    order_id TEXT NOT NULL REFERENCES orders (id) ON DELETE CASCADE,
+    product_id TEXT REFERENCES products (id),
+    position INTEGER NOT NULL,
+    description TEXT NOT NULL,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    unit_price BIGINT NOT NULL,
+    line_total BIGINT NOT NULL,
+    currency TEXT NOT NULL,
+    tax_class TEXT NOT NULL,
+    tax_name TEXT NOT NULL,
+    tax_rate_bps INTEGER NOT NULL,
+    net_amount BIGINT NOT NULL,
+    tax_amount BIGINT NOT NULL,
+    gross_amount BIGINT NOT NULL,
+    UNIQUE (order_id, position)
+);
+
+CREATE INDEX idx_order_lines_product_id ON order_lines (product_id);
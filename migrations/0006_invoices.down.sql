@@ -0,0 +1,4 @@
+DROP TABLE number_sequences;
+DROP TABLE numbering_schemes;
+DROP TABLE invoice_lines;
+DROP TABLE invoices;
@@ -0,0 +1,61 @@
+-- `number` stays NULL while the invoice is a draft and is assigned on issue
+CREATE TABLE invoices (
+    id TEXT PRIMARY KEY,
+    number TEXT UNIQUE,
+    order_id TEXT NOT NULL REFERENCES orders (id),
+    user_id TEXT NOT NULL REFERENCES users (id),
+    status TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    subtotal BIGINT NOT NULL,
+    tax_total BIGINT NOT NULL,
+    total BIGINT NOT NULL,
+    amount_paid BIGINT NOT NULL DEFAULT 0,
+    amount_credited BIGINT NOT NULL DEFAULT 0,
+    prices_include_tax BOOLEAN NOT NULL DEFAULT FALSE,
+    tax_rounding TEXT NOT NULL DEFAULT 'per_line',
+    due_date DATE,
+    issued_at TIMESTAMP,
+    voided_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_invoices_order_id ON invoices (order_id);
+CREATE INDEX idx_invoices_user_id ON invoices (user_id);
+CREATE INDEX idx_invoices_status_due_date ON invoices (status, due_date);
+CREATE INDEX idx_invoices_issued_at ON invoices (issued_at);
+
+CREATE TABLE invoice_lines (
+    id TEXT PRIMARY KEY,
+    invoice_id TEXT NOT NULL REFERENCES invoices (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    description TEXT NOT NULL,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    unit_price BIGINT NOT NULL,
+    line_total BIGINT NOT NULL,
+    currency TEXT NOT NULL,
+    tax_class TEXT NOT NULL,
+    tax_name TEXT NOT NULL,
+    tax_rate_bps INTEGER NOT NULL,
+    net_amount BIGINT NOT NULL,
+    tax_amount BIGINT NOT NULL,
+    gross_amount BIGINT NOT NULL,
+    UNIQUE (invoice_id, position)
+);
+
+CREATE TABLE numbering_schemes (
+    tenant_id TEXT NOT NULL,
+    document_type TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    format TEXT NOT NULL,
+    reset_yearly BOOLEAN NOT NULL DEFAULT TRUE,
+    PRIMARY KEY (tenant_id, document_type)
+);
+
+CREATE TABLE number_sequences (
+    tenant_id TEXT NOT NULL,
+    document_type TEXT NOT NULL,
+    period TEXT NOT NULL,
+    last_value BIGINT NOT NULL,
+    PRIMARY KEY (tenant_id, document_type, period)
+);
@@ -0,0 +1,18 @@
+-- Refunds are rows of kind 'refund' with a positive amount
+CREATE TABLE payments (
+    id TEXT PRIMARY KEY,
+    invoice_id TEXT NOT NULL REFERENCES invoices (id),
+    user_id TEXT NOT NULL REFERENCES users (id),
+    kind TEXT NOT NULL,
+    method TEXT NOT NULL,
+    reference TEXT,
+    amount BIGINT NOT NULL CHECK (amount > 0),
+    currency TEXT NOT NULL,
+    received_at TIMESTAMP NOT NULL,
+    recorded_by TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_payments_invoice_id ON payments (invoice_id);
+CREATE INDEX idx_payments_user_id ON payments (user_id);
+CREATE INDEX idx_payments_received_at ON payments (received_at);
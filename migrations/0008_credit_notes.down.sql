@@ -0,0 +1,2 @@
+DROP TABLE credit_note_lines;
+DROP TABLE credit_notes;
@@ -0,0 +1,34 @@
+CREATE TABLE credit_notes (
+    id TEXT PRIMARY KEY,
+    number TEXT NOT NULL UNIQUE,
+    invoice_id TEXT NOT NULL REFERENCES invoices (id),
+    user_id TEXT NOT NULL REFERENCES users (id),
+    reason TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    subtotal BIGINT NOT NULL,
+    tax_total BIGINT NOT NULL,
+    total BIGINT NOT NULL,
+    issued_at TIMESTAMP NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_credit_notes_invoice_id ON credit_notes (invoice_id);
+
+CREATE TABLE credit_note_lines (
+    id TEXT PRIMARY KEY,
+    credit_note_id TEXT NOT NULL REFERENCES credit_notes (id) ON DELETE CASCADE,
+    invoice_line_id TEXT NOT NULL REFERENCES invoice_lines (id),
+    position INTEGER NOT NULL,
+    description TEXT NOT NULL,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    unit_price BIGINT NOT NULL,
+    currency TEXT NOT NULL,
+    tax_name TEXT NOT NULL,
+    tax_rate_bps INTEGER NOT NULL,
+    net_amount BIGINT NOT NULL,
+    tax_amount BIGINT NOT NULL,
+    gross_amount BIGINT NOT NULL
+);
+
+CREATE INDEX idx_credit_note_lines_invoice_line_id ON credit_note_lines (invoice_line_id);
@@ -0,0 +1,7 @@
+-- What the fixture loader has already created, for records without a natural key of their own
+CREATE TABLE seed_records (
+    seed_key TEXT PRIMARY KEY,
+    entity TEXT NOT NULL,
+    entity_id TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
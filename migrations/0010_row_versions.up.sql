@@ -0,0 +1,3 @@
+-- Bumped on every write to an order or invoice; a write that read an older version fails
+ALTER TABLE orders ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE invoices ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+DELETE FROM permissions
+WHERE slug IN ('roles:manage', 'users:read', 'catalog:manage', 'system:manage', 'dashboard:read', 'invoices:manage', 'payments:manage');
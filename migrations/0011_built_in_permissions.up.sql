@@ -0,0 +1,23 @@
+-- The permissions the admin and back-office routes check. Databases seeded before these
+-- existed get them granted to whichever built-in roles are already present.
+INSERT INTO permissions (slug, description, created_at) VALUES
+    ('roles:manage', 'Create, edit and delete roles, and assign them to users', CURRENT_TIMESTAMP),
+    ('users:read', 'List users and their effective permissions', CURRENT_TIMESTAMP),
+    ('catalog:manage', 'Maintain products and tax rates', CURRENT_TIMESTAMP),
+    ('system:manage', 'Apply migrations and configure document numbering', CURRENT_TIMESTAMP),
+    ('dashboard:read', 'View the back-office dashboard', CURRENT_TIMESTAMP),
+    ('invoices:manage', 'Create, issue, void and age invoices', CURRENT_TIMESTAMP),
+    ('payments:manage', 'Record payments and refunds, and raise credit notes', CURRENT_TIMESTAMP)
+ON CONFLICT (slug) DO NOTHING;
+
+INSERT INTO role_permissions (role_slug, permission_slug)
+SELECT 'admin', slug FROM permissions
+WHERE slug IN ('roles:manage', 'users:read', 'catalog:manage', 'system:manage', 'dashboard:read', 'invoices:manage', 'payments:manage')
+AND EXISTS (SELECT 1 FROM roles WHERE slug = 'admin')
+ON CONFLICT (role_slug, permission_slug) DO NOTHING;
+
+INSERT INTO role_permissions (role_slug, permission_slug)
+SELECT 'staff', slug FROM permissions
+WHERE slug IN ('dashboard:read', 'invoices:manage', 'payments:manage')
+AND EXISTS (SELECT 1 FROM roles WHERE slug = 'staff')
+ON CONFLICT (role_slug, permission_slug) DO NOTHING;
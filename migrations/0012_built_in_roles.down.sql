@@ -0,0 +1,4 @@
+-- Roles still held by a user stay; their grants go with the roles that are removed
+DELETE FROM roles
+WHERE slug IN ('admin', 'staff', 'customer')
+AND NOT EXISTS (SELECT 1 FROM users_roles WHERE users_roles.role_slug = roles.slug);
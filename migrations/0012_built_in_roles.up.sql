@@ -0,0 +1,21 @@
+-- The roles the application itself depends on: sign-up grants `customer` and the back office
+-- admits `admin` and `staff`. Every backend starts with them, so a fresh deployment can sign
+-- users up and promote an administrator without running the native seeder.
+INSERT INTO roles (slug, name, description, created_at, updated_at) VALUES
+    ('admin', 'Administrator', 'Full access, including roles, catalog and configuration', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP),
+    ('staff', 'Staff', 'Back-office access to orders, invoices and payments', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP),
+    ('customer', 'Customer', 'Places orders and sees their own invoices', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+ON CONFLICT (slug) DO NOTHING;
+
+INSERT INTO role_permissions (role_slug, permission_slug) VALUES
+    ('admin', 'roles:manage'),
+    ('admin', 'users:read'),
+    ('admin', 'catalog:manage'),
+    ('admin', 'system:manage'),
+    ('admin', 'dashboard:read'),
+    ('admin', 'invoices:manage'),
+    ('admin', 'payments:manage'),
+    ('staff', 'dashboard:read'),
+    ('staff', 'invoices:manage'),
+    ('staff', 'payments:manage')
+ON CONFLICT (role_slug, permission_slug) DO NOTHING;
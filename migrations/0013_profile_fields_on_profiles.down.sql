@@ -0,0 +1,34 @@
+-- The copies come back filled from `profiles`; date_of_birth returns nullable, since a NOT NULL
+-- column cannot be added to a populated table on every backend
+ALTER TABLE users ADD COLUMN telephone TEXT;
+ALTER TABLE users ADD COLUMN salutation TEXT;
+ALTER TABLE users ADD COLUMN first_name TEXT;
+ALTER TABLE users ADD COLUMN middle_name TEXT;
+ALTER TABLE users ADD COLUMN last_name TEXT;
+ALTER TABLE users ADD COLUMN gender TEXT;
+ALTER TABLE users ADD COLUMN address_line_1 TEXT;
+ALTER TABLE users ADD COLUMN address_line_2 TEXT;
+ALTER TABLE users ADD COLUMN city TEXT;
+ALTER TABLE users ADD COLUMN state TEXT;
+ALTER TABLE users ADD COLUMN country TEXT;
+ALTER TABLE users ADD COLUMN date_of_birth DATE;
+ALTER TABLE users ADD COLUMN configuration {json};
+
+UPDATE users SET
+    telephone = (SELECT p.telephone FROM profiles p WHERE p.user_id = users.id),
+    salutation = (SELECT p.salutation FROM profiles p WHERE p.user_id = users.id),
+    first_name = (SELECT p.first_name FROM profiles p WHERE p.user_id = users.id),
+    middle_name = (SELECT p.middle_name FROM profiles p WHERE p.user_id = users.id),
+    last_name = (SELECT p.last_name FROM profiles p WHERE p.user_id = users.id),
+    gender = (SELECT p.gender FROM profiles p WHERE p.user_id = users.id),
+    address_line_1 = (SELECT p.address_line_1 FROM profiles p WHERE p.user_id = users.id),
+    address_line_2 = (SELECT p.address_line_2 FROM profiles p WHERE p.user_id = users.id),
+    city = (SELECT p.city FROM profiles p WHERE p.user_id = users.id),
+    state = (SELECT p.state FROM profiles p WHERE p.user_id = users.id),
+    country = (SELECT p.country FROM profiles p WHERE p.user_id = users.id),
+    date_of_birth = (SELECT p.date_of_birth FROM profiles p WHERE p.user_id = users.id),
+    configuration = (SELECT p.configuration FROM profiles p WHERE p.user_id = users.id);
+
+DROP INDEX idx_users_email_sort;
+DROP INDEX idx_profiles_country_city;
+CREATE INDEX idx_users_country_city ON users (country, city);
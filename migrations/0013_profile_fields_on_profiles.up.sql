@@ -0,0 +1,20 @@
+-- Sign-up used to copy every profile field onto `users` as well, where nothing kept it current.
+-- `profiles` is the only copy now, and the directory's filters and sorts get indexes that match
+-- the columns and expressions it actually queries.
+DROP INDEX idx_users_country_city;
+CREATE INDEX idx_profiles_country_city ON profiles (country, city);
+CREATE INDEX idx_users_email_sort ON users ((COALESCE(email, '')), id);
+
+ALTER TABLE users DROP COLUMN telephone;
+ALTER TABLE users DROP COLUMN salutation;
+ALTER TABLE users DROP COLUMN first_name;
+ALTER TABLE users DROP COLUMN middle_name;
+ALTER TABLE users DROP COLUMN last_name;
+ALTER TABLE users DROP COLUMN gender;
+ALTER TABLE users DROP COLUMN address_line_1;
+ALTER TABLE users DROP COLUMN address_line_2;
+ALTER TABLE users DROP COLUMN city;
+ALTER TABLE users DROP COLUMN state;
+ALTER TABLE users DROP COLUMN country;
+ALTER TABLE users DROP COLUMN date_of_birth;
+ALTER TABLE users DROP COLUMN configuration;
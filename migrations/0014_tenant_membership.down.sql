@@ -0,0 +1,3 @@
+DROP INDEX idx_invoices_tenant_id;
+ALTER TABLE invoices DROP COLUMN tenant_id;
+ALTER TABLE users DROP COLUMN tenant_id;
@@ -0,0 +1,5 @@
+-- Every user belongs to one tenant and every invoice to its customer's, so the numbering an
+-- invoice or credit note draws from follows the data rather than a header the caller picks
+ALTER TABLE users ADD COLUMN tenant_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE invoices ADD COLUMN tenant_id TEXT NOT NULL DEFAULT 'default';
+CREATE INDEX idx_invoices_tenant_id ON invoices (tenant_id);
@@ -0,0 +1,249 @@
+// src/analytics.rs
+use super::*;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use db::{Backend, Database, Statement};
+use models::{DashboardPoint, DashboardQuery, DashboardStats, RoleCount};
+use money::{Currency, Money};
+use serde::{Deserialize, Serialize};
+use utils::AppError;
+
+const DEFAULT_DAYS: i64 = 30;
+const MAX_BUCKETS: usize = 400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Day,
+    Week,
+    Month,
+}
+
+impl Granularity {
+    // Weeks start on Monday, months on the 1st
+    fn bucket_start(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Granularity::Day => date,
+            Granularity::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Granularity::Month => date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    fn next(self, start: NaiveDate) -> NaiveDate {
+        match self {
+            Granularity::Day => start + Duration::days(1),
+            Granularity::Week => start + Duration::weeks(1),
+            Granularity::Month => {
+                let (year, month) = if start.month() == 12 { (start.year() + 1, 1) } else { (start.year(), start.month() + 1) };
+                NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(start)
+            }
+        }
+    }
+}
+
+struct Bucket {
+    // Start of the whole period, which labels the point
+    period_start: NaiveDate,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+}
+
+// `to` is inclusive. Buckets follow whole periods but the first and last are clipped to the range,
+// so nothing from before `from` or after `to` is counted.
+fn buckets(from: NaiveDate, to: NaiveDate, granularity: Granularity) -> Result<Vec<Bucket>, AppError> {
+    if from > to {
+        return Err(AppError::Validation("`from` must not be after `to`".to_string()));
+    }
+
+    let mut buckets = Vec::new();
+    let until = to + Duration::days(1);
+    let mut start = granularity.bucket_start(from);
+    while start <= to {
+        if buckets.len() >= MAX_BUCKETS {
+            return Err(AppError::Validation(format!(
+                "Range spans more than {} periods; use a coarser granularity",
+                MAX_BUCKETS
+            )));
+        }
+        let end = granularity.next(start);
+        buckets.push(Bucket {
+            period_start: start,
+            start: start.max(from).and_hms_opt(0, 0, 0).unwrap_or_default(),
+            end: end.min(until).and_hms_opt(0, 0, 0).unwrap_or_default(),
+        });
+        start = end;
+    }
+
+    Ok(buckets)
+}
+
+// D1 accepts at most 100 bound parameters per statement, fewer than a long range of buckets needs,
+// so the boundaries (generated here, never user input) are written inline. Postgres needs the
+// literal typed to compare it with a TIMESTAMP column; SQLite and D1 compare the text as stored.
+fn timestamp_literal(backend: Backend, value: NaiveDateTime) -> String {
+    let text = value.format("%Y-%m-%d %H:%M:%S");
+    match backend {
+        Backend::Postgres => format!("TIMESTAMP '{}'", text),
+        Backend::Sqlite | Backend::D1 => format!("'{}'", text),
+    }
+}
+
+// Date bucketing functions differ between SQLite and Postgres (strftime vs date_trunc), so the
+// periods are computed here and shipped as a VALUES table; the grouping SQL is then plain ANSI.
+// Rows are matched back to their bucket by index. SUM over BIGINT is NUMERIC on Postgres, hence
+// the explicit casts below.
+fn buckets_cte(backend: Backend, buckets: &[Bucket]) -> String {
+    let values: Vec<String> = buckets
+        .iter()
+        .enumerate()
+        .map(|(i, bucket)| {
+            format!(
+                "({}, {}, {})",
+                i,
+                timestamp_literal(backend, bucket.start),
+                timestamp_literal(backend, bucket.end)
+            )
+        })
+        .collect();
+
+    format!(
+        "WITH buckets (bucket_index, bucket_start, bucket_end) AS (VALUES {}) ",
+        values.join(", ")
+    )
+}
+
+async fn count_series(
+    db: &dyn Database,
+    buckets: &[Bucket],
+    table: &str,
+    column: &str,
+) -> Result<Vec<i64>, Box<dyn std::error::Error>> {
+    let statement = Statement::new(buckets_cte(db.backend(), buckets)).push(&format!(
+        "SELECT b.bucket_index, COUNT(t.{column}) AS total FROM buckets b \
+         LEFT JOIN {table} t ON t.{column} >= b.bucket_start AND t.{column} < b.bucket_end \
+         GROUP BY b.bucket_index ORDER BY b.bucket_index",
+        table = table,
+        column = column,
+    ));
+
+    let mut series = vec![0; buckets.len()];
+    for row in db.fetch_all(statement).await? {
+        let index: usize = row.get("bucket_index")?;
+        if let Some(count) = series.get_mut(index) {
+            *count = row.get("total")?;
+        }
+    }
+
+    Ok(series)
+}
+
+// Net collections per period and currency: payments minus refunds by the date the money moved
+async fn revenue_series(
+    db: &dyn Database,
+    buckets: &[Bucket],
+) -> Result<Vec<Vec<Money>>, Box<dyn std::error::Error>> {
+    let statement = Statement::new(buckets_cte(db.backend(), buckets))
+        .push("SELECT b.bucket_index, p.currency, CAST(SUM(CASE WHEN p.kind = ")
+        .push_bind("?", services::PAYMENT)
+        .push(
+            " THEN p.amount ELSE -p.amount END) AS BIGINT) AS net FROM buckets b \
+             JOIN payments p ON p.received_at >= b.bucket_start AND p.received_at < b.bucket_end \
+             GROUP BY b.bucket_index, p.currency ORDER BY b.bucket_index, p.currency",
+        );
+
+    let mut series: Vec<Vec<Money>> = buckets.iter().map(|_| Vec::new()).collect();
+    for row in db.fetch_all(statement).await? {
+        let index: usize = row.get("bucket_index")?;
+        let currency: Currency = row.get("currency")?;
+        if let Some(revenue) = series.get_mut(index) {
+            revenue.push(Money::from_minor(row.get("net")?, currency));
+        }
+    }
+
+    Ok(series)
+}
+
+pub async fn users_per_role(db: &dyn Database) -> Result<Vec<RoleCount>, Box<dyn std::error::Error>> {
+    let statement = Statement::new(
+        "SELECT r.slug, COUNT(ur.user_id) AS users
+        FROM roles r
+        LEFT JOIN users_roles ur ON ur.role_slug = r.slug
+        GROUP BY r.slug
+        ORDER BY r.slug",
+    );
+
+    let mut counts = Vec::new();
+    for row in db.fetch_all(statement).await? {
+        counts.push(RoleCount {
+            role: row.get("slug")?,
+            users: row.get("users")?,
+        });
+    }
+
+    Ok(counts)
+}
+
+pub async fn dashboard_stats(
+    db: &dyn Database,
+    query: &DashboardQuery,
+) -> Result<DashboardStats, Box<dyn std::error::Error>> {
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query.from.unwrap_or(to - Duration::days(DEFAULT_DAYS - 1));
+    let granularity = query.granularity.unwrap_or(Granularity::Day);
+    let buckets = buckets(from, to, granularity)?;
+
+    let new_users = count_series(db, &buckets, "users", "created_at").await?;
+    let orders_placed = count_series(db, &buckets, "orders", "created_at").await?;
+    let invoices_issued = count_series(db, &buckets, "invoices", "issued_at").await?;
+    let revenue = revenue_series(db, &buckets).await?;
+
+    let series = buckets
+        .iter()
+        .zip(new_users)
+        .zip(orders_placed)
+        .zip(invoices_issued)
+        .zip(revenue)
+        .map(|((((bucket, new_users), orders_placed), invoices_issued), revenue)| DashboardPoint {
+            period_start: bucket.period_start,
+            new_users,
+            orders_placed,
+            invoices_issued,
+            revenue,
+        })
+        .collect();
+
+    Ok(DashboardStats {
+        from,
+        to,
+        granularity,
+        users_per_role: users_per_role(db).await?,
+        series,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn buckets_are_clipped_to_the_range() {
+        // Wednesday 2024-01-10 to Tuesday 2024-01-23
+        let weeks = buckets(date(2024, 1, 10), date(2024, 1, 23), Granularity::Week).unwrap();
+        let spans = weeks.iter().map(|bucket| (bucket.period_start, bucket.start.date(), bucket.end.date())).collect::<Vec<_>>();
+        assert_eq!(
+            spans,
+            [
+                (date(2024, 1, 8), date(2024, 1, 10), date(2024, 1, 15)),
+                (date(2024, 1, 15), date(2024, 1, 15), date(2024, 1, 22)),
+                (date(2024, 1, 22), date(2024, 1, 22), date(2024, 1, 24)),
+            ]
+        );
+
+        let months = buckets(date(2024, 2, 20), date(2024, 2, 21), Granularity::Month).unwrap();
+        assert_eq!(months.len(), 1);
+        assert_eq!((months[0].start.date(), months[0].end.date()), (date(2024, 2, 20), date(2024, 2, 22)));
+    }
+}
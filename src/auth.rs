@@ -0,0 +1,423 @@
+// src/auth.rs
+use super::*;
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use jsonwebtoken::errors::ErrorKind;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+use utils::Claims;
+
+// In-process cache in front of the revoked_tokens and user_token_cutoffs tables.
+// Only positive results are cached so a revocation made by another instance is
+// still picked up from the database on the next request.
+#[derive(Default)]
+pub struct RevocationCache {
+    // jti → expiry, in seconds
+    tokens: RwLock<HashMap<String, i64>>,
+    // user id → sign-out-everywhere cutoff, in milliseconds
+    cutoffs: RwLock<HashMap<String, i64>>,
+}
+
+impl RevocationCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn contains(&self, claims: &Claims) -> bool {
+        if self.tokens.read().unwrap_or_else(PoisonError::into_inner).contains_key(&claims.jti) {
+            return true;
+        }
+
+        let cutoffs = self.cutoffs.read().unwrap_or_else(PoisonError::into_inner);
+        matches!(cutoffs.get(&claims.sub), Some(cutoff) if *cutoff > claims.issued_at_ms())
+    }
+
+    fn insert_token(&self, jti: &str, exp: i64) {
+        let now = Utc::now().timestamp();
+        let mut tokens = self.tokens.write().unwrap_or_else(PoisonError::into_inner);
+        tokens.retain(|_, exp| *exp > now);
+        tokens.insert(jti.to_string(), exp);
+    }
+
+    // A cutoff older than the token lifetime can only match tokens that have expired anyway
+    fn insert_cutoff(&self, user_id: &str, revoked_before: i64, token_lifetime: i64) {
+        let horizon = Utc::now().timestamp_millis() - token_lifetime * 1000;
+        let mut cutoffs = self.cutoffs.write().unwrap_or_else(PoisonError::into_inner);
+        cutoffs.retain(|_, cutoff| *cutoff > horizon);
+        cutoffs.insert(user_id.to_string(), revoked_before);
+    }
+}
+
+fn to_naive(timestamp_ms: i64) -> NaiveDateTime {
+    DateTime::from_timestamp_millis(timestamp_ms)
+        .unwrap_or_default()
+        .naive_utc()
+}
+
+pub async fn is_revoked(state: &AppState, claims: &Claims) -> Result<bool, Box<dyn std::error::Error>> {
+    if state.revocations.contains(claims) {
+        return Ok(true);
+    }
+
+    let issued_at = to_naive(claims.issued_at_ms());
+    let revoked = services::is_token_revoked(state.repo.as_ref(), &claims.jti, &claims.sub, issued_at).await?;
+    if revoked {
+        state.revocations.insert_token(&claims.jti, claims.exp);
+    }
+
+    Ok(revoked)
+}
+
+pub async fn revoke(state: &AppState, claims: &Claims) -> Result<(), Box<dyn std::error::Error>> {
+    services::revoke_access_token(state.db.as_ref(), &claims.jti, &claims.sub, to_naive(claims.exp * 1000)).await?;
+    state.revocations.insert_token(&claims.jti, claims.exp);
+
+    Ok(())
+}
+
+pub async fn revoke_all(state: &AppState, user_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // Tokens issued strictly before this instant are revoked, so a sign-in right after still works
+    let now = Utc::now().timestamp_millis();
+    services::revoke_all_tokens(state.db.as_ref(), user_id, to_naive(now)).await?;
+    state.revocations.insert_cutoff(user_id, now, state.config.jwt_expiry);
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingToken,
+    MalformedToken,
+    InvalidSignature,
+    ExpiredToken,
+    RevokedToken,
+    Forbidden { code: &'static str, requirement: serde_json::Value },
+    Internal(String),
+}
+
+impl AuthError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::MissingToken => "token_missing",
+            AuthError::MalformedToken => "token_malformed",
+            AuthError::InvalidSignature => "token_invalid_signature",
+            AuthError::ExpiredToken => "token_expired",
+            AuthError::RevokedToken => "token_revoked",
+            AuthError::Forbidden { code, .. } => code,
+            AuthError::Internal(_) => "auth_internal_error",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            AuthError::MissingToken => "Authorization header missing",
+            AuthError::MalformedToken => "Malformed bearer token",
+            AuthError::InvalidSignature => "Invalid token signature",
+            AuthError::ExpiredToken => "Token has expired",
+            AuthError::RevokedToken => "Token has been revoked",
+            AuthError::Forbidden { .. } => "You do not have access to this resource",
+            AuthError::Internal(message) => message,
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::Forbidden { .. } => StatusCode::FORBIDDEN,
+            AuthError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+}
+
+impl From<ErrorKind> for AuthError {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::ExpiredSignature => AuthError::ExpiredToken,
+            ErrorKind::InvalidSignature => AuthError::InvalidSignature,
+            _ => AuthError::MalformedToken,
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let data = match &self {
+            AuthError::Forbidden { requirement, .. } => json!({ "required": requirement }),
+            _ => serde_json::Value::Null,
+        };
+
+        let body = json!({
+            "status": "error",
+            "message": self.message(),
+            "code": self.code(),
+            "data": data
+        });
+
+        (self.status_code(), Json(body)).into_response()
+    }
+}
+
+// Verifies signature and expiry, then consults the revocation store
+pub async fn authenticate(state: &AppState, token: &str) -> Result<Claims, AuthError> {
+    let claims = utils::validate_jwt(&state.config, token)?;
+
+    match is_revoked(state, &claims).await {
+        Ok(false) => Ok(claims),
+        Ok(true) => Err(AuthError::RevokedToken),
+        Err(e) => Err(AuthError::Internal(e.to_string())),
+    }
+}
+
+fn bearer_token(parts: &Parts) -> Result<&str, AuthError> {
+    let header = parts
+        .headers
+        .get(AUTHORIZATION)
+        .ok_or(AuthError::MissingToken)?
+        .to_str()
+        .map_err(|_| AuthError::MalformedToken)?;
+
+    match header.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty() => {
+            Ok(token.trim())
+        }
+        _ => Err(AuthError::MalformedToken),
+    }
+}
+
+// The authenticated caller, resolved once per request and cached in the request extensions
+#[derive(Clone)]
+pub struct AuthUser {
+    pub user_id: String,
+    pub roles: Vec<String>,
+    pub claims: Arc<Claims>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(auth_user) = parts.extensions.get::<AuthUser>() {
+            return Ok(auth_user.clone());
+        }
+
+        let state = parts
+            .extensions
+            .get::<Arc<AppState>>()
+            .cloned()
+            .ok_or_else(|| AuthError::Internal("Application state missing".to_string()))?;
+
+        let claims = authenticate(&state, bearer_token(parts)?).await?;
+
+        let roles = services::fetch_user_role_slugs(state.repo.as_ref(), &claims.sub)
+            .await
+            .map_err(|e| AuthError::Internal(e.to_string()))?;
+
+        let auth_user = AuthUser {
+            user_id: claims.sub.clone(),
+            roles,
+            claims: Arc::new(claims),
+        };
+
+        parts.extensions.insert(auth_user.clone());
+
+        Ok(auth_user)
+    }
+}
+
+// Route-group middleware: `.route_layer(middleware::from_fn(auth::require_auth))`
+pub async fn require_auth(auth_user: AuthUser, mut req: Request, next: Next) -> Response {
+    req.extensions_mut().insert(auth_user);
+    next.run(req).await
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum RoleRequirement {
+    AnyOf(&'static [&'static str]),
+    AllOf(&'static [&'static str]),
+}
+
+fn holds(held: &[String], slug: &str) -> bool {
+    held.iter().any(|candidate| candidate == slug)
+}
+
+impl RoleRequirement {
+    pub fn is_satisfied_by(&self, roles: &[String]) -> bool {
+        match self {
+            RoleRequirement::AnyOf(slugs) => slugs.iter().any(|slug| holds(roles, slug)),
+            RoleRequirement::AllOf(slugs) => slugs.iter().all(|slug| holds(roles, slug)),
+        }
+    }
+
+    fn to_json(self) -> serde_json::Value {
+        match self {
+            RoleRequirement::AnyOf(slugs) => json!({ "any_of_roles": slugs }),
+            RoleRequirement::AllOf(slugs) => json!({ "all_of_roles": slugs }),
+        }
+    }
+}
+
+impl AuthUser {
+    pub fn require_roles(&self, requirement: RoleRequirement) -> Result<(), AuthError> {
+        if requirement.is_satisfied_by(&self.roles) {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden {
+                code: "role_required",
+                requirement: requirement.to_json(),
+            })
+        }
+    }
+}
+
+// Route-group guard:
+// `.route_layer(middleware::from_fn_with_state(RoleRequirement::AnyOf(&["admin"]), auth::require_roles))`
+pub async fn require_roles(
+    State(requirement): State<RoleRequirement>,
+    auth_user: AuthUser,
+    req: Request,
+    next: Next,
+) -> Result<Response, AuthError> {
+    auth_user.require_roles(requirement)?;
+    Ok(next.run(req).await)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PermissionRequirement {
+    AnyOf(&'static [&'static str]),
+    AllOf(&'static [&'static str]),
+}
+
+impl PermissionRequirement {
+    pub fn is_satisfied_by(&self, permissions: &[String]) -> bool {
+        match self {
+            PermissionRequirement::AnyOf(slugs) => slugs.iter().any(|slug| holds(permissions, slug)),
+            PermissionRequirement::AllOf(slugs) => slugs.iter().all(|slug| holds(permissions, slug)),
+        }
+    }
+
+    fn to_json(self) -> serde_json::Value {
+        match self {
+            PermissionRequirement::AnyOf(slugs) => json!({ "any_of_permissions": slugs }),
+            PermissionRequirement::AllOf(slugs) => json!({ "all_of_permissions": slugs }),
+        }
+    }
+}
+
+// Effective permission set of the caller, resolved on first use and cached in the request extensions
+#[derive(Clone)]
+pub struct UserPermissions(pub Arc<Vec<String>>);
+
+pub async fn resolve_permissions(
+    state: &AppState,
+    auth_user: &AuthUser,
+    extensions: &mut axum::http::Extensions,
+) -> Result<UserPermissions, AuthError> {
+    if let Some(permissions) = extensions.get::<UserPermissions>() {
+        return Ok(permissions.clone());
+    }
+
+    let permissions = services::fetch_user_permissions(state.repo.as_ref(), &auth_user.user_id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+
+    let permissions = UserPermissions(Arc::new(permissions));
+    extensions.insert(permissions.clone());
+
+    Ok(permissions)
+}
+
+// Route-group guard:
+// `.route_layer(middleware::from_fn_with_state(PermissionRequirement::AllOf(&["orders:read"]), auth::require_permissions))`
+pub async fn require_permissions(
+    State(requirement): State<PermissionRequirement>,
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, AuthError> {
+    let permissions = resolve_permissions(&state, &auth_user, req.extensions_mut()).await?;
+
+    if !requirement.is_satisfied_by(&permissions.0) {
+        return Err(AuthError::Forbidden {
+            code: "permission_required",
+            requirement: requirement.to_json(),
+        });
+    }
+
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(user_id: &str, jti: &str, iat: i64) -> Claims {
+        Claims { sub: user_id.to_string(), jti: jti.to_string(), iat, exp: iat + 3600, iat_ms: None }
+    }
+
+    #[test]
+    fn cache_survives_a_poisoned_lock() {
+        let cache = Arc::new(RevocationCache::new());
+        let now = Utc::now().timestamp();
+        cache.insert_token("jti-1", now + 60);
+
+        // A panic while holding the write guard poisons the lock for every later request
+        let poisoner = cache.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.tokens.write().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(cache.tokens.is_poisoned());
+
+        assert!(cache.contains(&claims("user-1", "jti-1", now)));
+        cache.insert_token("jti-2", now + 60);
+        assert!(cache.contains(&claims("user-1", "jti-2", now)));
+    }
+
+    #[test]
+    fn cutoffs_compare_milliseconds_and_expire() {
+        let cache = RevocationCache::new();
+        let now = Utc::now();
+        let issued = |offset_ms: i64| Claims {
+            iat_ms: Some(now.timestamp_millis() + offset_ms),
+            ..claims("user-1", &utils::generate_uuid(), now.timestamp())
+        };
+
+        cache.insert_cutoff("user-1", now.timestamp_millis(), 3600);
+        assert!(cache.contains(&issued(-1)));
+        assert!(!cache.contains(&issued(0)));
+        assert!(!cache.contains(&issued(1)));
+
+        // Inserting prunes cutoffs older than a token lifetime
+        cache.insert_cutoff("user-2", now.timestamp_millis() - 7_200_000, 3600);
+        cache.insert_cutoff("user-3", now.timestamp_millis(), 3600);
+        let cutoffs = cache.cutoffs.read().unwrap();
+        assert!(!cutoffs.contains_key("user-2"));
+        assert!(cutoffs.contains_key("user-1") && cutoffs.contains_key("user-3"));
+    }
+
+    #[test]
+    fn role_requirements_match_any_or_all() {
+        let held = vec!["staff".to_string(), "auditor".to_string()];
+
+        assert!(RoleRequirement::AnyOf(&["admin", "staff"]).is_satisfied_by(&held));
+        assert!(!RoleRequirement::AnyOf(&["admin"]).is_satisfied_by(&held));
+        assert!(RoleRequirement::AllOf(&["staff", "auditor"]).is_satisfied_by(&held));
+        assert!(!RoleRequirement::AllOf(&["staff", "admin"]).is_satisfied_by(&held));
+
+        assert_eq!(RoleRequirement::AllOf(&["staff"]).to_json(), json!({ "all_of_roles": ["staff"] }));
+    }
+}
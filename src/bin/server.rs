@@ -0,0 +1,36 @@
+// src/bin/server.rs
+use std::env;
+use std::process;
+
+const USAGE: &str = "usage: server [serve | migrate | migrate status | rollback <version> | seed [fixture]]";
+const DEFAULT_FIXTURE: &str = "fixtures/dev.yaml";
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args.as_slice() {
+        [] | ["serve"] => axum_crud_app::native::serve().await,
+        ["migrate"] => axum_crud_app::native::migrate().await,
+        ["migrate", "status"] => axum_crud_app::native::migration_status().await,
+        ["seed"] => axum_crud_app::native::seed(DEFAULT_FIXTURE).await,
+        ["seed", fixture] => axum_crud_app::native::seed(fixture).await,
+        ["rollback", version] => match version.parse() {
+            Ok(target) => axum_crud_app::native::rollback(target).await,
+            Err(_) => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
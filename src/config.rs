@@ -0,0 +1,166 @@
+// src/config.rs
+use super::*;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::net::SocketAddr;
+use tax::TaxRounding;
+
+const DEFAULT_DATABASE_URL: &str = "sqlite://local.db?mode=rwc";
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8080";
+const DEFAULT_JWT_EXPIRY: i64 = 3600; // 1 hour
+const DEFAULT_REFRESH_TOKEN_EXPIRY: i64 = 2_592_000; // 30 days
+const MIN_SECRET_KEY_LEN: usize = 32;
+
+// Every setting the application reads, loaded once at startup and shared through AppState.
+// Keys are the environment variable names; a config file uses the same names in lowercase.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub secret_key: String,
+    // Lifetimes in seconds
+    pub jwt_expiry: i64,
+    pub refresh_token_expiry: i64,
+    // Postgres or a SQLite file for the native server; unused on Workers, where the data store
+    // is the D1 binding
+    pub database_url: String,
+    // Defaults for new orders; each order records the settings it was priced with
+    pub prices_include_tax: bool,
+    pub tax_rounding: TaxRounding,
+    pub auto_migrate: bool,
+    pub bind_address: SocketAddr,
+}
+
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Collects problems while reading so that one bad value doesn't hide the next
+struct Reader<F> {
+    lookup: F,
+    problems: Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Reader<F> {
+    fn raw(&self, key: &str) -> Option<String> {
+        (self.lookup)(key).map(|value| value.trim().to_string()).filter(|value| !value.is_empty())
+    }
+
+    fn parse<T>(&mut self, key: &str, default: T, expected: &str, parse: impl Fn(&str) -> Option<T>) -> T {
+        match self.raw(key) {
+            None => default,
+            Some(value) => parse(&value).unwrap_or_else(|| {
+                self.problems.push(format!("{} must be {}, got `{}`", key, expected, value));
+                default
+            }),
+        }
+    }
+
+    fn seconds(&mut self, key: &str, default: i64) -> i64 {
+        self.parse(key, default, "a positive number of seconds", |value| {
+            value.parse().ok().filter(|seconds: &i64| *seconds > 0)
+        })
+    }
+
+    fn flag(&mut self, key: &str, default: bool) -> bool {
+        self.parse(key, default, "true or false", |value| match value {
+            "true" | "1" => Some(true),
+            "false" | "0" => Some(false),
+            _ => None,
+        })
+    }
+}
+
+impl Config {
+    // `lookup` is the source: process environment, a config file, or a Worker's vars and secrets
+    pub fn load(lookup: impl Fn(&str) -> Option<String>) -> Result<Config, ConfigError> {
+        let mut reader = Reader { lookup, problems: Vec::new() };
+
+        let secret_key = reader.raw("SECRET_KEY").unwrap_or_default();
+        if secret_key.is_empty() {
+            reader.problems.push("SECRET_KEY must be set".to_string());
+        } else if secret_key.len() < MIN_SECRET_KEY_LEN {
+            reader
+                .problems
+                .push(format!("SECRET_KEY must be at least {} characters", MIN_SECRET_KEY_LEN));
+        }
+
+        let jwt_expiry = reader.seconds("JWT_EXPIRY", DEFAULT_JWT_EXPIRY);
+        let refresh_token_expiry = reader.seconds("REFRESH_TOKEN_EXPIRY", DEFAULT_REFRESH_TOKEN_EXPIRY);
+        if refresh_token_expiry <= jwt_expiry {
+            reader
+                .problems
+                .push("REFRESH_TOKEN_EXPIRY must be longer than JWT_EXPIRY".to_string());
+        }
+
+        let database_url = reader.parse(
+            "DATABASE_URL",
+            DEFAULT_DATABASE_URL.to_string(),
+            "a postgres:// or sqlite: URL",
+            |value| db::Backend::from_url(value).map(|_| value.to_string()),
+        );
+
+        let prices_include_tax = reader.flag("TAX_PRICES_INCLUDE_TAX", false);
+        let tax_rounding =
+            reader.parse("TAX_ROUNDING", TaxRounding::PerLine, "per_line or per_document", TaxRounding::parse);
+        let auto_migrate = reader.flag("AUTO_MIGRATE", true);
+        let bind_address = reader.parse(
+            "BIND_ADDRESS",
+            DEFAULT_BIND_ADDRESS.parse().expect("default bind address is valid"),
+            "a host:port socket address",
+            |value| value.parse().ok(),
+        );
+
+        if !reader.problems.is_empty() {
+            return Err(ConfigError(reader.problems));
+        }
+
+        Ok(Config {
+            secret_key,
+            jwt_expiry,
+            refresh_token_expiry,
+            database_url,
+            prices_include_tax,
+            tax_rounding,
+            auto_migrate,
+            bind_address,
+        })
+    }
+
+    // Environment variables win over the JSON file named by CONFIG_FILE, so a deployment can
+    // keep shared settings in the file and override single values per host
+    pub fn from_env() -> Result<Config, ConfigError> {
+        let file = match std::env::var("CONFIG_FILE") {
+            Ok(path) => read_file(&path).map_err(|problem| ConfigError(vec![problem]))?,
+            Err(_) => Map::new(),
+        };
+
+        Config::load(|key| {
+            std::env::var(key).ok().or_else(|| match file.get(&key.to_lowercase())? {
+                Value::String(value) => Some(value.clone()),
+                Value::Null => None,
+                value => Some(value.to_string()),
+            })
+        })
+    }
+}
+
+fn read_file(path: &str) -> Result<Map<String, Value>, String> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("CONFIG_FILE `{}` could not be read: {}", path, e))?;
+
+    match serde_json::from_str(&contents) {
+        Ok(Value::Object(settings)) => Ok(settings),
+        Ok(_) => Err(format!("CONFIG_FILE `{}` must contain a JSON object", path)),
+        Err(e) => Err(format!("CONFIG_FILE `{}` is not valid JSON: {}", path, e)),
+    }
+}
@@ -1,224 +1,1322 @@
 // src/controllers.rs
 use super::*;
-use sqlx::{PgPool};
-use serde::{Serialize, Deserialize};
-use chrono::NaiveDate;
-
-#[derive(Serialize)]
-pub struct DashboardStats {
-    roles: Vec<String>,
-    users: i32,
-    orders: i32,
-    invoices: i32,
-}
-
-#[derive(Serialize)]
-pub struct UserProfileDetails {
-    username: String,
-    email: String,
-    telephone: Option<String>,
-    salutation: Option<String>,
-    first_name: String,
-    middle_name: Option<String>,
-    last_name: String,
-    gender: Option<String>,
-    address_line_1: Option<String>,
-    address_line_2: Option<String>,
-    city: Option<String>,
-    state: Option<String>,
-    country: Option<String>,
-    date_of_birth: String,
-    configuration: Option<serde_json::Value>,
-    roles: Vec<String>,
-}
-
-#[derive(Deserialize)]
-pub struct UpdateProfilePayload {
-    username: Option<String>,
-    email: Option<String>,
-    telephone: Option<String>,
-    salutation: Option<String>,
-    first_name: Option<String>,
-    middle_name: Option<String>,
-    last_name: Option<String>,
-    gender: Option<String>,
-    address_line_1: Option<String>,
-    address_line_2: Option<String>,
-    city: Option<String>,
-    state: Option<String>,
-    country: Option<String>,
-    date_of_birth: Option<String>,
-    configuration: Option<serde_json::Value>,
-}
-
-#[derive(Serialize)]
-pub struct SettingsResponse {
-    theme: String,
-    language: String,
-    notifications: bool,
+use axum::{Router, body::Bytes, extract::{Path, Query}, http::{HeaderMap, header::{CONTENT_DISPOSITION, CONTENT_TYPE}}, middleware, routing::{delete, get, post, put}};
+use auth::{AuthUser, PermissionRequirement, RoleRequirement};
+use numbering::Tenant;
+use serde_json::json;
+
+// Role granted to every account created through the public sign-up route
+const DEFAULT_ROLE: &str = "customer";
+
+// Decides whose records a caller sees, not which routes they reach
+const BACK_OFFICE: RoleRequirement = RoleRequirement::AnyOf(&["admin", "staff"]);
+
+// Admin and back-office routes are guarded by permission alone, so any role granted an area's
+// permission reaches it; the built-in grants are in migration 0012
+const MANAGE_ROLES: PermissionRequirement = PermissionRequirement::AnyOf(&["roles:manage"]);
+// Assigning roles means finding the user first
+const ASSIGN_ROLES: PermissionRequirement = PermissionRequirement::AllOf(&["roles:manage", "users:read"]);
+const READ_USERS: PermissionRequirement = PermissionRequirement::AnyOf(&["users:read"]);
+const MANAGE_CATALOG: PermissionRequirement = PermissionRequirement::AnyOf(&["catalog:manage"]);
+const MANAGE_SYSTEM: PermissionRequirement = PermissionRequirement::AnyOf(&["system:manage"]);
+const READ_DASHBOARD: PermissionRequirement = PermissionRequirement::AnyOf(&["dashboard:read"]);
+const MANAGE_INVOICES: PermissionRequirement = PermissionRequirement::AnyOf(&["invoices:manage"]);
+const MANAGE_PAYMENTS: PermissionRequirement = PermissionRequirement::AnyOf(&["payments:manage"]);
+
+pub fn config() -> Router {
+    let public = Router::new()
+        .route("/auth/sign-up", post(sign_up))
+        .route("/auth/sign-in", post(sign_in))
+        .route("/auth/refresh", post(refresh))
+        .route("/products", get(list_products))
+        .route("/products/:id", get(show_product));
+
+    let protected = Router::new()
+        .route("/auth/logout", post(logout))
+        .route("/auth/logout-all", post(logout_all))
+        .route("/profile", get(user_profile).put(update_profile).patch(update_profile))
+        .route("/settings", get(settings))
+        .route("/orders", get(list_orders).post(create_order))
+        .route("/orders/:id", get(show_order).put(update_order))
+        .route("/orders/:id/cancel", post(cancel_order))
+        .route("/invoices", get(list_invoices))
+        .route("/invoices/:id", get(show_invoice))
+        .route("/invoices/:id/pdf", get(invoice_pdf))
+        .route("/invoices/:id/payments", get(invoice_payments))
+        .route("/payments", get(list_payments))
+        .route_layer(middleware::from_fn(auth::require_auth));
+
+    let guarded = |requirement: PermissionRequirement, routes: Router| {
+        routes
+            .route_layer(middleware::from_fn_with_state(requirement, auth::require_permissions))
+            .route_layer(middleware::from_fn(auth::require_auth))
+    };
+
+    let dashboard_routes = Router::new().route("/dashboard", get(dashboard));
+
+    let invoice_routes = Router::new()
+        .route("/invoices", post(create_invoice))
+        .route("/invoices/:id/issue", post(issue_invoice))
+        .route("/invoices/:id/void", post(void_invoice))
+        .route("/invoices/mark-overdue", post(mark_overdue_invoices));
+
+    let payment_routes = Router::new()
+        .route("/invoices/:id/payments", post(record_payment))
+        .route("/invoices/:id/refunds", post(record_refund))
+        .route("/invoices/:id/credit-notes", post(create_credit_note));
+
+    let role_routes = Router::new()
+        .route("/admin/roles", get(list_roles).post(create_role))
+        .route("/admin/roles/:slug", get(show_role).put(update_role).delete(delete_role))
+        .route("/admin/roles/:slug/permissions", get(role_permissions).post(grant_role_permission))
+        .route("/admin/roles/:slug/permissions/:permission", delete(revoke_role_permission));
+
+    let user_role_routes = Router::new()
+        .route("/admin/users/:id/roles", post(assign_user_role))
+        .route("/admin/users/:id/roles/:slug", delete(revoke_user_role));
+
+    let user_routes = Router::new()
+        .route("/admin/users", get(list_users))
+        .route("/admin/users/:id/permissions", get(user_permissions));
+
+    let catalog_routes = Router::new()
+        .route("/admin/products", get(admin_list_products).post(create_product))
+        .route("/admin/products/:id", get(admin_show_product).put(update_product).delete(delete_product))
+        .route("/admin/tax-rates", get(list_tax_rates).post(create_tax_rate))
+        .route("/admin/tax-rates/:id", delete(delete_tax_rate));
+
+    let system_routes = Router::new()
+        .route("/admin/numbering/:document_type", put(update_numbering_scheme))
+        .route("/admin/migrations", get(migration_status))
+        .route("/admin/migrations/apply", post(apply_migrations));
+
+    public
+        .merge(protected)
+        .merge(guarded(READ_DASHBOARD, dashboard_routes))
+        .merge(guarded(MANAGE_INVOICES, invoice_routes))
+        .merge(guarded(MANAGE_PAYMENTS, payment_routes))
+        .merge(guarded(MANAGE_ROLES, role_routes))
+        .merge(guarded(ASSIGN_ROLES, user_role_routes))
+        .merge(guarded(READ_USERS, user_routes))
+        .merge(guarded(MANAGE_CATALOG, catalog_routes))
+        .merge(guarded(MANAGE_SYSTEM, system_routes))
 }
 
-pub async fn dashboard(
+pub async fn sign_up(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<SignUpPayload>,
+) -> impl IntoResponse {
+    if let Err(e) = services::validate_sign_up(&payload) {
+        return error_response(&e.to_string(), e.status_code());
+    }
+
+    let hashed_password = match services::hash_password(&payload.password).await {
+        Ok(hashed) => hashed,
+        Err(_) => return error_response("Failed to hash password", StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let user_id = utils::generate_uuid();
+
+    let user = match services::create_user_statement(&payload, &hashed_password, &user_id) {
+        Ok(statement) => statement,
+        Err(e) => return error_response(&format!("Failed to create user: {}", e), StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let profile = match services::create_profile_statement(&payload, &user_id) {
+        Ok(statement) => statement,
+        Err(e) => return error_response(&format!("Failed to create profile: {}", e), StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let (refresh_token, token) = services::refresh_token_statement(
+        &state.config,
+        &user_id,
+        &utils::generate_uuid(),
+        None,
+        chrono::Utc::now().naive_utc(),
+    );
+
+    // The account, its profile, default role and first refresh token are written as one batch
+    let statements = vec![user, profile, services::assign_role_statement(&user_id, DEFAULT_ROLE), token];
+    if let Err(e) = state.db.batch(statements).await {
+        if e.is_unique_violation() {
+            return error_response("Username or email already exists", StatusCode::CONFLICT);
+        }
+        return error_response(&format!("Failed to create user: {}", e), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let tokens = AuthTokens {
+        access_token: utils::generate_jwt(&state.config, &user_id),
+        user_id,
+        refresh_token,
+    };
+
+    success_response(Some(tokens), "User registered successfully", StatusCode::CREATED)
+}
+
+pub async fn sign_in(
     Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<SignInPayload>,
 ) -> impl IntoResponse {
-    let stats = match fetch_dashboard_stats(&state.pool).await {
-        Ok(stats) => stats,
-        Err(_) => return error_response("Failed to fetch dashboard stats", StatusCode::INTERNAL_SERVER_ERROR),
+    let user = match services::fetch_user_by_username(state.repo.as_ref(), &payload.username).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return error_response("Invalid username or password", StatusCode::UNAUTHORIZED),
+        Err(_) => return error_response("Failed to fetch user", StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    match services::verify_password(&payload.password, &user.password).await {
+        Ok(true) => {}
+        _ => return error_response("Invalid username or password", StatusCode::UNAUTHORIZED),
+    }
+
+    // Every sign-in starts a new refresh token family for the signing-in device
+    let refresh_token = match services::issue_refresh_token(
+        state.db.as_ref(),
+        &state.config,
+        &user.id,
+        &utils::generate_uuid(),
+        payload.device_id.as_deref(),
+    )
+    .await
+    {
+        Ok(refresh_token) => refresh_token,
+        Err(_) => return error_response("Failed to issue refresh token", StatusCode::INTERNAL_SERVER_ERROR),
     };
 
-    success_response(stats, "Dashboard stats retrieved successfully", StatusCode::OK)
+    let tokens = AuthTokens {
+        access_token: utils::generate_jwt(&state.config, &user.id),
+        user_id: user.id,
+        refresh_token,
+    };
+
+    success_response(Some(tokens), "User signed in successfully", StatusCode::OK)
 }
 
-pub async fn user_profile(
+pub async fn refresh(
     Extension(state): Extension<Arc<AppState>>,
-    TypedHeader(token): TypedHeader<Bearer>,
+    Json(payload): Json<RefreshPayload>,
 ) -> impl IntoResponse {
-    let decoded_token = match decode::<Claims>(
-        &token.token(),
-        &DecodingKey::from_secret(SECRET_KEY),
-        &Validation::new(Algorithm::HS256),
-    ) {
-        Ok(decoded) => decoded.claims.sub,
-        Err(_) => return error_response("Invalid token", StatusCode::UNAUTHORIZED).into_response(),
+    let outcome = match services::rotate_refresh_token(state.db.as_ref(), &state.config, &payload.refresh_token).await {
+        Ok(outcome) => outcome,
+        Err(_) => return error_response("Failed to refresh token", StatusCode::INTERNAL_SERVER_ERROR),
     };
 
-    let profile = match services::fetch_user_profile(&state.pool, &decoded_token).await {
-        Ok(profile) => profile,
+    match outcome {
+        services::RefreshOutcome::Rotated { user_id, refresh_token } => {
+            let tokens = AuthTokens {
+                access_token: utils::generate_jwt(&state.config, &user_id),
+                user_id,
+                refresh_token,
+            };
+            success_response(Some(tokens), "Token refreshed successfully", StatusCode::OK)
+        }
+        services::RefreshOutcome::Reused => error_response(
+            "Refresh token reuse detected, all sessions for this device have been revoked",
+            StatusCode::UNAUTHORIZED,
+        ),
+        services::RefreshOutcome::Expired => error_response("Refresh token expired", StatusCode::UNAUTHORIZED),
+        services::RefreshOutcome::Invalid => error_response("Invalid refresh token", StatusCode::UNAUTHORIZED),
+    }
+}
+
+pub async fn logout(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+) -> impl IntoResponse {
+    if auth::revoke(&state, &auth_user.claims).await.is_err() {
+        return error_response("Failed to revoke token", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    success_response(None::<()>, "Logged out successfully", StatusCode::OK)
+}
+
+pub async fn logout_all(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+) -> impl IntoResponse {
+    if auth::revoke_all(&state, &auth_user.user_id).await.is_err() {
+        return error_response("Failed to revoke tokens", StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    success_response(None::<()>, "Logged out of all sessions successfully", StatusCode::OK)
+}
+
+pub async fn dashboard(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<DashboardQuery>,
+) -> impl IntoResponse {
+    match analytics::dashboard_stats(state.db.as_ref(), &query).await {
+        Ok(stats) => success_response(Some(stats), "Dashboard stats retrieved successfully", StatusCode::OK),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to fetch dashboard stats"),
+    }
+}
+
+pub async fn user_profile(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+) -> impl IntoResponse {
+    let profile = match services::fetch_user_profile(state.db.as_ref(), &auth_user.user_id).await {
+        Ok(Some(profile)) => profile,
+        Ok(None) => return error_response("User profile not found", StatusCode::NOT_FOUND),
         Err(_) => return error_response("Failed to fetch user profile", StatusCode::INTERNAL_SERVER_ERROR),
     };
 
-    success_response(profile, "User profile retrieved successfully", StatusCode::OK)
+    success_response(Some(profile), "User profile retrieved successfully", StatusCode::OK)
 }
 
 pub async fn update_profile(
     Extension(state): Extension<Arc<AppState>>,
-    TypedHeader(token): TypedHeader<Bearer>,
-    Json(payload): Json<UpdateProfilePayload>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> impl IntoResponse {
-    let decoded_token = match decode::<Claims>(
-        &token.token(),
-        &DecodingKey::from_secret(SECRET_KEY),
-        &Validation::new(Algorithm::HS256),
-    ) {
-        Ok(decoded) => decoded.claims.sub,
-        Err(_) => return error_response("Invalid token", StatusCode::UNAUTHORIZED).into_response(),
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let result = match content_type.as_str() {
+        "application/merge-patch+json" => match serde_json::from_slice(&body) {
+            Ok(merge) => {
+                services::patch_user_profile(state.db.as_ref(), &auth_user.user_id, &services::ProfilePatch::Merge(merge)).await
+            }
+            Err(_) => return error_response("Invalid merge patch document", StatusCode::BAD_REQUEST),
+        },
+        "application/json-patch+json" => match serde_json::from_slice(&body) {
+            Ok(operations) => {
+                services::patch_user_profile(state.db.as_ref(), &auth_user.user_id, &services::ProfilePatch::Json(operations)).await
+            }
+            Err(_) => return error_response("Invalid JSON patch document", StatusCode::BAD_REQUEST),
+        },
+        "" | "application/json" => match serde_json::from_slice::<UpdateProfilePayload>(&body) {
+            Ok(payload) => services::update_user_profile(state.db.as_ref(), &auth_user.user_id, &payload).await,
+            Err(_) => return error_response("Invalid profile payload", StatusCode::BAD_REQUEST),
+        },
+        _ => return error_response("Unsupported content type", StatusCode::UNSUPPORTED_MEDIA_TYPE),
     };
 
-    if let Err(e) = update_user_profile(&state.pool, &decoded_token, &payload).await {
-        return error_response(&format!("Failed to update profile: {}", e), StatusCode::INTERNAL_SERVER_ERROR);
+    match result {
+        Ok(Some(profile)) => success_response(Some(profile), "User profile updated successfully", StatusCode::OK),
+        Ok(None) => error_response("User not found", StatusCode::NOT_FOUND),
+        Err(e) if e.is::<chrono::ParseError>() => {
+            error_response("date_of_birth must be formatted as YYYY-MM-DD", StatusCode::BAD_REQUEST)
+        }
+        Err(e) if e.is::<services::PatchError>() => error_response(&e.to_string(), StatusCode::UNPROCESSABLE_ENTITY),
+        Err(e) if utils::is_unique_violation(e.as_ref()) => {
+            error_response("Username or email already exists", StatusCode::CONFLICT)
+        }
+        Err(e) => error_response(&format!("Failed to update profile: {}", e), StatusCode::INTERNAL_SERVER_ERROR),
     }
-
-    success_response(None, "User profile updated successfully", StatusCode::OK)
 }
 
 pub async fn settings(
     Extension(state): Extension<Arc<AppState>>,
-    TypedHeader(token): TypedHeader<Bearer>,
+    auth_user: AuthUser,
+) -> impl IntoResponse {
+    match services::fetch_user_settings(state.repo.as_ref(), &auth_user.user_id).await {
+        Ok(Some(settings)) => success_response(Some(settings), "User settings retrieved successfully", StatusCode::OK),
+        Ok(None) => error_response("User settings not found", StatusCode::NOT_FOUND),
+        Err(_) => error_response("Failed to fetch user settings", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn list_products(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<ProductSearchQuery>,
+) -> impl IntoResponse {
+    match products::list_products(state.repo.as_ref(), &query, false).await {
+        Ok(products) => success_response(Some(products), "Products retrieved successfully", StatusCode::OK),
+        Err(_) => error_response("Failed to fetch products", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn show_product(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(product_id): Path<String>,
+) -> impl IntoResponse {
+    match products::fetch_product(state.repo.as_ref(), &product_id, false).await {
+        Ok(Some(product)) => success_response(Some(product), "Product retrieved successfully", StatusCode::OK),
+        Ok(None) => error_response("Product not found", StatusCode::NOT_FOUND),
+        Err(_) => error_response("Failed to fetch product", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn admin_list_products(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<ProductSearchQuery>,
+) -> impl IntoResponse {
+    match products::list_products(state.repo.as_ref(), &query, true).await {
+        Ok(products) => success_response(Some(products), "Products retrieved successfully", StatusCode::OK),
+        Err(_) => error_response("Failed to fetch products", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn admin_show_product(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(product_id): Path<String>,
+) -> impl IntoResponse {
+    match products::fetch_product(state.repo.as_ref(), &product_id, true).await {
+        Ok(Some(product)) => success_response(Some(product), "Product retrieved successfully", StatusCode::OK),
+        Ok(None) => error_response("Product not found", StatusCode::NOT_FOUND),
+        Err(_) => error_response("Failed to fetch product", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn create_product(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<CreateProductPayload>,
+) -> impl IntoResponse {
+    match products::create_product(state.repo.as_ref(), &payload).await {
+        Ok(product) => success_response(Some(product), "Product created successfully", StatusCode::CREATED),
+        Err(e) if utils::is_unique_violation(e.as_ref()) => {
+            error_response("A product with this SKU already exists", StatusCode::CONFLICT)
+        }
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to create product"),
+    }
+}
+
+pub async fn update_product(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(product_id): Path<String>,
+    Json(payload): Json<UpdateProductPayload>,
+) -> impl IntoResponse {
+    match products::update_product(state.repo.as_ref(), &product_id, &payload).await {
+        Ok(Some(product)) => success_response(Some(product), "Product updated successfully", StatusCode::OK),
+        Ok(None) => error_response("Product not found", StatusCode::NOT_FOUND),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to update product"),
+    }
+}
+
+pub async fn delete_product(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(product_id): Path<String>,
+) -> impl IntoResponse {
+    match products::delete_product(state.repo.as_ref(), &product_id).await {
+        Ok(true) => success_response(None::<()>, "Product deleted successfully", StatusCode::OK),
+        Ok(false) => error_response("Product not found", StatusCode::NOT_FOUND),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to delete product"),
+    }
+}
+
+// Customers only ever see their own records; back-office staff see everyone's
+fn owner_scope(auth_user: &AuthUser) -> Option<&str> {
+    if BACK_OFFICE.is_satisfied_by(&auth_user.roles) {
+        None
+    } else {
+        Some(&auth_user.user_id)
+    }
+}
+
+pub async fn list_orders(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Query(query): Query<OrderListQuery>,
+) -> impl IntoResponse {
+    match services::list_orders(state.db.as_ref(), owner_scope(&auth_user), &query).await {
+        Ok(orders) => success_response(Some(orders), "Orders retrieved successfully", StatusCode::OK),
+        Err(_) => error_response("Failed to fetch orders", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn create_order(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateOrderPayload>,
+) -> impl IntoResponse {
+    match services::create_order(state.db.as_ref(), &state.config, &auth_user.user_id, &payload).await {
+        Ok(order) => success_response(Some(order), "Order created successfully", StatusCode::CREATED),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to create order"),
+    }
+}
+
+pub async fn show_order(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(order_id): Path<String>,
+) -> impl IntoResponse {
+    match services::fetch_order_details(state.db.as_ref(), &order_id, owner_scope(&auth_user)).await {
+        Ok(Some(order)) => success_response(Some(order), "Order retrieved successfully", StatusCode::OK),
+        Ok(None) => error_response("Order not found", StatusCode::NOT_FOUND),
+        Err(_) => error_response("Failed to fetch order", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn update_order(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(order_id): Path<String>,
+    Json(payload): Json<UpdateOrderPayload>,
+) -> impl IntoResponse {
+    match services::update_order(state.db.as_ref(), &order_id, owner_scope(&auth_user), &payload).await {
+        Ok(Some(order)) => success_response(Some(order), "Order updated successfully", StatusCode::OK),
+        Ok(None) => error_response("Order not found", StatusCode::NOT_FOUND),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to update order"),
+    }
+}
+
+pub async fn cancel_order(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(order_id): Path<String>,
 ) -> impl IntoResponse {
-    let decoded_token = match decode::<Claims>(
-        &token.token(),
-        &DecodingKey::from_secret(SECRET_KEY),
-        &Validation::new(Algorithm::HS256),
-    ) {
-        Ok(decoded) => decoded.claims.sub,
-        Err(_) => return error_response("Invalid token", StatusCode::UNAUTHORIZED).into_response(),
+    match services::cancel_order(state.db.as_ref(), &order_id, owner_scope(&auth_user)).await {
+        Ok(Some(order)) => success_response(Some(order), "Order cancelled successfully", StatusCode::OK),
+        Ok(None) => error_response("Order not found", StatusCode::NOT_FOUND),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to cancel order"),
+    }
+}
+
+pub async fn list_invoices(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Query(query): Query<InvoiceListQuery>,
+) -> impl IntoResponse {
+    match services::list_invoices(state.db.as_ref(), owner_scope(&auth_user), &query).await {
+        Ok(invoices) => success_response(Some(invoices), "Invoices retrieved successfully", StatusCode::OK),
+        Err(_) => error_response("Failed to fetch invoices", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn show_invoice(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<String>,
+) -> impl IntoResponse {
+    match services::fetch_invoice_details(state.db.as_ref(), &invoice_id, owner_scope(&auth_user)).await {
+        Ok(Some(invoice)) => success_response(Some(invoice), "Invoice retrieved successfully", StatusCode::OK),
+        Ok(None) => error_response("Invoice not found", StatusCode::NOT_FOUND),
+        Err(_) => error_response("Failed to fetch invoice", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn invoice_pdf(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<String>,
+) -> impl IntoResponse {
+    let document = match services::fetch_invoice_document(state.db.as_ref(), &invoice_id, owner_scope(&auth_user)).await {
+        Ok(Some(document)) => document,
+        Ok(None) => return error_response("Invoice not found", StatusCode::NOT_FOUND).into_response(),
+        Err(_) => return error_response("Failed to fetch invoice", StatusCode::INTERNAL_SERVER_ERROR).into_response(),
     };
 
-    let settings = match fetch_user_settings(&state.pool, &decoded_token).await {
-        Ok(settings) => settings,
-        Err(_) => return error_response("Failed to fetch user settings", StatusCode::INTERNAL_SERVER_ERROR),
+    let bytes = state.pdf_cache.get_or_render(state.invoice_template.as_ref(), &document);
+    let filename = document.invoice.invoice.number.as_deref().unwrap_or(&invoice_id).to_string();
+
+    (
+        [
+            (CONTENT_TYPE, "application/pdf".to_string()),
+            (CONTENT_DISPOSITION, format!("attachment; filename=\"{}.pdf\"", filename)),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+pub async fn create_invoice(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<CreateInvoicePayload>,
+) -> impl IntoResponse {
+    match services::create_invoice(state.db.as_ref(), &payload).await {
+        Ok(invoice) => success_response(Some(invoice), "Invoice created successfully", StatusCode::CREATED),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to create invoice"),
+    }
+}
+
+pub async fn issue_invoice(
+    Extension(state): Extension<Arc<AppState>>,
+    Tenant(tenant_id): Tenant,
+    Path(invoice_id): Path<String>,
+) -> impl IntoResponse {
+    match services::issue_invoice(state.db.as_ref(), state.repo.as_ref(), &tenant_id, &invoice_id).await {
+        Ok(Some(invoice)) => success_response(Some(invoice), "Invoice issued successfully", StatusCode::OK),
+        Ok(None) => error_response("Invoice not found", StatusCode::NOT_FOUND),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to issue invoice"),
+    }
+}
+
+pub async fn void_invoice(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(invoice_id): Path<String>,
+) -> impl IntoResponse {
+    match services::void_invoice(state.db.as_ref(), &invoice_id).await {
+        Ok(Some(invoice)) => success_response(Some(invoice), "Invoice voided successfully", StatusCode::OK),
+        Ok(None) => error_response("Invoice not found", StatusCode::NOT_FOUND),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to void invoice"),
+    }
+}
+
+pub async fn mark_overdue_invoices(
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    match services::mark_overdue_invoices(state.db.as_ref(), chrono::Utc::now().date_naive()).await {
+        Ok(updated) => success_response(Some(updated), "Overdue invoices updated successfully", StatusCode::OK),
+        Err(_) => error_response("Failed to update overdue invoices", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn update_numbering_scheme(
+    Extension(state): Extension<Arc<AppState>>,
+    Tenant(tenant_id): Tenant,
+    Path(document_type): Path<String>,
+    Json(payload): Json<numbering::NumberingSchemePayload>,
+) -> impl IntoResponse {
+    let defaults = numbering::NumberingScheme::default_for(&tenant_id, &document_type);
+    let scheme = numbering::NumberingScheme {
+        prefix: payload.prefix,
+        format: payload.format.unwrap_or(defaults.format.clone()),
+        reset_yearly: payload.reset_yearly.unwrap_or(defaults.reset_yearly),
+        ..defaults
     };
 
-    success_response(settings, "User settings retrieved successfully", StatusCode::OK)
+    match numbering::save_scheme(state.repo.as_ref(), &scheme).await {
+        Ok(()) => success_response(Some(scheme), "Numbering scheme saved successfully", StatusCode::OK),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to save numbering scheme"),
+    }
 }
 
-async fn fetch_dashboard_stats(pool: &PgPool) -> Result<DashboardStats, Box<dyn std::error::Error>> {
-    let roles = sqlx::query!(
-        r#"SELECT DISTINCT slug FROM roles"#,
-        pool
-    )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|role| role.slug)
-    .collect();
-
-    let users_count = sqlx::query!(
-        r#"SELECT COUNT(*) FROM users"#,
-        pool
-    )
-    .fetch_one(pool)
-    .await?.count.unwrap_or(0);
+pub async fn migration_status(
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    match migrations::status(state.db.as_ref()).await {
+        Ok(statuses) => success_response(Some(statuses), "Migration status retrieved successfully", StatusCode::OK),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to fetch migration status"),
+    }
+}
 
-    let orders_count = sqlx::query!(
-        r#"SELECT COUNT(*) FROM orders"#,
-        pool
-    )
-    .fetch_one(pool)
-    .await?.count.unwrap_or(0);
+pub async fn apply_migrations(
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    let applied = match migrations::migrate(state.db.as_ref()).await {
+        Ok(applied) => applied,
+        Err(e) => return utils::service_error_response(e.as_ref(), "Failed to apply migrations"),
+    };
 
-    let invoices_count = sqlx::query!(
-        r#"SELECT COUNT(*) FROM invoices"#,
-        pool
-    )
-    .fetch_one(pool)
-    .await?.count.unwrap_or(0);
+    match migrations::status(state.db.as_ref()).await {
+        Ok(statuses) => success_response(
+            Some(json!({ "applied": applied, "migrations": statuses })),
+            "Migrations applied successfully",
+            StatusCode::OK,
+        ),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to fetch migration status"),
+    }
+}
 
-    Ok(DashboardStats {
-        roles,
-        users: users_count,
-        orders: orders_count,
-        invoices: invoices_count,
-    })
+pub async fn list_tax_rates(
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    match tax::list_rates(state.db.as_ref()).await {
+        Ok(rates) => success_response(Some(rates), "Tax rates retrieved successfully", StatusCode::OK),
+        Err(_) => error_response("Failed to fetch tax rates", StatusCode::INTERNAL_SERVER_ERROR),
+    }
 }
 
-async fn update_user_profile(
-    pool: &PgPool,
-    user_id: &str,
-    payload: &UpdateProfilePayload,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut query = String::from("UPDATE users SET ");
-    let mut values = Vec::new();
-    let mut params = Vec::new();
+pub async fn create_tax_rate(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<tax::TaxRatePayload>,
+) -> impl IntoResponse {
+    match tax::create_rate(state.db.as_ref(), &payload).await {
+        Ok(rate) => success_response(Some(rate), "Tax rate created successfully", StatusCode::CREATED),
+        Err(e) if utils::is_unique_violation(e.as_ref()) => {
+            error_response("A rate already exists for this jurisdiction and tax class", StatusCode::CONFLICT)
+        }
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to create tax rate"),
+    }
+}
+
+pub async fn delete_tax_rate(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(rate_id): Path<String>,
+) -> impl IntoResponse {
+    match tax::delete_rate(state.db.as_ref(), &rate_id).await {
+        Ok(true) => success_response(None::<()>, "Tax rate deleted successfully", StatusCode::OK),
+        Ok(false) => error_response("Tax rate not found", StatusCode::NOT_FOUND),
+        Err(_) => error_response("Failed to delete tax rate", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn invoice_payments(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<String>,
+) -> impl IntoResponse {
+    match services::list_invoice_payments(state.db.as_ref(), &invoice_id, owner_scope(&auth_user)).await {
+        Ok(Some(payments)) => success_response(Some(payments), "Payments retrieved successfully", StatusCode::OK),
+        Ok(None) => error_response("Invoice not found", StatusCode::NOT_FOUND),
+        Err(_) => error_response("Failed to fetch payments", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn list_payments(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Query(query): Query<PaymentListQuery>,
+) -> impl IntoResponse {
+    match services::list_payments(state.db.as_ref(), owner_scope(&auth_user), &query).await {
+        Ok(payments) => success_response(Some(payments), "Payments retrieved successfully", StatusCode::OK),
+        Err(_) => error_response("Failed to fetch payments", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn record_payment(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<String>,
+    Json(payload): Json<RecordPaymentPayload>,
+) -> impl IntoResponse {
+    match services::record_payment(state.db.as_ref(), &invoice_id, services::PAYMENT, &payload, &auth_user.user_id).await {
+        Ok(Some((payment, invoice))) => success_response(
+            Some(json!({ "payment": payment, "invoice": invoice })),
+            "Payment recorded successfully",
+            StatusCode::CREATED,
+        ),
+        Ok(None) => error_response("Invoice not found", StatusCode::NOT_FOUND),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to record payment"),
+    }
+}
 
-    if let Some(username) = &payload.username {
-        query.push_str("username = $1, ");
-        values.push(username);
-        params.push(sqlx::types::Json(&username));
+pub async fn create_credit_note(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Tenant(tenant_id): Tenant,
+    Path(invoice_id): Path<String>,
+    Json(payload): Json<CreateCreditNotePayload>,
+) -> impl IntoResponse {
+    match services::create_credit_note(state.db.as_ref(), state.repo.as_ref(), &tenant_id, &invoice_id, &payload, &auth_user.user_id).await {
+        Ok(Some(invoice)) => success_response(Some(invoice), "Credit note issued successfully", StatusCode::CREATED),
+        Ok(None) => error_response("Invoice not found", StatusCode::NOT_FOUND),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to issue credit note"),
+    }
+}
+
+pub async fn record_refund(
+    Extension(state): Extension<Arc<AppState>>,
+    auth_user: AuthUser,
+    Path(invoice_id): Path<String>,
+    Json(payload): Json<RecordPaymentPayload>,
+) -> impl IntoResponse {
+    match services::record_payment(state.db.as_ref(), &invoice_id, services::REFUND, &payload, &auth_user.user_id).await {
+        Ok(Some((payment, invoice))) => success_response(
+            Some(json!({ "payment": payment, "invoice": invoice })),
+            "Refund recorded successfully",
+            StatusCode::CREATED,
+        ),
+        Ok(None) => error_response("Invoice not found", StatusCode::NOT_FOUND),
+        Err(e) => utils::service_error_response(e.as_ref(), "Failed to record refund"),
     }
+}
 
-    if let Some(email) = &payload.email {
-        query.push_str("email = $2, ");
-        values.push(email);
-        params.push(sqlx::types::Json(&email));
+pub async fn list_roles(
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    match services::list_roles(state.repo.as_ref()).await {
+        Ok(roles) => success_response(Some(roles), "Roles retrieved successfully", StatusCode::OK),
+        Err(_) => error_response("Failed to fetch roles", StatusCode::INTERNAL_SERVER_ERROR),
     }
+}
 
-    // Add more fields as needed...
+pub async fn show_role(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(slug): Path<String>,
+) -> impl IntoResponse {
+    match services::fetch_role(state.repo.as_ref(), &slug).await {
+        Ok(Some(role)) => success_response(Some(role), "Role retrieved successfully", StatusCode::OK),
+        Ok(None) => error_response("Role not found", StatusCode::NOT_FOUND),
+        Err(_) => error_response("Failed to fetch role", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
 
-    query.pop(); // Remove trailing comma and space
-    query.push_str(" WHERE id = $3");
+pub async fn create_role(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<CreateRolePayload>,
+) -> impl IntoResponse {
+    if !services::is_valid_role_slug(&payload.slug) {
+        return error_response("Role slug may only contain lowercase letters, digits, '-' and '_'", StatusCode::BAD_REQUEST);
+    }
 
-    values.push(user_id);
-    params.push(sqlx::types::Json(&user_id));
+    if let Err(e) = services::create_role(state.db.as_ref(), &payload).await {
+        if utils::is_unique_violation(e.as_ref()) {
+            return error_response("Role already exists", StatusCode::CONFLICT);
+        }
+        return error_response(&format!("Failed to create role: {}", e), StatusCode::INTERNAL_SERVER_ERROR);
+    }
 
-    sqlx::query_as!(models::User, &query, &params)
-        .execute(pool)
-        .await?;
+    let role = RoleDetails {
+        slug: payload.slug,
+        name: payload.name,
+        description: payload.description,
+    };
 
-    Ok(())
+    success_response(Some(role), "Role created successfully", StatusCode::CREATED)
 }
 
-async fn fetch_user_settings(
-    pool: &PgPool,
-    user_id: &str,
-) -> Result<SettingsResponse, Box<dyn std::error::Error>> {
-    let settings = sqlx::query_as!(
-        SettingsResponse,
-        r#"SELECT theme, language, notifications FROM user_settings WHERE user_id = $1"#,
+pub async fn update_role(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(slug): Path<String>,
+    Json(payload): Json<UpdateRolePayload>,
+) -> impl IntoResponse {
+    match services::update_role(state.db.as_ref(), state.repo.as_ref(), &slug, &payload).await {
+        Ok(Some(role)) => success_response(Some(role), "Role updated successfully", StatusCode::OK),
+        Ok(None) => error_response("Role not found", StatusCode::NOT_FOUND),
+        Err(_) => error_response("Failed to update role", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn delete_role(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(slug): Path<String>,
+    Query(query): Query<DeleteRoleQuery>,
+) -> impl IntoResponse {
+    match services::delete_role(state.db.as_ref(), state.repo.as_ref(), &slug, query.reassign_to.as_deref()).await {
+        Ok(services::DeleteRoleOutcome::Deleted) => {
+            success_response(None::<()>, "Role deleted successfully", StatusCode::OK)
+        }
+        Ok(services::DeleteRoleOutcome::NotFound) => error_response("Role not found", StatusCode::NOT_FOUND),
+        Ok(services::DeleteRoleOutcome::InUse(holders)) => error_response(
+            &format!("Role is still held by {} user(s); pass ?reassign_to=<slug> to move them", holders),
+            StatusCode::CONFLICT,
+        ),
+        Ok(services::DeleteRoleOutcome::ReassignTargetNotFound) => {
+            error_response("Reassign target role not found", StatusCode::UNPROCESSABLE_ENTITY)
+        }
+        Err(_) => error_response("Failed to delete role", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn list_users(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<UserDirectoryQuery>,
+) -> impl IntoResponse {
+    let params = match services::directory_params(query) {
+        Ok(params) => params,
+        Err(message) => return error_response(&message, StatusCode::BAD_REQUEST),
+    };
+
+    match services::list_users(state.db.as_ref(), &params).await {
+        Ok(page) => success_response(Some(page), "Users retrieved successfully", StatusCode::OK),
+        Err(_) => error_response("Failed to fetch users", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn assign_user_role(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<AssignRolePayload>,
+) -> impl IntoResponse {
+    match services::role_exists(state.repo.as_ref(), &payload.role).await {
+        Ok(true) => {}
+        Ok(false) => return error_response("Role not found", StatusCode::NOT_FOUND),
+        Err(_) => return error_response("Failed to fetch role", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+
+    match services::fetch_user_with_roles(state.db.as_ref(), &user_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return error_response("User not found", StatusCode::NOT_FOUND),
+        Err(_) => return error_response("Failed to fetch user", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+
+    if let Err(e) = services::assign_role(state.db.as_ref(), &user_id, &payload.role).await {
+        if utils::is_unique_violation(e.as_ref()) {
+            return error_response("User already holds this role", StatusCode::CONFLICT);
+        }
+        return error_response(&format!("Failed to assign role: {}", e), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    match services::fetch_user_with_roles(state.db.as_ref(), &user_id).await {
+        Ok(Some(user)) => success_response(Some(user), "Role assigned successfully", StatusCode::OK),
+        _ => error_response("Failed to fetch user roles", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn revoke_user_role(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((user_id, slug)): Path<(String, String)>,
+) -> impl IntoResponse {
+    match services::revoke_role(state.db.as_ref(), &user_id, &slug).await {
+        Ok(true) => {}
+        Ok(false) => return error_response("User does not hold this role", StatusCode::NOT_FOUND),
+        Err(_) => return error_response("Failed to revoke role", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+
+    match services::fetch_user_with_roles(state.db.as_ref(), &user_id).await {
+        Ok(Some(user)) => success_response(Some(user), "Role revoked successfully", StatusCode::OK),
+        _ => error_response("Failed to fetch user roles", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn role_permissions(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(slug): Path<String>,
+) -> impl IntoResponse {
+    match services::role_exists(state.repo.as_ref(), &slug).await {
+        Ok(true) => {}
+        Ok(false) => return error_response("Role not found", StatusCode::NOT_FOUND),
+        Err(_) => return error_response("Failed to fetch role", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+
+    let permissions = match services::fetch_role_permissions(state.db.as_ref(), &slug).await {
+        Ok(permissions) => permissions,
+        Err(_) => return error_response("Failed to fetch role permissions", StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    success_response(Some(permissions), "Role permissions retrieved successfully", StatusCode::OK)
+}
+
+pub async fn grant_role_permission(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(slug): Path<String>,
+    Json(payload): Json<GrantPermissionPayload>,
+) -> impl IntoResponse {
+    if !services::is_valid_permission_slug(&payload.permission) {
+        return error_response("Permission must look like `resource:action`", StatusCode::BAD_REQUEST);
+    }
+
+    match services::role_exists(state.repo.as_ref(), &slug).await {
+        Ok(true) => {}
+        Ok(false) => return error_response("Role not found", StatusCode::NOT_FOUND),
+        Err(_) => return error_response("Failed to fetch role", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+
+    if let Err(e) = services::grant_permission(state.db.as_ref(), &slug, &payload).await {
+        return error_response(&format!("Failed to grant permission: {}", e), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    success_response(None::<()>, "Permission granted successfully", StatusCode::OK)
+}
+
+pub async fn revoke_role_permission(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((slug, permission)): Path<(String, String)>,
+) -> impl IntoResponse {
+    match services::revoke_permission(state.db.as_ref(), &slug, &permission).await {
+        Ok(true) => success_response(None::<()>, "Permission revoked successfully", StatusCode::OK),
+        Ok(false) => error_response("Role does not hold this permission", StatusCode::NOT_FOUND),
+        Err(_) => error_response("Failed to revoke permission", StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn user_permissions(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    let roles = match services::fetch_user_role_slugs(state.repo.as_ref(), &user_id).await {
+        Ok(roles) => roles,
+        Err(_) => return error_response("Failed to fetch user roles", StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let permissions = match services::fetch_user_permissions(state.repo.as_ref(), &user_id).await {
+        Ok(permissions) => permissions,
+        Err(_) => return error_response("Failed to fetch user permissions", StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let effective = EffectivePermissions {
         user_id,
-    )
-    .fetch_one(pool)
-    .await?;
+        roles,
+        permissions,
+    };
+
+    success_response(Some(effective), "User permissions retrieved successfully", StatusCode::OK)
+}
+
+// Route-level behaviour: status codes, guards and response shapes, driven through the router.
+// Storage itself is covered per backend in repository.rs; these run on in-memory SQLite.
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{Method, Request}};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn app() -> (Router, Arc<AppState>) {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let db: Arc<dyn db::Database> = Arc::new(db::Sqlite::new(pool));
+        migrations::migrate(db.as_ref()).await.unwrap();
+
+        let config = config::Config::load(|key| (key == "SECRET_KEY").then(|| "s".repeat(32))).unwrap();
+        let state = Arc::new(AppState::new(config, db));
+
+        (router(state.clone()), state)
+    }
+
+    async fn send(app: &Router, method: Method, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+        send_with_headers(app, method, uri, token, &[], body).await
+    }
+
+    async fn send_with_headers(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let typed = headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("content-type"));
+        let request = match body {
+            Some(body) if typed => request.body(Body::from(body.to_string())),
+            Some(body) => request.header("content-type", "application/json").body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+
+        let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    fn account(username: &str) -> Value {
+        json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "password": "correct horse battery staple",
+            "first_name": "Test",
+            "country": "DE",
+            "city": "Berlin",
+            "date_of_birth": "1990-01-31",
+        })
+    }
+
+    // Signs up through the route and returns (user id, access token)
+    async fn sign_up_as(app: &Router, username: &str) -> (String, String) {
+        let (status, body) = send(app, Method::POST, "/auth/sign-up", None, Some(account(username))).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+
+        let data = &body["data"];
+        (data["user_id"].as_str().unwrap().to_string(), data["access_token"].as_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn sign_up_on_a_fresh_database() {
+        let (app, state) = app().await;
+
+        let (user_id, _) = sign_up_as(&app, "ada").await;
+        let roles = services::fetch_user_role_slugs(state.repo.as_ref(), &user_id).await.unwrap();
+        assert_eq!(roles, vec![DEFAULT_ROLE.to_string()]);
+
+        let (status, _) = send(&app, Method::POST, "/auth/sign-up", None, Some(account("ada"))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn sign_up_rejects_malformed_fields() {
+        let (app, _) = app().await;
+
+        let mut invalid = account("grace");
+        invalid["date_of_birth"] = json!("31/01/1990");
+        let (status, body) = send(&app, Method::POST, "/auth/sign-up", None, Some(invalid)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["message"].as_str().unwrap().contains("date_of_birth"));
+
+        let mut invalid = account("grace");
+        invalid["password"] = json!("short");
+        let (status, body) = send(&app, Method::POST, "/auth/sign-up", None, Some(invalid)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["message"].as_str().unwrap().contains("password"));
+    }
+
+    // Drafts an invoice for a one-line order the customer places
+    async fn draft_invoice(app: &Router, admin: &str, customer: &str) -> String {
+        let order = json!({
+            "currency": "EUR",
+            "lines": [{ "description": "Support", "quantity": 1, "unit_price": { "amount": "10.00", "currency": "EUR" } }],
+        });
+        let (status, body) = send(app, Method::POST, "/orders", Some(customer), Some(order)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+
+        let invoice = json!({ "order_id": body["data"]["id"] });
+        let (status, body) = send(app, Method::POST, "/invoices", Some(admin), Some(invoice)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        body["data"]["id"].as_str().unwrap().to_string()
+    }
+
+    async fn sign_in_as(app: &Router, username: &str) -> String {
+        let credentials = json!({ "username": username, "password": "correct horse battery staple" });
+        let (status, body) = send(app, Method::POST, "/auth/sign-in", None, Some(credentials)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        body["data"]["access_token"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn logout_revokes_only_what_came_before() {
+        let (app, _) = app().await;
+        let (_, first) = sign_up_as(&app, "linus").await;
+        let second = sign_in_as(&app, "linus").await;
+
+        let (status, _) = send(&app, Method::POST, "/auth/logout", Some(&first), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&app, Method::GET, "/profile", Some(&first), None).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::UNAUTHORIZED, Some("token_revoked")));
+        assert_eq!(send(&app, Method::GET, "/profile", Some(&second), None).await.0, StatusCode::OK);
+
+        // Signing out everywhere catches the other session, but not a sign-in in the same second
+        let (status, _) = send(&app, Method::POST, "/auth/logout-all", Some(&second), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let third = sign_in_as(&app, "linus").await;
+        let (status, body) = send(&app, Method::GET, "/profile", Some(&second), None).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::UNAUTHORIZED, Some("token_revoked")));
+        assert_eq!(send(&app, Method::GET, "/profile", Some(&third), None).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn auth_failures_carry_distinct_codes() {
+        let (app, state) = app().await;
+        let (user_id, _) = sign_up_as(&app, "ken").await;
+
+        let token = |secret: &str, exp: i64| {
+            let now = chrono::Utc::now().timestamp();
+            let claims = utils::Claims { sub: user_id.clone(), jti: utils::generate_uuid(), iat: now, exp: now + exp, iat_ms: None };
+            let key = jsonwebtoken::EncodingKey::from_secret(secret.as_bytes());
+            jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap()
+        };
+        let forged = token("not-the-secret", 60);
+        let expired = token(&state.config.secret_key, -3600);
+
+        for (token, code) in [
+            (None, "token_missing"),
+            (Some("not-a-jwt"), "token_malformed"),
+            (Some(forged.as_str()), "token_invalid_signature"),
+            (Some(expired.as_str()), "token_expired"),
+        ] {
+            let (status, body) = send(&app, Method::GET, "/profile", token, None).await;
+            assert_eq!((status, body["code"].as_str()), (StatusCode::UNAUTHORIZED, Some(code)));
+        }
+    }
+
+    #[tokio::test]
+    async fn role_guards_name_the_missing_requirement() {
+        let (_, state) = app().await;
+        let app = Router::new()
+            .route("/audit", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(RoleRequirement::AllOf(&["staff", "auditor"]), auth::require_roles))
+            .route_layer(middleware::from_fn(auth::require_auth))
+            .merge(config())
+            .layer(Extension(state.clone()));
+        let (user_id, token) = sign_up_as(&app, "casey").await;
+        services::assign_role(state.db.as_ref(), &user_id, "staff").await.unwrap();
+
+        let (status, body) = send(&app, Method::GET, "/audit", Some(&token), None).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::FORBIDDEN, Some("role_required")));
+        assert_eq!(body["data"]["required"], json!({ "all_of_roles": ["staff", "auditor"] }));
+
+        services::create_role(state.db.as_ref(), &serde_json::from_value(json!({ "slug": "auditor", "name": "Auditor" })).unwrap())
+            .await
+            .unwrap();
+        services::assign_role(state.db.as_ref(), &user_id, "auditor").await.unwrap();
+        assert_eq!(send(&app, Method::GET, "/audit", Some(&token), None).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn permission_guards_admit_any_role_holding_the_permission() {
+        let (app, state) = app().await;
+        let (user_id, token) = sign_up_as(&app, "bea").await;
+
+        let (status, body) = send(&app, Method::GET, "/admin/products", Some(&token), None).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::FORBIDDEN, Some("permission_required")));
+        assert_eq!(body["data"]["required"], json!({ "any_of_permissions": ["catalog:manage"] }));
+
+        // A custom role holding one area's permission reaches that area and nothing else
+        let (admin_id, admin) = sign_up_as(&app, "root").await;
+        services::assign_role(state.db.as_ref(), &admin_id, "admin").await.unwrap();
+        let role = json!({ "slug": "merchandiser", "name": "Merchandiser" });
+        assert_eq!(send(&app, Method::POST, "/admin/roles", Some(&admin), Some(role)).await.0, StatusCode::CREATED);
+        let grant = json!({ "permission": "catalog:manage" });
+        let (status, _) = send(&app, Method::POST, "/admin/roles/merchandiser/permissions", Some(&admin), Some(grant)).await;
+        assert_eq!(status, StatusCode::OK);
+        let assignment = json!({ "role": "merchandiser" });
+        let uri = format!("/admin/users/{}/roles", user_id);
+        assert_eq!(send(&app, Method::POST, &uri, Some(&admin), Some(assignment)).await.0, StatusCode::OK);
+
+        assert_eq!(send(&app, Method::GET, "/admin/products", Some(&token), None).await.0, StatusCode::OK);
+        assert_eq!(send(&app, Method::GET, "/dashboard", Some(&token), None).await.0, StatusCode::FORBIDDEN);
+
+        // Role assignment needs both permissions
+        let (status, body) = send(&app, Method::POST, &uri, Some(&token), Some(json!({ "role": "admin" }))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["data"]["required"], json!({ "all_of_permissions": ["roles:manage", "users:read"] }));
+    }
+
+    #[tokio::test]
+    async fn directory_filters_on_profiles_and_pages_by_cursor() {
+        let (app, state) = app().await;
+        let (admin_id, admin) = sign_up_as(&app, "root").await;
+        services::assign_role(state.db.as_ref(), &admin_id, "admin").await.unwrap();
+
+        for (username, city) in [("ana", "Berlin"), ("ben", "Hamburg"), ("cid", "Berlin"), ("dee", "Berlin")] {
+            let mut payload = account(username);
+            payload["city"] = json!(city);
+            if username == "cid" {
+                payload["email"] = Value::Null;
+            }
+            assert_eq!(send(&app, Method::POST, "/auth/sign-up", None, Some(payload)).await.0, StatusCode::CREATED);
+        }
+
+        // Filters read the profile, so a move made after sign-up is what the directory sees
+        let ben = sign_in_as(&app, "ben").await;
+        let (status, _) = send(&app, Method::PATCH, "/profile", Some(&ben), Some(json!({ "city": "Berlin" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(&app, Method::GET, "/admin/users?city=Hamburg", Some(&admin), None).await;
+        assert_eq!(body["data"]["total"], 0);
+
+        let usernames = |body: &Value| {
+            body["data"]["items"].as_array().unwrap().iter().map(|item| item["username"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        // A missing email sorts as the empty string, ahead of every address
+        let uri = "/admin/users?country=DE&city=Berlin&sort=email&order=asc&limit=2";
+        let (status, body) = send(&app, Method::GET, uri, Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["total"], 5);
+        assert_eq!(usernames(&body), ["cid", "ana"]);
+        assert!(body["data"]["prev_cursor"].is_null());
 
-    Ok(settings)
+        let next = body["data"]["next_cursor"].as_str().unwrap().to_string();
+        let (_, body) = send(&app, Method::GET, &format!("{}&cursor={}", uri, next), Some(&admin), None).await;
+        assert_eq!(usernames(&body), ["ben", "dee"]);
+
+        let next = body["data"]["next_cursor"].as_str().unwrap().to_string();
+        let (_, body) = send(&app, Method::GET, &format!("{}&cursor={}", uri, next), Some(&admin), None).await;
+        assert_eq!(usernames(&body), ["root"]);
+        assert!(body["data"]["next_cursor"].is_null());
+
+        // Walking back from the last page returns the one before it, in order
+        let prev = body["data"]["prev_cursor"].as_str().unwrap().to_string();
+        let (_, body) = send(&app, Method::GET, &format!("{}&cursor={}", uri, prev), Some(&admin), None).await;
+        assert_eq!(usernames(&body), ["ben", "dee"]);
+
+        // A cursor minted for one sort is refused under another
+        let uri = format!("/admin/users?sort=username&cursor={}", prev);
+        assert_eq!(send(&app, Method::GET, &uri, Some(&admin), None).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn numbering_follows_the_callers_tenant_not_the_header() {
+        let (app, state) = app().await;
+        let (root_id, root) = sign_up_as(&app, "root").await;
+        let (other_id, other) = sign_up_as(&app, "acme-admin").await;
+        for user_id in [&root_id, &other_id] {
+            services::assign_role(state.db.as_ref(), user_id, "admin").await.unwrap();
+        }
+        let moved = db::Statement::new("UPDATE users SET tenant_id = 'acme' WHERE id = ?1").bind(other_id.as_str());
+        state.db.execute(moved).await.unwrap();
+
+        let (_, customer) = sign_up_as(&app, "carol").await;
+        let issue = format!("/invoices/{}/issue", draft_invoice(&app, &root, &customer).await);
+
+        // Naming a tenant the caller does not belong to is refused rather than trusted
+        let acme = [(numbering::TENANT_HEADER, "acme")];
+        let (status, _) = send_with_headers(&app, Method::POST, &issue, Some(&root), &acme, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // A member of another tenant cannot draw numbers for this tenant's invoice
+        let (status, _) = send_with_headers(&app, Method::POST, &issue, Some(&other), &acme, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let scheme = json!({ "prefix": "ACME" });
+        let (status, body) = send(&app, Method::PUT, "/admin/numbering/invoice", Some(&other), Some(scheme)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["tenant_id"], "acme");
+
+        let (status, body) = send(&app, Method::POST, &issue, Some(&root), None).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["data"]["number"].as_str().unwrap().starts_with("INV-"));
+    }
+
+    #[tokio::test]
+    async fn invoice_pdf_is_served_to_its_owner_from_the_cache() {
+        let (app, state) = app().await;
+        let (admin_id, admin) = sign_up_as(&app, "root").await;
+        services::assign_role(state.db.as_ref(), &admin_id, "admin").await.unwrap();
+        let (customer_id, customer) = sign_up_as(&app, "carol").await;
+        let (_, stranger) = sign_up_as(&app, "dave").await;
+
+        let invoice_id = draft_invoice(&app, &admin, &customer).await;
+        let (status, body) = send(&app, Method::POST, &format!("/invoices/{}/issue", invoice_id), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let number = body["data"]["number"].as_str().unwrap().to_string();
+
+        let uri = format!("/invoices/{}/pdf", invoice_id);
+        let download = |token: String| {
+            let request = Request::builder()
+                .uri(uri.as_str())
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let response = download(customer.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/pdf");
+        let disposition = format!("attachment; filename=\"{}.pdf\"", number);
+        assert_eq!(response.headers()[CONTENT_DISPOSITION], disposition.as_str());
+        let pdf = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(pdf.starts_with(b"%PDF-"));
+
+        // Other customers cannot tell the invoice exists
+        assert_eq!(download(stranger).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+        // Later downloads share the cached buffer rather than rendering or copying it again
+        let document = services::fetch_invoice_document(state.db.as_ref(), &invoice_id, Some(&customer_id)).await.unwrap().unwrap();
+        let cached = state.pdf_cache.get_or_render(state.invoice_template.as_ref(), &document);
+        assert_eq!(cached, pdf);
+        let again = state.pdf_cache.get_or_render(state.invoice_template.as_ref(), &document);
+        assert_eq!(cached.as_ptr(), again.as_ptr());
+    }
+
+    #[tokio::test]
+    async fn profile_patches_follow_their_media_type() {
+        let (app, _) = app().await;
+        let (_, token) = sign_up_as(&app, "pat").await;
+        let patch = |media_type: &'static str, body: Value| {
+            let app = app.clone();
+            let token = token.clone();
+            async move {
+                let headers = [("content-type", media_type)];
+                send_with_headers(&app, Method::PATCH, "/profile", Some(&token), &headers, Some(body)).await
+            }
+        };
+        let merge = "application/merge-patch+json";
+        let json_patch = "application/json-patch+json";
+
+        // Merge patch: null clears, absent keeps, nested objects merge member by member
+        let (status, body) =
+            patch(merge, json!({ "first_name": null, "middle_name": "Q", "configuration": { "theme": "dark", "lang": "en" } })).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["first_name"], Value::Null);
+        assert_eq!(body["data"]["middle_name"], "Q");
+        assert_eq!(body["data"]["city"], "Berlin");
+        let (_, body) = patch(merge, json!({ "configuration": { "theme": null, "lang": "de" } })).await;
+        assert_eq!(body["data"]["configuration"], json!({ "lang": "de" }));
+
+        // JSON Patch applies all operations or none of them
+        let operations = json!([
+            { "op": "test", "path": "/city", "value": "Berlin" },
+            { "op": "replace", "path": "/city", "value": "Munich" },
+        ]);
+        let (status, body) = patch(json_patch, operations.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["city"], "Munich");
+        assert_eq!(patch(json_patch, operations).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+        let (_, body) = send(&app, Method::GET, "/profile", Some(&token), None).await;
+        assert_eq!(body["data"]["city"], "Munich");
+
+        let immutable = json!([{ "op": "replace", "path": "/id", "value": "someone-else" }]);
+        assert_eq!(patch(json_patch, immutable).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(patch(merge, json!({ "shoe_size": 44 })).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, body) = patch(merge, json!({ "date_of_birth": "31.01.1990" })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["message"], "`date_of_birth` must be formatted as YYYY-MM-DD");
+        assert_eq!(patch("text/plain", json!({})).await.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
 }
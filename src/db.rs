@@ -0,0 +1,629 @@
+// src/db.rs
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value as Json};
+#[cfg(feature = "native")]
+use sqlx::{Column, Row as _, TypeInfo, ValueRef};
+use std::fmt;
+#[cfg(feature = "native")]
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Postgres,
+    Sqlite,
+    D1,
+}
+
+impl Backend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Backend::Postgres => "postgres",
+            Backend::Sqlite => "sqlite",
+            Backend::D1 => "d1",
+        }
+    }
+
+    // D1 is never reached through a URL; it comes from the Worker's `DB` binding
+    pub fn from_url(url: &str) -> Option<Self> {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            Some(Backend::Postgres)
+        } else if url.starts_with("sqlite:") {
+            Some(Backend::Sqlite)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DbError {
+    #[cfg(feature = "native")]
+    Sqlx(sqlx::Error),
+    D1(String),
+    Decode(String),
+    RowNotFound,
+    Unsupported(String),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "native")]
+            DbError::Sqlx(e) => write!(f, "{}", e),
+            DbError::D1(message) => write!(f, "D1: {}", message),
+            DbError::Decode(message) => write!(f, "Failed to decode row: {}", message),
+            DbError::RowNotFound => f.write_str("No rows returned by a query that expected to return at least one row"),
+            DbError::Unsupported(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+// The constraint failures callers react to, however the backend reports them
+#[derive(Clone, Copy, PartialEq)]
+enum Constraint {
+    Unique,
+    NotNull,
+    Check,
+}
+
+impl DbError {
+    fn constraint(&self) -> Option<Constraint> {
+        match self {
+            #[cfg(feature = "native")]
+            DbError::Sqlx(sqlx::Error::Database(e)) => match e.kind() {
+                sqlx::error::ErrorKind::UniqueViolation => Some(Constraint::Unique),
+                sqlx::error::ErrorKind::NotNullViolation => Some(Constraint::NotNull),
+                sqlx::error::ErrorKind::CheckViolation => Some(Constraint::Check),
+                _ => None,
+            },
+            // D1 only reports SQLite's message text
+            DbError::D1(message) if message.contains("UNIQUE constraint failed") => Some(Constraint::Unique),
+            DbError::D1(message) if message.contains("NOT NULL constraint failed") => Some(Constraint::NotNull),
+            DbError::D1(message) if message.contains("CHECK constraint failed") => Some(Constraint::Check),
+            _ => None,
+        }
+    }
+
+    pub fn is_unique_violation(&self) -> bool {
+        self.constraint() == Some(Constraint::Unique)
+    }
+
+    // Batches carry their own guards, since D1 cannot hold a lock between a read and a write:
+    // a version bump that writes NULL when the row moved on, or a CHECK such as non-negative
+    // stock. Either one failing aborts the whole batch.
+    pub fn is_guard_violation(&self) -> bool {
+        matches!(self.constraint(), Some(Constraint::NotNull | Constraint::Check))
+    }
+}
+
+#[cfg(feature = "native")]
+impl From<sqlx::Error> for DbError {
+    fn from(e: sqlx::Error) -> Self {
+        DbError::Sqlx(e)
+    }
+}
+
+#[cfg(feature = "workers")]
+impl From<worker::Error> for DbError {
+    fn from(e: worker::Error) -> Self {
+        DbError::D1(e.to_string())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Real(f64),
+    Text(String),
+    Timestamp(NaiveDateTime),
+    Date(NaiveDate),
+    Json(Json),
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value as i64)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Real(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<NaiveDateTime> for Value {
+    fn from(value: NaiveDateTime) -> Self {
+        Value::Timestamp(value)
+    }
+}
+
+impl From<NaiveDate> for Value {
+    fn from(value: NaiveDate) -> Self {
+        Value::Date(value)
+    }
+}
+
+impl From<Json> for Value {
+    fn from(value: Json) -> Self {
+        Value::Json(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+// SQL is written once with SQLite-style `?1, ?2` placeholders, which D1 shares;
+// the Postgres backend rewrites them to `$1, $2` before preparing
+#[derive(Clone, Debug)]
+pub struct Statement {
+    pub sql: String,
+    pub params: Vec<Value>,
+}
+
+impl Statement {
+    pub fn new(sql: impl Into<String>) -> Self {
+        Statement {
+            sql: sql.into(),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn bind(mut self, value: impl Into<Value>) -> Self {
+        self.params.push(value.into());
+        self
+    }
+
+    pub fn push(mut self, sql: &str) -> Self {
+        self.sql.push_str(sql);
+        self
+    }
+
+    // Appends an optional clause; every bare `?` in it refers to the one value bound here
+    pub fn push_bind(mut self, clause: &str, value: impl Into<Value>) -> Self {
+        self.params.push(value.into());
+        self.sql.push_str(&clause.replace('?', &format!("?{}", self.params.len())));
+        self
+    }
+}
+
+// A case-insensitive "contains" pattern for `LOWER(column) LIKE ? ESCAPE '\'`, with the term's own
+// wildcards escaped so a search for `50%` or `a_b` matches them literally
+pub fn contains_pattern(term: &str) -> String {
+    let escaped = term.to_lowercase().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+#[cfg(feature = "native")]
+fn postgres_placeholders(sql: &str) -> String {
+    let mut rewritten = String::with_capacity(sql.len());
+    let mut in_string = false;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_string = !in_string;
+                rewritten.push(c);
+            }
+            '?' if !in_string && chars.peek().is_some_and(|next| next.is_ascii_digit()) => rewritten.push('$'),
+            _ => rewritten.push(c),
+        }
+    }
+
+    rewritten
+}
+
+// A row decoded into column name → JSON value, the one shape all three backends can produce.
+// Timestamps are text on SQLite and D1, so they are parsed here rather than through serde.
+#[derive(Clone, Debug, Default)]
+pub struct Row(Map<String, Json>);
+
+impl Row {
+    pub fn get<T: DeserializeOwned>(&self, column: &str) -> Result<T, DbError> {
+        let value = self.0.get(column).cloned().unwrap_or(Json::Null);
+        serde_json::from_value(value).map_err(|e| DbError::Decode(format!("column `{}`: {}", column, e)))
+    }
+
+    // SQLite and D1 have no boolean type and hand back 0/1
+    pub fn get_bool(&self, column: &str) -> Result<bool, DbError> {
+        match self.0.get(column) {
+            Some(Json::Bool(value)) => Ok(*value),
+            Some(Json::Number(value)) => Ok(value.as_i64() != Some(0)),
+            other => Err(DbError::Decode(format!("column `{}`: expected a boolean, got {:?}", column, other))),
+        }
+    }
+
+    pub fn get_datetime(&self, column: &str) -> Result<NaiveDateTime, DbError> {
+        let text: String = self.get(column)?;
+        ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(&text, format).ok())
+            .ok_or_else(|| DbError::Decode(format!("column `{}`: `{}` is not a timestamp", column, text)))
+    }
+
+    pub fn get_optional_datetime(&self, column: &str) -> Result<Option<NaiveDateTime>, DbError> {
+        match self.0.get(column) {
+            None | Some(Json::Null) => Ok(None),
+            Some(_) => self.get_datetime(column).map(Some),
+        }
+    }
+
+    pub fn get_date(&self, column: &str) -> Result<NaiveDate, DbError> {
+        let text: String = self.get(column)?;
+        NaiveDate::parse_from_str(&text[..text.len().min(10)], "%Y-%m-%d")
+            .map_err(|_| DbError::Decode(format!("column `{}`: `{}` is not a date", column, text)))
+    }
+
+    pub fn get_optional_date(&self, column: &str) -> Result<Option<NaiveDate>, DbError> {
+        match self.0.get(column) {
+            None | Some(Json::Null) => Ok(None),
+            Some(_) => self.get_date(column).map(Some),
+        }
+    }
+
+    // JSON columns arrive parsed from Postgres but as text from SQLite and D1
+    pub fn get_json(&self, column: &str) -> Result<Option<Json>, DbError> {
+        match self.0.get(column) {
+            None | Some(Json::Null) => Ok(None),
+            Some(Json::String(text)) => serde_json::from_str(text)
+                .map(Some)
+                .map_err(|e| DbError::Decode(format!("column `{}`: {}", column, e))),
+            Some(value) => Ok(Some(value.clone())),
+        }
+    }
+
+    pub fn get_money(&self, amount: &str, currency: &str) -> Result<crate::money::Money, DbError> {
+        Ok(crate::money::Money::from_minor(self.get(amount)?, self.get(currency)?))
+    }
+}
+
+// Send is required so the same trait object can sit in axum state on every runtime;
+// the D1 backend wraps its single-threaded JS handles to satisfy it
+#[async_trait]
+pub trait Database: Send + Sync {
+    fn backend(&self) -> Backend;
+
+    async fn fetch_all(&self, statement: Statement) -> Result<Vec<Row>, DbError>;
+
+    async fn execute(&self, statement: Statement) -> Result<u64, DbError>;
+
+    // All statements commit together or not at all: a transaction on sqlx pools, a batch on D1
+    async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<u64>, DbError>;
+
+    async fn fetch_optional(&self, statement: Statement) -> Result<Option<Row>, DbError> {
+        Ok(self.fetch_all(statement).await?.into_iter().next())
+    }
+
+    async fn fetch_one(&self, statement: Statement) -> Result<Row, DbError> {
+        self.fetch_optional(statement)
+            .await?
+            .ok_or(DbError::RowNotFound)
+    }
+}
+
+#[cfg(feature = "native")]
+pub struct Postgres {
+    pool: sqlx::PgPool,
+}
+
+#[cfg(feature = "native")]
+impl Postgres {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Postgres { pool }
+    }
+
+    fn query(statement: &Statement) -> (String, Vec<Value>) {
+        (postgres_placeholders(&statement.sql), statement.params.clone())
+    }
+}
+
+// A NULL sent with no type of its own, which Postgres then infers from where it is used,
+// so the same statement can bind NULL into a TEXT, BIGINT or TIMESTAMP column
+#[cfg(feature = "native")]
+struct UntypedNull;
+
+#[cfg(feature = "native")]
+impl sqlx::Type<sqlx::Postgres> for UntypedNull {
+    fn type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_oid(sqlx::postgres::types::Oid(0))
+    }
+}
+
+#[cfg(feature = "native")]
+impl sqlx::Encode<'_, sqlx::Postgres> for UntypedNull {
+    fn encode_by_ref(&self, _buf: &mut sqlx::postgres::PgArgumentBuffer) -> sqlx::encode::IsNull {
+        sqlx::encode::IsNull::Yes
+    }
+}
+
+#[cfg(feature = "native")]
+fn bind_postgres<'q>(
+    mut query: sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments>,
+    params: Vec<Value>,
+) -> sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments> {
+    for param in params {
+        query = match param {
+            Value::Null => query.bind(UntypedNull),
+            Value::Bool(value) => query.bind(value),
+            Value::Int(value) => query.bind(value),
+            Value::Real(value) => query.bind(value),
+            Value::Text(value) => query.bind(value),
+            Value::Timestamp(value) => query.bind(value),
+            Value::Date(value) => query.bind(value),
+            Value::Json(value) => query.bind(sqlx::types::Json(value)),
+        };
+    }
+    query
+}
+
+#[cfg(feature = "native")]
+fn decode_postgres(row: &sqlx::postgres::PgRow) -> Result<Row, DbError> {
+    let mut decoded = Map::new();
+
+    for column in row.columns() {
+        let i = column.ordinal();
+        let value = if row.try_get_raw(i)?.is_null() {
+            Json::Null
+        } else {
+            match column.type_info().name() {
+                "BOOL" => Json::from(row.try_get::<bool, _>(i)?),
+                "INT2" => Json::from(row.try_get::<i16, _>(i)?),
+                "INT4" => Json::from(row.try_get::<i32, _>(i)?),
+                "INT8" => Json::from(row.try_get::<i64, _>(i)?),
+                "FLOAT4" => Json::from(row.try_get::<f32, _>(i)?),
+                "FLOAT8" => Json::from(row.try_get::<f64, _>(i)?),
+                "TIMESTAMP" => Json::from(row.try_get::<NaiveDateTime, _>(i)?.to_string()),
+                "TIMESTAMPTZ" => Json::from(row.try_get::<chrono::DateTime<chrono::Utc>, _>(i)?.naive_utc().to_string()),
+                "DATE" => Json::from(row.try_get::<NaiveDate, _>(i)?.to_string()),
+                "JSON" | "JSONB" => row.try_get::<Json, _>(i)?,
+                "TEXT" | "VARCHAR" | "BPCHAR" | "NAME" => Json::from(row.try_get::<String, _>(i)?),
+                other => {
+                    return Err(DbError::Decode(format!(
+                        "column `{}` has unsupported type {}; cast it in the query",
+                        column.name(),
+                        other
+                    )))
+                }
+            }
+        };
+        decoded.insert(column.name().to_string(), value);
+    }
+
+    Ok(Row(decoded))
+}
+
+#[cfg(feature = "native")]
+#[async_trait]
+impl Database for Postgres {
+    fn backend(&self) -> Backend {
+        Backend::Postgres
+    }
+
+    async fn fetch_all(&self, statement: Statement) -> Result<Vec<Row>, DbError> {
+        let (sql, params) = Self::query(&statement);
+        let rows = bind_postgres(sqlx::query(&sql), params).fetch_all(&self.pool).await?;
+        rows.iter().map(decode_postgres).collect()
+    }
+
+    async fn execute(&self, statement: Statement) -> Result<u64, DbError> {
+        let (sql, params) = Self::query(&statement);
+        let result = bind_postgres(sqlx::query(&sql), params).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<u64>, DbError> {
+        let mut tx = self.pool.begin().await?;
+        let mut affected = Vec::with_capacity(statements.len());
+        for statement in &statements {
+            let (sql, params) = Self::query(statement);
+            affected.push(bind_postgres(sqlx::query(&sql), params).execute(&mut *tx).await?.rows_affected());
+        }
+        tx.commit().await?;
+        Ok(affected)
+    }
+}
+
+#[cfg(feature = "native")]
+pub struct Sqlite {
+    pool: sqlx::SqlitePool,
+}
+
+#[cfg(feature = "native")]
+impl Sqlite {
+    pub fn new(pool: sqlx::SqlitePool) -> Self {
+        Sqlite { pool }
+    }
+}
+
+#[cfg(feature = "native")]
+fn bind_sqlite<'q>(
+    mut query: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    params: Vec<Value>,
+) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+    for param in params {
+        query = match param {
+            Value::Null => query.bind(None::<String>),
+            Value::Bool(value) => query.bind(value),
+            Value::Int(value) => query.bind(value),
+            Value::Real(value) => query.bind(value),
+            Value::Text(value) => query.bind(value),
+            Value::Timestamp(value) => query.bind(value),
+            Value::Date(value) => query.bind(value),
+            Value::Json(value) => query.bind(value.to_string()),
+        };
+    }
+    query
+}
+
+// SQLite is dynamically typed, so each value is decoded by its storage class
+#[cfg(feature = "native")]
+fn decode_sqlite(row: &sqlx::sqlite::SqliteRow) -> Result<Row, DbError> {
+    let mut decoded = Map::new();
+
+    for column in row.columns() {
+        let i = column.ordinal();
+        let raw = row.try_get_raw(i)?;
+        let value = if raw.is_null() {
+            Json::Null
+        } else {
+            match raw.type_info().name() {
+                "INTEGER" | "BOOLEAN" => Json::from(row.try_get::<i64, _>(i)?),
+                "REAL" => Json::from(row.try_get::<f64, _>(i)?),
+                "BLOB" => Json::from(hex::encode(row.try_get::<Vec<u8>, _>(i)?)),
+                _ => Json::from(row.try_get::<String, _>(i)?),
+            }
+        };
+        decoded.insert(column.name().to_string(), value);
+    }
+
+    Ok(Row(decoded))
+}
+
+#[cfg(feature = "native")]
+#[async_trait]
+impl Database for Sqlite {
+    fn backend(&self) -> Backend {
+        Backend::Sqlite
+    }
+
+    async fn fetch_all(&self, statement: Statement) -> Result<Vec<Row>, DbError> {
+        let rows = bind_sqlite(sqlx::query(&statement.sql), statement.params).fetch_all(&self.pool).await?;
+        rows.iter().map(decode_sqlite).collect()
+    }
+
+    async fn execute(&self, statement: Statement) -> Result<u64, DbError> {
+        let result = bind_sqlite(sqlx::query(&statement.sql), statement.params).execute(&self.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<u64>, DbError> {
+        let mut tx = self.pool.begin().await?;
+        let mut affected = Vec::with_capacity(statements.len());
+        for statement in statements {
+            affected.push(
+                bind_sqlite(sqlx::query(&statement.sql), statement.params)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected(),
+            );
+        }
+        tx.commit().await?;
+        Ok(affected)
+    }
+}
+
+#[cfg(feature = "workers")]
+pub struct D1 {
+    db: worker::send::SendWrapper<worker::D1Database>,
+}
+
+#[cfg(feature = "workers")]
+impl D1 {
+    pub fn new(db: worker::D1Database) -> Self {
+        D1 {
+            db: worker::send::SendWrapper::new(db),
+        }
+    }
+
+    fn prepare(&self, statement: &Statement) -> Result<worker::D1PreparedStatement, DbError> {
+        let params: Vec<worker::wasm_bindgen::JsValue> = statement
+            .params
+            .iter()
+            .map(|param| match param {
+                Value::Null => worker::wasm_bindgen::JsValue::NULL,
+                Value::Bool(value) => worker::wasm_bindgen::JsValue::from(*value as i32),
+                Value::Int(value) => worker::wasm_bindgen::JsValue::from(*value as f64),
+                Value::Real(value) => worker::wasm_bindgen::JsValue::from(*value),
+                Value::Text(value) => worker::wasm_bindgen::JsValue::from_str(value),
+                Value::Timestamp(value) => worker::wasm_bindgen::JsValue::from_str(&value.to_string()),
+                Value::Date(value) => worker::wasm_bindgen::JsValue::from_str(&value.to_string()),
+                Value::Json(value) => worker::wasm_bindgen::JsValue::from_str(&value.to_string()),
+            })
+            .collect();
+
+        Ok(self.db.prepare(&statement.sql).bind(&params)?)
+    }
+}
+
+#[cfg(feature = "workers")]
+fn d1_changes(result: &worker::D1Result) -> Result<u64, DbError> {
+    Ok(result.meta()?.and_then(|meta| meta.changes).unwrap_or(0) as u64)
+}
+
+#[cfg(feature = "workers")]
+#[async_trait]
+impl Database for D1 {
+    fn backend(&self) -> Backend {
+        Backend::D1
+    }
+
+    async fn fetch_all(&self, statement: Statement) -> Result<Vec<Row>, DbError> {
+        let prepared = self.prepare(&statement)?;
+        worker::send::SendFuture::new(async move {
+            let rows = prepared.all().await?.results::<Map<String, Json>>()?;
+            Ok(rows.into_iter().map(Row).collect())
+        })
+        .await
+    }
+
+    async fn execute(&self, statement: Statement) -> Result<u64, DbError> {
+        let prepared = self.prepare(&statement)?;
+        worker::send::SendFuture::new(async move { d1_changes(&prepared.run().await?) }).await
+    }
+
+    // D1 has no interactive transactions; a batch is its unit of atomicity
+    async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<u64>, DbError> {
+        let prepared = statements
+            .iter()
+            .map(|statement| self.prepare(statement))
+            .collect::<Result<Vec<_>, _>>()?;
+        worker::send::SendFuture::new(async move { self.db.batch(prepared).await?.iter().map(d1_changes).collect() })
+            .await
+    }
+}
+
+#[cfg(feature = "native")]
+pub async fn connect(database_url: &str) -> Result<Arc<dyn Database>, DbError> {
+    match Backend::from_url(database_url) {
+        Some(Backend::Postgres) => Ok(Arc::new(Postgres::new(sqlx::PgPool::connect(database_url).await?))),
+        Some(Backend::Sqlite) => Ok(Arc::new(Sqlite::new(sqlx::SqlitePool::connect(database_url).await?))),
+        _ => Err(DbError::Unsupported(format!(
+            "Unsupported DATABASE_URL `{}`; expected postgres:// or sqlite:",
+            database_url
+        ))),
+    }
+}
@@ -1,41 +1,59 @@
 // src/lib.rs
-use actix_web::{web, App, HttpServer, middleware};
-use actix_cors::Cors;
-use sqlx::sqlite::SqlitePool;  // Use SqlitePool instead of PgPool
-use dotenv::dotenv;
-use std::env;
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+use models::*;
+use std::sync::Arc;
+use utils::{error_response, success_response};
 
+mod analytics;
+// Public so routes mounted next to `router()` can reuse the extractor and the role and permission guards
+pub mod auth;
+mod config;
 mod controllers;
+mod db;
+mod migrations;
 mod models;
+mod money;
+mod numbering;
+mod pdf;
+mod products;
+mod repository;
+#[cfg(feature = "native")]
+mod seed;
 mod services;
+mod tax;
 mod utils;
 
-// Define the environment interface for D1 binding
-pub interface Env {
-    DB: D1Database;  // This refers to the D1Database binding set in wrangler.toml
-}
-
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    dotenv().ok();
+// The runtime is picked at build time: `workers` (the default) compiles the #[event(fetch)]
+// entrypoint for Cloudflare, `native` the tokio server behind `src/bin/server.rs`
+#[cfg(feature = "native")]
+pub mod native;
+#[cfg(feature = "workers")]
+mod workers;
 
-    // For local development, use a DATABASE_URL from the environment
-    let database_url = env::var("DATABASE_URL")
-        .unwrap_or_else(|_| "sqlite://cloudflare-d1-database".to_string());
+// `db` is whichever backend the runtime connected to; `repo` wraps it in the typed repositories
+pub struct AppState {
+    pub config: config::Config,
+    pub db: Arc<dyn db::Database>,
+    pub repo: Arc<repository::SqlRepository>,
+    pub revocations: auth::RevocationCache,
+    pub invoice_template: Box<dyn pdf::InvoiceTemplate>,
+    pub pdf_cache: pdf::PdfCache,
+}
 
-    // Connect to SQLite (D1 database)
-    let pool = SqlitePool::connect(&database_url)
-        .await
-        .expect("Failed to create SQLite pool.");
+impl AppState {
+    pub fn new(config: config::Config, db: Arc<dyn db::Database>) -> Self {
+        AppState {
+            config,
+            repo: Arc::new(repository::SqlRepository::new(db.clone())),
+            db,
+            revocations: auth::RevocationCache::new(),
+            invoice_template: Box::new(pdf::DefaultInvoiceTemplate),
+            pdf_cache: pdf::PdfCache::new(),
+        }
+    }
+}
 
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::new(pool.clone()))
-            .wrap(Cors::default())
-            .configure(controllers::config)
-            .wrap(middleware::Logger::default())
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+// The one route table both runtimes serve
+pub fn router(state: Arc<AppState>) -> axum::Router {
+    controllers::config().layer(Extension(state))
 }
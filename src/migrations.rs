@@ -0,0 +1,264 @@
+// src/migrations.rs
+use super::*;
+use chrono::{NaiveDateTime, Utc};
+use db::{Backend, Database, Statement};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utils::AppError;
+
+// Each migration is a pair of scripts under `migrations/`, compiled into the binary so a Worker
+// can migrate D1 without a filesystem. The scripts are shared by every backend; the few type
+// names that differ are written as `{placeholders}` and filled in by `render`.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $name, ".down.sql")),
+        }
+    };
+}
+
+// Append only: once a migration has shipped, edit it and every database that ran it refuses to start
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_identity"),
+    migration!(2, "0002_tokens"),
+    migration!(3, "0003_permissions"),
+    migration!(4, "0004_catalog"),
+    migration!(5, "0005_orders"),
+    migration!(6, "0006_invoices"),
+    migration!(7, "0007_payments"),
+    migration!(8, "0008_credit_notes"),
+    migration!(9, "0009_seed_records"),
+    migration!(10, "0010_row_versions"),
+    migration!(11, "0011_built_in_permissions"),
+    migration!(12, "0012_built_in_roles"),
+    migration!(13, "0013_profile_fields_on_profiles"),
+    migration!(14, "0014_tenant_membership"),
+];
+
+const TRACKING_TABLE: &str = r#"CREATE TABLE IF NOT EXISTS schema_migrations (
+    version BIGINT PRIMARY KEY,
+    name TEXT NOT NULL,
+    checksum TEXT NOT NULL,
+    applied_at TIMESTAMP NOT NULL
+)"#;
+
+impl Migration {
+    // Taken over both scripts as written, before placeholders are filled in, so the same
+    // migration has the same checksum on every backend and an edited rollback counts as drift
+    pub fn checksum(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.up.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.down.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+}
+
+fn render(sql: &str, backend: Backend) -> String {
+    let (json, serial_pk) = match backend {
+        Backend::Postgres => ("JSONB", "SERIAL PRIMARY KEY"),
+        Backend::Sqlite | Backend::D1 => ("TEXT", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+    };
+    sql.replace("{json}", json).replace("{serial_pk}", serial_pk)
+}
+
+// D1 prepares one statement at a time, so scripts are split on `;`. The scripts hold only DDL and
+// plain inserts, with no semicolons inside literals, which keeps this split safe.
+fn statements(sql: &str, backend: Backend) -> Vec<Statement> {
+    let rendered = render(sql, backend);
+    let without_comments: String = rendered
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    without_comments
+        .split(';')
+        .map(str::trim)
+        .filter(|statement| !statement.is_empty())
+        .map(Statement::new)
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    // Applied, but the embedded script no longer matches what ran
+    Drifted,
+    // Recorded in the database but unknown to this build, e.g. after deploying an older binary
+    Unknown,
+}
+
+#[derive(Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub checksum: String,
+    pub state: MigrationState,
+    pub applied_at: Option<NaiveDateTime>,
+}
+
+struct AppliedMigration {
+    version: i64,
+    name: String,
+    checksum: String,
+    applied_at: NaiveDateTime,
+}
+
+async fn applied_migrations(db: &dyn Database) -> Result<Vec<AppliedMigration>, Box<dyn std::error::Error>> {
+    db.execute(Statement::new(TRACKING_TABLE)).await?;
+
+    let rows = db
+        .fetch_all(Statement::new(
+            "SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version",
+        ))
+        .await?;
+
+    let mut applied = Vec::with_capacity(rows.len());
+    for row in rows {
+        applied.push(AppliedMigration {
+            version: row.get("version")?,
+            name: row.get("name")?,
+            checksum: row.get("checksum")?,
+            applied_at: row.get_datetime("applied_at")?,
+        });
+    }
+
+    Ok(applied)
+}
+
+pub async fn status(db: &dyn Database) -> Result<Vec<MigrationStatus>, Box<dyn std::error::Error>> {
+    let applied = applied_migrations(db).await?;
+
+    let mut statuses: Vec<MigrationStatus> = MIGRATIONS
+        .iter()
+        .map(|migration| {
+            let checksum = migration.checksum();
+            let record = applied.iter().find(|record| record.version == migration.version);
+            let state = match record {
+                Some(record) if record.checksum == checksum => MigrationState::Applied,
+                Some(_) => MigrationState::Drifted,
+                None => MigrationState::Pending,
+            };
+            MigrationStatus {
+                version: migration.version,
+                name: migration.name.to_string(),
+                checksum,
+                state,
+                applied_at: record.map(|record| record.applied_at),
+            }
+        })
+        .collect();
+
+    statuses.extend(
+        applied
+            .into_iter()
+            .filter(|record| !MIGRATIONS.iter().any(|migration| migration.version == record.version))
+            .map(|record| MigrationStatus {
+                version: record.version,
+                name: record.name,
+                checksum: record.checksum,
+                state: MigrationState::Unknown,
+                applied_at: Some(record.applied_at),
+            }),
+    );
+    statuses.sort_by_key(|status| status.version);
+
+    Ok(statuses)
+}
+
+// Every drifted or unknown migration is listed at once rather than stopping at the first
+fn check_drift(statuses: &[MigrationStatus]) -> Result<(), AppError> {
+    let problems: Vec<String> = statuses
+        .iter()
+        .filter_map(|status| match status.state {
+            MigrationState::Drifted => Some(format!(
+                "migration {} ({}) has changed since it was applied",
+                status.version, status.name
+            )),
+            MigrationState::Unknown => Some(format!(
+                "migration {} ({}) is applied but not part of this build",
+                status.version, status.name
+            )),
+            MigrationState::Applied | MigrationState::Pending => None,
+        })
+        .collect();
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Conflict(format!("Refusing to migrate: {}", problems.join("; "))))
+    }
+}
+
+// Applies every pending migration in order, each in its own transaction (a batch on D1)
+// together with its tracking row. Returns the versions that were applied.
+pub async fn migrate(db: &dyn Database) -> Result<Vec<i64>, Box<dyn std::error::Error>> {
+    let statuses = status(db).await?;
+    check_drift(&statuses)?;
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS {
+        let pending = statuses
+            .iter()
+            .any(|status| status.version == migration.version && status.state == MigrationState::Pending);
+        if !pending {
+            continue;
+        }
+
+        let mut batch = statements(migration.up, db.backend());
+        batch.push(
+            Statement::new(
+                "INSERT INTO schema_migrations (version, name, checksum, applied_at) VALUES (?1, ?2, ?3, ?4)",
+            )
+            .bind(migration.version)
+            .bind(migration.name)
+            .bind(migration.checksum())
+            .bind(Utc::now().naive_utc()),
+        );
+        db.batch(batch).await?;
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+// Reverts applied migrations newer than `target`, newest first; `target = 0` empties the schema
+#[cfg(feature = "native")]
+pub async fn rollback(db: &dyn Database, target: i64) -> Result<Vec<i64>, Box<dyn std::error::Error>> {
+    if target < 0 {
+        return Err(Box::new(AppError::Validation("Target version cannot be negative".to_string())));
+    }
+
+    let statuses = status(db).await?;
+    check_drift(&statuses)?;
+
+    let mut reverted = Vec::new();
+    for migration in MIGRATIONS.iter().rev() {
+        let applied = statuses
+            .iter()
+            .any(|status| status.version == migration.version && status.state == MigrationState::Applied);
+        if migration.version <= target || !applied {
+            continue;
+        }
+
+        let mut batch = statements(migration.down, db.backend());
+        batch.push(Statement::new("DELETE FROM schema_migrations WHERE version = ?1").bind(migration.version));
+        db.batch(batch).await?;
+        reverted.push(migration.version);
+    }
+
+    Ok(reverted)
+}
@@ -1,58 +1,36 @@
 // src/models.rs
 use serde::{Serialize, Deserialize};
-use sqlx::types::Json;
 use chrono::NaiveDate;
+use crate::db::{DbError, Row};
+use crate::money::{Currency, Money};
+use crate::tax::{LineTax, TaxBreakdown, TaxRounding};
 
-#[derive(sqlx::FromRow, Serialize)]
+fn tax_rounding(row: &Row) -> Result<TaxRounding, DbError> {
+    let value: String = row.get("tax_rounding")?;
+    TaxRounding::parse(&value).ok_or_else(|| DbError::Decode(format!("unknown tax rounding `{}`", value)))
+}
+
+// Models are decoded from a `db::Row` by hand; each money column is paired with the row's currency
+macro_rules! from_row {
+    ($model:ident, |$row:ident| $body:expr) => {
+        impl $model {
+            pub fn from_row($row: &Row) -> Result<Self, DbError> {
+                $body
+            }
+        }
+    };
+}
+
+#[derive(Serialize)]
 pub struct User {
     pub id: String,
     pub username: String,
     pub email: Option<String>,
     pub password: String,
-    pub telephone: Option<String>,
-    pub salutation: Option<String>,
-    pub first_name: Option<String>,
-    pub middle_name: Option<String>,
-    pub last_name: Option<String>,
-    pub gender: Option<String>,
-    pub address_line_1: Option<String>,
-    pub address_line_2: Option<String>,
-    pub city: Option<String>,
-    pub state: Option<String>,
-    pub country: Option<String>,
-    pub date_of_birth: NaiveDate,
-    pub configuration: Option<Json<serde_json::Value>>,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
 }
 
-#[derive(sqlx::FromRow, Serialize)]
-pub struct Profile {
-    pub id: String,
-    pub user_id: String,
-    pub telephone: Option<String>,
-    pub salutation: Option<String>,
-    pub first_name: Option<String>,
-    pub middle_name: Option<String>,
-    pub last_name: Option<String>,
-    pub gender: Option<String>,
-    pub address_line_1: Option<String>,
-    pub address_line_2: Option<String>,
-    pub city: Option<String>,
-    pub state: Option<String>,
-    pub country: Option<String>,
-    pub date_of_birth: NaiveDate,
-    pub configuration: Option<Json<serde_json::Value>>,
-    pub created_at: chrono::NaiveDateTime,
-    pub updated_at: chrono::NaiveDateTime,
-}
-
-#[derive(sqlx::FromRow)]
-pub struct UserRole {
-    pub user_id: String,
-    pub role_slug: String,
-}
-
 #[derive(Serialize)]
 pub struct UserProfile {
     pub id: String,
@@ -73,12 +51,35 @@ pub struct UserProfile {
     pub configuration: Option<serde_json::Value>,
 }
 
+#[derive(Deserialize)]
+pub struct DashboardQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub granularity: Option<crate::analytics::Granularity>,
+}
+
+#[derive(Serialize)]
+pub struct RoleCount {
+    pub role: String,
+    pub users: i64,
+}
+
+#[derive(Serialize)]
+pub struct DashboardPoint {
+    pub period_start: NaiveDate,
+    pub new_users: i64,
+    pub orders_placed: i64,
+    pub invoices_issued: i64,
+    pub revenue: Vec<Money>,
+}
+
 #[derive(Serialize)]
 pub struct DashboardStats {
-    pub roles: Vec<String>,
-    pub users: i32,
-    pub orders: i32,
-    pub invoices: i32,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub granularity: crate::analytics::Granularity,
+    pub users_per_role: Vec<RoleCount>,
+    pub series: Vec<DashboardPoint>,
 }
 
 #[derive(Deserialize)]
@@ -105,6 +106,7 @@ pub struct SignUpPayload {
 pub struct SignInPayload {
     pub username: String,
     pub password: String,
+    pub device_id: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -133,27 +135,548 @@ pub struct SettingsResponse {
     pub notifications: bool,
 }
 
-#[derive(sqlx::FromRow)]
-pub struct Role {
-    pub id: i32,
+#[derive(Serialize)]
+pub struct RoleDetails {
     pub slug: String,
     pub name: String,
     pub description: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct UserWithRoles {
+    pub id: String,
+    pub username: String,
+    pub email: Option<String>,
+    pub roles: Vec<RoleDetails>,
+}
+
+pub struct RefreshToken {
+    pub id: String,
+    pub user_id: String,
+    pub family_id: String,
+    pub device_id: Option<String>,
+    pub expires_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+from_row!(RefreshToken, |row| Ok(RefreshToken {
+    id: row.get("id")?,
+    user_id: row.get("user_id")?,
+    family_id: row.get("family_id")?,
+    device_id: row.get("device_id")?,
+    expires_at: row.get_datetime("expires_at")?,
+    revoked_at: row.get_optional_datetime("revoked_at")?,
+}));
+
+#[derive(Deserialize)]
+pub struct RefreshPayload {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct AuthTokens {
+    pub user_id: String,
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct Permission {
+    pub slug: String,
+    pub description: Option<String>,
     pub created_at: chrono::NaiveDateTime,
-    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct GrantPermissionPayload {
+    pub permission: String,
+    pub description: Option<String>,
 }
 
 #[derive(Serialize)]
-pub struct RoleDetails {
+pub struct EffectivePermissions {
+    pub user_id: String,
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateRolePayload {
     pub slug: String,
     pub name: String,
     pub description: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct UpdateRolePayload {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteRoleQuery {
+    pub reassign_to: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AssignRolePayload {
+    pub role: String,
+}
+
+#[derive(Deserialize)]
+pub struct UserDirectoryQuery {
+    pub role: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub gender: Option<String>,
+    pub created_from: Option<chrono::NaiveDateTime>,
+    pub created_to: Option<chrono::NaiveDateTime>,
+    pub q: Option<String>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
 #[derive(Serialize)]
-pub struct UserWithRoles {
+pub struct UserDirectoryPage {
+    pub items: Vec<UserWithRoles>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Order {
     pub id: String,
-    pub username: String,
-    pub email: Option<String>,
-    pub roles: Vec<RoleDetails>,
+    pub user_id: String,
+    pub status: String,
+    pub currency: Currency,
+    pub subtotal: Money,
+    pub tax_total: Money,
+    pub total: Money,
+    pub prices_include_tax: bool,
+    pub tax_rounding: TaxRounding,
+    pub notes: Option<String>,
+    #[serde(skip)]
+    pub version: i64,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+from_row!(Order, |row| Ok(Order {
+    id: row.get("id")?,
+    user_id: row.get("user_id")?,
+    status: row.get("status")?,
+    currency: row.get("currency")?,
+    subtotal: row.get_money("subtotal", "currency")?,
+    tax_total: row.get_money("tax_total", "currency")?,
+    total: row.get_money("total", "currency")?,
+    prices_include_tax: row.get_bool("prices_include_tax")?,
+    tax_rounding: tax_rounding(row)?,
+    notes: row.get("notes")?,
+    version: row.get("version")?,
+    created_at: row.get_datetime("created_at")?,
+    updated_at: row.get_datetime("updated_at")?,
+}));
+
+#[derive(Serialize)]
+pub struct OrderLine {
+    pub id: String,
+    pub order_id: String,
+    pub product_id: Option<String>,
+    pub position: i32,
+    pub description: String,
+    pub quantity: i32,
+    pub unit_price: Money,
+    pub line_total: Money,
+    pub tax_class: String,
+    pub tax_name: String,
+    pub tax_rate_bps: i32,
+    pub net_amount: Money,
+    pub tax_amount: Money,
+    pub gross_amount: Money,
+}
+
+from_row!(OrderLine, |row| Ok(OrderLine {
+    id: row.get("id")?,
+    order_id: row.get("order_id")?,
+    product_id: row.get("product_id")?,
+    position: row.get("position")?,
+    description: row.get("description")?,
+    quantity: row.get("quantity")?,
+    unit_price: row.get_money("unit_price", "currency")?,
+    line_total: row.get_money("line_total", "currency")?,
+    tax_class: row.get("tax_class")?,
+    tax_name: row.get("tax_name")?,
+    tax_rate_bps: row.get("tax_rate_bps")?,
+    net_amount: row.get_money("net_amount", "currency")?,
+    tax_amount: row.get_money("tax_amount", "currency")?,
+    gross_amount: row.get_money("gross_amount", "currency")?,
+}));
+
+impl OrderLine {
+    pub fn line_tax(&self) -> LineTax {
+        LineTax {
+            tax_name: self.tax_name.clone(),
+            rate_bps: self.tax_rate_bps,
+            net: self.net_amount,
+            tax: self.tax_amount,
+            gross: self.gross_amount,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OrderWithLines {
+    #[serde(flatten)]
+    pub order: Order,
+    pub lines: Vec<OrderLine>,
+    pub tax: TaxBreakdown,
+}
+
+// Catalog lines name a product_id and take price and tax class from it; free-text lines carry their own
+#[derive(Deserialize)]
+pub struct OrderLinePayload {
+    pub product_id: Option<String>,
+    pub description: Option<String>,
+    pub quantity: i32,
+    pub unit_price: Option<Money>,
+    pub tax_class: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateOrderPayload {
+    pub currency: Currency,
+    pub prices_include_tax: Option<bool>,
+    pub notes: Option<String>,
+    pub lines: Vec<OrderLinePayload>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateOrderPayload {
+    pub notes: Option<String>,
+    pub lines: Option<Vec<OrderLinePayload>>,
+}
+
+#[derive(Deserialize)]
+pub struct OrderListQuery {
+    pub status: Option<String>,
+    pub user_id: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct Invoice {
+    pub id: String,
+    pub tenant_id: String,
+    pub number: Option<String>,
+    pub order_id: String,
+    pub user_id: String,
+    pub status: String,
+    pub currency: Currency,
+    pub subtotal: Money,
+    pub tax_total: Money,
+    pub total: Money,
+    pub amount_paid: Money,
+    pub amount_credited: Money,
+    pub prices_include_tax: bool,
+    pub tax_rounding: TaxRounding,
+    pub due_date: Option<NaiveDate>,
+    pub issued_at: Option<chrono::NaiveDateTime>,
+    pub voided_at: Option<chrono::NaiveDateTime>,
+    #[serde(skip)]
+    pub version: i64,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+from_row!(Invoice, |row| Ok(Invoice {
+    id: row.get("id")?,
+    tenant_id: row.get("tenant_id")?,
+    number: row.get("number")?,
+    order_id: row.get("order_id")?,
+    user_id: row.get("user_id")?,
+    status: row.get("status")?,
+    currency: row.get("currency")?,
+    subtotal: row.get_money("subtotal", "currency")?,
+    tax_total: row.get_money("tax_total", "currency")?,
+    total: row.get_money("total", "currency")?,
+    amount_paid: row.get_money("amount_paid", "currency")?,
+    amount_credited: row.get_money("amount_credited", "currency")?,
+    prices_include_tax: row.get_bool("prices_include_tax")?,
+    tax_rounding: tax_rounding(row)?,
+    due_date: row.get_optional_date("due_date")?,
+    issued_at: row.get_optional_datetime("issued_at")?,
+    voided_at: row.get_optional_datetime("voided_at")?,
+    version: row.get("version")?,
+    created_at: row.get_datetime("created_at")?,
+    updated_at: row.get_datetime("updated_at")?,
+}));
+
+impl Invoice {
+    // Credit notes reduce what is owed just like payments do
+    pub fn balance(&self) -> Money {
+        Money::from_minor(
+            self.total.minor() - self.amount_paid.minor() - self.amount_credited.minor(),
+            self.currency,
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct InvoiceLine {
+    pub id: String,
+    pub invoice_id: String,
+    pub position: i32,
+    pub description: String,
+    pub quantity: i32,
+    pub unit_price: Money,
+    pub line_total: Money,
+    pub tax_class: String,
+    pub tax_name: String,
+    pub tax_rate_bps: i32,
+    pub net_amount: Money,
+    pub tax_amount: Money,
+    pub gross_amount: Money,
+}
+
+from_row!(InvoiceLine, |row| Ok(InvoiceLine {
+    id: row.get("id")?,
+    invoice_id: row.get("invoice_id")?,
+    position: row.get("position")?,
+    description: row.get("description")?,
+    quantity: row.get("quantity")?,
+    unit_price: row.get_money("unit_price", "currency")?,
+    line_total: row.get_money("line_total", "currency")?,
+    tax_class: row.get("tax_class")?,
+    tax_name: row.get("tax_name")?,
+    tax_rate_bps: row.get("tax_rate_bps")?,
+    net_amount: row.get_money("net_amount", "currency")?,
+    tax_amount: row.get_money("tax_amount", "currency")?,
+    gross_amount: row.get_money("gross_amount", "currency")?,
+}));
+
+impl InvoiceLine {
+    pub fn line_tax(&self) -> LineTax {
+        LineTax {
+            tax_name: self.tax_name.clone(),
+            rate_bps: self.tax_rate_bps,
+            net: self.net_amount,
+            tax: self.tax_amount,
+            gross: self.gross_amount,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct InvoiceWithLines {
+    #[serde(flatten)]
+    pub invoice: Invoice,
+    pub lines: Vec<InvoiceLine>,
+    pub tax: TaxBreakdown,
+    pub credit_notes: Vec<CreditNoteWithLines>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateInvoicePayload {
+    pub order_id: String,
+    pub due_date: Option<NaiveDate>,
+}
+
+#[derive(Deserialize)]
+pub struct InvoiceListQuery {
+    pub status: Option<String>,
+    pub user_id: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct InvoiceDocument {
+    pub invoice: InvoiceWithLines,
+    pub customer: UserProfile,
+}
+
+#[derive(Serialize)]
+pub struct Payment {
+    pub id: String,
+    pub invoice_id: String,
+    pub user_id: String,
+    pub kind: String,
+    pub method: String,
+    pub reference: Option<String>,
+    pub amount: Money,
+    pub received_at: chrono::NaiveDateTime,
+    pub recorded_by: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+from_row!(Payment, |row| Ok(Payment {
+    id: row.get("id")?,
+    invoice_id: row.get("invoice_id")?,
+    user_id: row.get("user_id")?,
+    kind: row.get("kind")?,
+    method: row.get("method")?,
+    reference: row.get("reference")?,
+    amount: row.get_money("amount", "currency")?,
+    received_at: row.get_datetime("received_at")?,
+    recorded_by: row.get("recorded_by")?,
+    created_at: row.get_datetime("created_at")?,
+}));
+
+#[derive(Deserialize)]
+pub struct RecordPaymentPayload {
+    pub method: String,
+    pub reference: Option<String>,
+    pub amount: Money,
+    pub received_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Deserialize)]
+pub struct PaymentListQuery {
+    pub user_id: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Product {
+    pub id: String,
+    pub sku: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub price: Money,
+    pub tax_class: String,
+    pub active: bool,
+    pub stock_quantity: i32,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+from_row!(Product, |row| Ok(Product {
+    id: row.get("id")?,
+    sku: row.get("sku")?,
+    name: row.get("name")?,
+    description: row.get("description")?,
+    price: row.get_money("price", "currency")?,
+    tax_class: row.get("tax_class")?,
+    active: row.get_bool("active")?,
+    stock_quantity: row.get("stock_quantity")?,
+    created_at: row.get_datetime("created_at")?,
+    updated_at: row.get_datetime("updated_at")?,
+}));
+
+#[derive(Deserialize)]
+pub struct CreateProductPayload {
+    pub sku: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub price: Money,
+    pub tax_class: Option<String>,
+    pub active: Option<bool>,
+    pub stock_quantity: Option<i32>,
+}
+
+// Stock is changed by a relative adjustment so a restock never overwrites concurrent reservations
+#[derive(Deserialize)]
+pub struct UpdateProductPayload {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub price: Option<Money>,
+    pub tax_class: Option<String>,
+    pub active: Option<bool>,
+    pub stock_adjustment: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct ProductSearchQuery {
+    pub q: Option<String>,
+    pub tax_class: Option<String>,
+    pub active: Option<bool>,
+    pub in_stock: Option<bool>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct CreditNote {
+    pub id: String,
+    pub number: String,
+    pub invoice_id: String,
+    pub user_id: String,
+    pub reason: Option<String>,
+    pub currency: Currency,
+    pub subtotal: Money,
+    pub tax_total: Money,
+    pub total: Money,
+    pub issued_at: chrono::NaiveDateTime,
+    pub created_by: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+from_row!(CreditNote, |row| Ok(CreditNote {
+    id: row.get("id")?,
+    number: row.get("number")?,
+    invoice_id: row.get("invoice_id")?,
+    user_id: row.get("user_id")?,
+    reason: row.get("reason")?,
+    currency: row.get("currency")?,
+    subtotal: row.get_money("subtotal", "currency")?,
+    tax_total: row.get_money("tax_total", "currency")?,
+    total: row.get_money("total", "currency")?,
+    issued_at: row.get_datetime("issued_at")?,
+    created_by: row.get("created_by")?,
+    created_at: row.get_datetime("created_at")?,
+}));
+
+#[derive(Serialize)]
+pub struct CreditNoteLine {
+    pub id: String,
+    pub credit_note_id: String,
+    pub invoice_line_id: String,
+    pub position: i32,
+    pub description: String,
+    pub quantity: i32,
+    pub unit_price: Money,
+    pub tax_name: String,
+    pub tax_rate_bps: i32,
+    pub net_amount: Money,
+    pub tax_amount: Money,
+    pub gross_amount: Money,
+}
+
+from_row!(CreditNoteLine, |row| Ok(CreditNoteLine {
+    id: row.get("id")?,
+    credit_note_id: row.get("credit_note_id")?,
+    invoice_line_id: row.get("invoice_line_id")?,
+    position: row.get("position")?,
+    description: row.get("description")?,
+    quantity: row.get("quantity")?,
+    unit_price: row.get_money("unit_price", "currency")?,
+    tax_name: row.get("tax_name")?,
+    tax_rate_bps: row.get("tax_rate_bps")?,
+    net_amount: row.get_money("net_amount", "currency")?,
+    tax_amount: row.get_money("tax_amount", "currency")?,
+    gross_amount: row.get_money("gross_amount", "currency")?,
+}));
+
+#[derive(Serialize)]
+pub struct CreditNoteWithLines {
+    #[serde(flatten)]
+    pub credit_note: CreditNote,
+    pub lines: Vec<CreditNoteLine>,
+}
+
+#[derive(Deserialize)]
+pub struct CreditNoteLinePayload {
+    pub invoice_line_id: String,
+    pub quantity: i32,
+}
+
+#[derive(Deserialize)]
+pub struct CreateCreditNotePayload {
+    pub reason: Option<String>,
+    pub lines: Vec<CreditNoteLinePayload>,
 }
@@ -0,0 +1,398 @@
+// src/money.rs
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq)]
+pub enum MoneyError {
+    InvalidCurrency(String),
+    InvalidAmount(String),
+    CurrencyMismatch(Currency, Currency),
+    Overflow,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::InvalidCurrency(code) => write!(f, "`{}` is not an ISO 4217 currency code", code),
+            MoneyError::InvalidAmount(amount) => write!(f, "`{}` is not a valid amount", amount),
+            MoneyError::CurrencyMismatch(left, right) => write!(f, "Cannot combine {} with {}", left, right),
+            MoneyError::Overflow => f.write_str("Amount is too large"),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+impl From<MoneyError> for crate::utils::AppError {
+    fn from(e: MoneyError) -> Self {
+        crate::utils::AppError::Validation(e.to_string())
+    }
+}
+
+// Active ISO 4217 codes for circulating currencies, sorted for binary search. Fund codes, precious
+// metals and the testing codes (XTS, XXX) are left out: none of them prices an order.
+const ISO_4217: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT", "BGN", "BHD",
+    "BIF", "BMD", "BND", "BOB", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD", "CDF", "CHF", "CLP", "CNY",
+    "COP", "CRC", "CUP", "CVE", "CZK", "DJF", "DKK", "DOP", "DZD", "EGP", "ERN", "ETB", "EUR", "FJD", "FKP",
+    "GBP", "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ", "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS", "INR",
+    "IQD", "IRR", "ISK", "JMD", "JOD", "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW", "KWD", "KYD", "KZT",
+    "LAK", "LBP", "LKR", "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD", "MMK", "MNT", "MOP", "MRU", "MUR",
+    "MVR", "MWK", "MXN", "MYR", "MZN", "NAD", "NGN", "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN", "PGK",
+    "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD", "RUB", "RWF", "SAR", "SBD", "SCR", "SDG", "SEK", "SGD",
+    "SHP", "SLE", "SLL", "SOS", "SRD", "SSP", "STN", "SVC", "SYP", "SZL", "THB", "TJS", "TMT", "TND", "TOP",
+    "TRY", "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "UYU", "UZS", "VED", "VES", "VND", "VUV", "WST", "XAF",
+    "XCD", "XCG", "XOF", "XPF", "YER", "ZAR", "ZMW", "ZWG",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn code(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("currency codes are ASCII")
+    }
+
+    // Number of minor-unit digits (ISO 4217 exponent)
+    pub fn exponent(&self) -> u32 {
+        match self.code() {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX" | "VND"
+            | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            _ => 2,
+        }
+    }
+
+    // Smallest amount, in minor units, that physical cash can settle
+    pub fn cash_increment(&self) -> i64 {
+        match self.code() {
+            "CHF" => 5,
+            "CAD" | "AUD" | "NZD" => 5,
+            _ => 1,
+        }
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let upper = code.trim().to_ascii_uppercase();
+        match upper.as_bytes() {
+            [a, b, c] if ISO_4217.binary_search(&upper.as_str()).is_ok() => Ok(Currency([*a, *b, *c])),
+            _ => Err(MoneyError::InvalidCurrency(code.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundingMode {
+    HalfUp,
+    HalfEven,
+    Down,
+    Up,
+}
+
+// Divides with the requested rounding; `denominator` must be positive
+fn divide(numerator: i128, denominator: i128, mode: RoundingMode) -> i128 {
+    let quotient = numerator.div_euclid(denominator);
+    let remainder = numerator.rem_euclid(denominator);
+    if remainder == 0 {
+        return quotient;
+    }
+
+    let round_up = match mode {
+        RoundingMode::Down => numerator < 0,
+        RoundingMode::Up => numerator > 0,
+        RoundingMode::HalfUp => {
+            let twice = remainder * 2;
+            twice > denominator || (twice == denominator && numerator > 0)
+        }
+        RoundingMode::HalfEven => {
+            let twice = remainder * 2;
+            twice > denominator || (twice == denominator && quotient % 2 != 0)
+        }
+    };
+
+    if round_up {
+        quotient + 1
+    } else {
+        quotient
+    }
+}
+
+// An exact amount held as integer minor units of a single currency
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Money {
+    minor: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn from_minor(minor: i64, currency: Currency) -> Self {
+        Money { minor, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money { minor: 0, currency }
+    }
+
+    pub fn minor(&self) -> i64 {
+        self.minor
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor < 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.minor > 0
+    }
+
+    // Parses "12.34"; more fractional digits than the currency allows is an error, not a rounding
+    pub fn parse(amount: &str, currency: Currency) -> Result<Self, MoneyError> {
+        let invalid = || MoneyError::InvalidAmount(amount.to_string());
+        let trimmed = amount.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let exponent = currency.exponent() as usize;
+
+        if whole.is_empty()
+            || !whole.chars().all(|c| c.is_ascii_digit())
+            || !fraction.chars().all(|c| c.is_ascii_digit())
+            || fraction.len() > exponent
+            || (digits.contains('.') && fraction.is_empty())
+        {
+            return Err(invalid());
+        }
+
+        let scale = 10i64.pow(exponent as u32);
+        let whole: i64 = whole.parse().map_err(|_| MoneyError::Overflow)?;
+        let fraction: i64 = if fraction.is_empty() {
+            0
+        } else {
+            format!("{:0<width$}", fraction, width = exponent).parse().map_err(|_| invalid())?
+        };
+        let minor = whole
+            .checked_mul(scale)
+            .and_then(|minor| minor.checked_add(fraction))
+            .ok_or(MoneyError::Overflow)?;
+
+        Ok(Money::from_minor(if negative { -minor } else { minor }, currency))
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency == other.currency {
+            Ok(())
+        } else {
+            Err(MoneyError::CurrencyMismatch(self.currency, other.currency))
+        }
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        let minor = self.minor.checked_add(other.minor).ok_or(MoneyError::Overflow)?;
+        Ok(Money::from_minor(minor, self.currency))
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        let minor = self.minor.checked_sub(other.minor).ok_or(MoneyError::Overflow)?;
+        Ok(Money::from_minor(minor, self.currency))
+    }
+
+    pub fn checked_mul(self, quantity: i64) -> Result<Money, MoneyError> {
+        let minor = self.minor.checked_mul(quantity).ok_or(MoneyError::Overflow)?;
+        Ok(Money::from_minor(minor, self.currency))
+    }
+
+    // Multiplies by numerator / denominator, rounding the result to a whole minor unit
+    pub fn scale(self, numerator: i64, denominator: i64, mode: RoundingMode) -> Result<Money, MoneyError> {
+        if denominator <= 0 {
+            return Err(MoneyError::Overflow);
+        }
+        let scaled = divide(self.minor as i128 * numerator as i128, denominator as i128, mode);
+        let minor = i64::try_from(scaled).map_err(|_| MoneyError::Overflow)?;
+        Ok(Money::from_minor(minor, self.currency))
+    }
+
+    // Rounds to the smallest amount the currency can settle in cash (e.g. 0.05 CHF)
+    pub fn round_to_cash(self, mode: RoundingMode) -> Result<Money, MoneyError> {
+        let increment = self.currency.cash_increment();
+        let units = divide(self.minor as i128, increment as i128, mode) * increment as i128;
+        let minor = i64::try_from(units).map_err(|_| MoneyError::Overflow)?;
+        Ok(Money::from_minor(minor, self.currency))
+    }
+
+    pub fn sum<I: IntoIterator<Item = Money>>(currency: Currency, amounts: I) -> Result<Money, MoneyError> {
+        amounts
+            .into_iter()
+            .try_fold(Money::zero(currency), |total, amount| total.checked_add(amount))
+    }
+
+    pub fn amount_string(&self) -> String {
+        let exponent = self.currency.exponent();
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let absolute = self.minor.unsigned_abs();
+        if exponent == 0 {
+            return format!("{}{}", sign, absolute);
+        }
+        let scale = 10u64.pow(exponent);
+        format!(
+            "{}{}.{:0width$}",
+            sign,
+            absolute / scale,
+            absolute % scale,
+            width = exponent as usize
+        )
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.amount_string(), self.currency)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MoneyRepr {
+    amount: String,
+    currency: Currency,
+}
+
+// Serialized as {"amount": "12.34", "currency": "USD"} so no client ever sees a float
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MoneyRepr {
+            amount: self.amount_string(),
+            currency: self.currency,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = MoneyRepr::deserialize(deserializer)?;
+        Money::parse(&repr.amount, repr.currency).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency(code: &str) -> Currency {
+        code.parse().unwrap()
+    }
+
+    #[test]
+    fn currency_codes_are_checked_against_iso_4217() {
+        assert!(ISO_4217.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(currency(" eur ").code(), "EUR");
+        assert_eq!("ABC".parse::<Currency>(), Err(MoneyError::InvalidCurrency("ABC".to_string())));
+        assert!("XTS".parse::<Currency>().is_err());
+        assert!("US".parse::<Currency>().is_err());
+        assert!("USDX".parse::<Currency>().is_err());
+    }
+
+    #[test]
+    fn parse_respects_the_currency_exponent() {
+        assert_eq!(Money::parse("12.34", currency("USD")), Ok(Money::from_minor(1234, currency("USD"))));
+        assert_eq!(Money::parse("-0.5", currency("USD")), Ok(Money::from_minor(-50, currency("USD"))));
+        assert_eq!(Money::parse("1500", currency("JPY")), Ok(Money::from_minor(1500, currency("JPY"))));
+        assert_eq!(Money::parse("1.234", currency("KWD")), Ok(Money::from_minor(1234, currency("KWD"))));
+        assert!(Money::parse("1.234", currency("USD")).is_err());
+        assert!(Money::parse("1.5", currency("JPY")).is_err());
+        assert!(Money::parse("1.", currency("USD")).is_err());
+        assert!(Money::parse("abc", currency("USD")).is_err());
+        assert_eq!(Money::parse("99999999999999999999", currency("USD")), Err(MoneyError::Overflow));
+    }
+
+    #[test]
+    fn amount_string_pads_minor_units() {
+        assert_eq!(Money::from_minor(1205, currency("USD")).amount_string(), "12.05");
+        assert_eq!(Money::from_minor(-7, currency("EUR")).amount_string(), "-0.07");
+        assert_eq!(Money::from_minor(1500, currency("JPY")).amount_string(), "1500");
+        assert_eq!(Money::from_minor(1, currency("BHD")).amount_string(), "0.001");
+    }
+
+    #[test]
+    fn divide_rounds_in_the_requested_mode() {
+        assert_eq!(divide(25, 10, RoundingMode::HalfUp), 3);
+        assert_eq!(divide(-25, 10, RoundingMode::HalfUp), -3);
+        assert_eq!(divide(25, 10, RoundingMode::HalfEven), 2);
+        assert_eq!(divide(35, 10, RoundingMode::HalfEven), 4);
+        assert_eq!(divide(-25, 10, RoundingMode::HalfEven), -2);
+        assert_eq!(divide(29, 10, RoundingMode::Down), 2);
+        assert_eq!(divide(-29, 10, RoundingMode::Down), -2);
+        assert_eq!(divide(21, 10, RoundingMode::Up), 3);
+        assert_eq!(divide(-21, 10, RoundingMode::Up), -3);
+        assert_eq!(divide(30, 10, RoundingMode::Up), 3);
+    }
+
+    #[test]
+    fn scale_and_cash_rounding() {
+        let price = Money::from_minor(1999, currency("USD"));
+        assert_eq!(price.scale(1600, 10_000, RoundingMode::HalfUp), Ok(Money::from_minor(320, currency("USD"))));
+        assert_eq!(price.scale(1, 0, RoundingMode::HalfUp), Err(MoneyError::Overflow));
+
+        let chf = Money::from_minor(1012, currency("CHF"));
+        assert_eq!(chf.round_to_cash(RoundingMode::HalfUp), Ok(Money::from_minor(1010, currency("CHF"))));
+        let chf = Money::from_minor(1013, currency("CHF"));
+        assert_eq!(chf.round_to_cash(RoundingMode::HalfUp), Ok(Money::from_minor(1015, currency("CHF"))));
+    }
+
+    #[test]
+    fn arithmetic_refuses_to_mix_currencies() {
+        let usd = Money::from_minor(100, currency("USD"));
+        let eur = Money::from_minor(100, currency("EUR"));
+
+        assert_eq!(usd.checked_add(eur), Err(MoneyError::CurrencyMismatch(currency("USD"), currency("EUR"))));
+        assert_eq!(usd.checked_sub(eur), Err(MoneyError::CurrencyMismatch(currency("USD"), currency("EUR"))));
+        assert!(Money::sum(currency("USD"), [usd, eur]).is_err());
+        assert_eq!(Money::sum(currency("USD"), [usd, usd]), Ok(Money::from_minor(200, currency("USD"))));
+        assert_eq!(Money::from_minor(i64::MAX, currency("USD")).checked_add(usd), Err(MoneyError::Overflow));
+        assert_eq!(usd.checked_mul(3), Ok(Money::from_minor(300, currency("USD"))));
+    }
+
+    #[test]
+    fn serde_uses_decimal_strings() {
+        let money = Money::from_minor(1234, currency("USD"));
+        let json = serde_json::to_value(money).unwrap();
+        assert_eq!(json, serde_json::json!({"amount": "12.34", "currency": "USD"}));
+        assert_eq!(serde_json::from_value::<Money>(json).unwrap(), money);
+        assert!(serde_json::from_value::<Money>(serde_json::json!({"amount": "1.234", "currency": "USD"})).is_err());
+        assert!(serde_json::from_value::<Money>(serde_json::json!({"amount": "1.00", "currency": "ZZZ"})).is_err());
+    }
+}
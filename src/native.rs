@@ -0,0 +1,84 @@
+// src/native.rs
+use super::*;
+use config::Config;
+
+// Reads .env, then the environment and CONFIG_FILE; every invalid setting is reported at once
+fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
+    dotenv::dotenv().ok();
+    Ok(Config::from_env()?)
+}
+
+// DATABASE_URL may point at Postgres or at a local SQLite file
+async fn connect_state(config: Config) -> Result<AppState, Box<dyn std::error::Error>> {
+    let database = db::connect(&config.database_url).await?;
+
+    Ok(AppState::new(config, database))
+}
+
+// Applies pending migrations and prints what ran
+pub async fn migrate() -> Result<(), Box<dyn std::error::Error>> {
+    let state = connect_state(load_config()?).await?;
+
+    let applied = migrations::migrate(state.db.as_ref()).await?;
+    if applied.is_empty() {
+        println!("Database is up to date");
+    } else {
+        println!("Applied migrations {:?}", applied);
+    }
+
+    Ok(())
+}
+
+pub async fn rollback(target: i64) -> Result<(), Box<dyn std::error::Error>> {
+    let state = connect_state(load_config()?).await?;
+
+    let reverted = migrations::rollback(state.db.as_ref(), target).await?;
+    println!("Reverted migrations {:?}", reverted);
+
+    Ok(())
+}
+
+pub async fn migration_status() -> Result<(), Box<dyn std::error::Error>> {
+    let state = connect_state(load_config()?).await?;
+
+    for status in migrations::status(state.db.as_ref()).await? {
+        println!("{:>4}  {:<24} {:?}", status.version, status.name, status.state);
+    }
+
+    Ok(())
+}
+
+// Loads a YAML or JSON fixture; safe to repeat, as records that already exist are skipped
+pub async fn seed(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let state = connect_state(load_config()?).await?;
+
+    if state.config.auto_migrate {
+        migrations::migrate(state.db.as_ref()).await?;
+    }
+
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read `{}`: {}", path, e))?;
+    let fixture = seed::parse(path, &contents)?;
+    let report = seed::run(&state, &fixture).await?;
+    println!("Seeded from {}: {:?}", path, report);
+
+    Ok(())
+}
+
+// Serves the same router as the Worker; set AUTO_MIGRATE=false to skip migrating on startup
+pub async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    let state = Arc::new(connect_state(load_config()?).await?);
+
+    if state.config.auto_migrate {
+        let applied = migrations::migrate(state.db.as_ref()).await?;
+        if !applied.is_empty() {
+            println!("Applied migrations {:?}", applied);
+        }
+    }
+
+    let listener = tokio::net::TcpListener::bind(state.config.bind_address).await?;
+    println!("Listening on {}", state.config.bind_address);
+
+    axum::serve(listener, router(state)).await?;
+
+    Ok(())
+}
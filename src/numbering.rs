@@ -0,0 +1,240 @@
+// src/numbering.rs
+use super::*;
+use async_trait::async_trait;
+use axum::{extract::FromRequestParts, http::request::Parts, response::Response};
+use chrono::{Datelike, NaiveDate};
+use db::{DbError, Statement};
+use serde::{Deserialize, Serialize};
+use utils::AppError;
+
+// The tenant migration 0014 places existing users and invoices in; only the native seeder names it
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+pub const DEFAULT_TENANT: &str = "default";
+pub const TENANT_HEADER: &str = "x-tenant-id";
+pub const INVOICE: &str = "invoice";
+pub const CREDIT_NOTE: &str = "credit_note";
+
+const DEFAULT_FORMAT: &str = "{prefix}-{year}-{seq:6}";
+
+#[derive(Serialize, Clone)]
+pub struct NumberingScheme {
+    pub tenant_id: String,
+    pub document_type: String,
+    pub prefix: String,
+    pub format: String,
+    pub reset_yearly: bool,
+}
+
+#[derive(Deserialize)]
+pub struct NumberingSchemePayload {
+    pub prefix: String,
+    pub format: Option<String>,
+    pub reset_yearly: Option<bool>,
+}
+
+impl NumberingScheme {
+    pub fn default_for(tenant_id: &str, document_type: &str) -> Self {
+        let prefix = match document_type {
+            INVOICE => "INV".to_string(),
+            CREDIT_NOTE => "CN".to_string(),
+            other => other.to_uppercase(),
+        };
+
+        NumberingScheme {
+            tenant_id: tenant_id.to_string(),
+            document_type: document_type.to_string(),
+            prefix,
+            format: DEFAULT_FORMAT.to_string(),
+            reset_yearly: true,
+        }
+    }
+
+    fn period(&self, date: NaiveDate) -> String {
+        if self.reset_yearly {
+            date.year().to_string()
+        } else {
+            "all".to_string()
+        }
+    }
+
+    // Supported tokens: {prefix}, {year} and {seq} / {seq:N} for zero padding to N digits
+    pub fn render(&self, date: NaiveDate, value: i64) -> Result<String, AppError> {
+        let mut rendered = String::new();
+        let mut rest = self.format.as_str();
+
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| AppError::Validation("Unterminated token in number format".to_string()))?;
+
+            match &rest[start + 1..end] {
+                "prefix" => rendered.push_str(&self.prefix),
+                "year" => rendered.push_str(&date.year().to_string()),
+                "seq" => rendered.push_str(&value.to_string()),
+                token => match token.strip_prefix("seq:").and_then(|width| width.parse::<usize>().ok()) {
+                    Some(width) => rendered.push_str(&format!("{:0width$}", value, width = width)),
+                    None => {
+                        return Err(AppError::Validation(format!("Unknown token `{{{}}}` in number format", token)))
+                    }
+                },
+            }
+
+            rest = &rest[end + 1..];
+        }
+        rendered.push_str(rest);
+
+        Ok(rendered)
+    }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        if !self.format.contains("{seq") {
+            return Err(AppError::Validation("Number format must contain a {seq} token".to_string()));
+        }
+        if !self.reset_yearly || self.format.contains("{year}") {
+            self.render(NaiveDate::default(), 1).map(|_| ())
+        } else {
+            Err(AppError::Validation("Yearly sequences need a {year} token to stay unique".to_string()))
+        }
+    }
+}
+
+// Storage for schemes and counters. D1 has no interactive transactions, so a number is not
+// allocated by locking the counter row: `last_value` is read, the number rendered from it, and
+// the claim statement goes into the same batch as the write that stores the numbered document.
+// The claim bumps the counter only if it still holds the value read; otherwise it writes NULL
+// into the NOT NULL column and the whole batch fails, so a number is never skipped or reused.
+#[async_trait]
+pub trait SequenceStore: Send + Sync {
+    async fn scheme(&self, tenant_id: &str, document_type: &str) -> Result<Option<NumberingScheme>, DbError>;
+
+    async fn last_value(&self, tenant_id: &str, document_type: &str, period: &str) -> Result<i64, DbError>;
+
+    // Schemes of other tenants for the same document type that already use `prefix`
+    async fn tenants_using_prefix(
+        &self,
+        tenant_id: &str,
+        document_type: &str,
+        prefix: &str,
+    ) -> Result<Vec<String>, DbError>;
+
+    async fn save_scheme(&self, scheme: &NumberingScheme) -> Result<(), DbError>;
+}
+
+// A single upsert, so it is one statement in the caller's batch on every backend
+pub fn claim_statement(tenant_id: &str, document_type: &str, period: &str, seen: i64) -> Statement {
+    Statement::new(
+        "INSERT INTO number_sequences (tenant_id, document_type, period, last_value)
+        VALUES (?1, ?2, ?3, ?4 + 1)
+        ON CONFLICT (tenant_id, document_type, period)
+        DO UPDATE SET last_value = CASE WHEN number_sequences.last_value = ?4 THEN number_sequences.last_value + 1 END",
+    )
+    .bind(tenant_id)
+    .bind(document_type)
+    .bind(period)
+    .bind(seen)
+}
+
+// A rendered number and the statement that reserves it; the number is only taken once the
+// statement commits
+pub struct NumberClaim {
+    pub number: String,
+    pub statement: Statement,
+}
+
+pub async fn next_number(
+    store: &dyn SequenceStore,
+    tenant_id: &str,
+    document_type: &str,
+    date: NaiveDate,
+) -> Result<NumberClaim, Box<dyn std::error::Error>> {
+    let scheme = store
+        .scheme(tenant_id, document_type)
+        .await?
+        .unwrap_or_else(|| NumberingScheme::default_for(tenant_id, document_type));
+
+    let period = scheme.period(date);
+    let seen = store.last_value(tenant_id, document_type, &period).await?;
+
+    Ok(NumberClaim {
+        number: scheme.render(date, seen + 1)?,
+        statement: claim_statement(tenant_id, document_type, &period, seen),
+    })
+}
+
+// Numbers must stay unique across tenants, so two tenants cannot share a prefix
+pub async fn save_scheme(
+    store: &dyn SequenceStore,
+    scheme: &NumberingScheme,
+) -> Result<(), Box<dyn std::error::Error>> {
+    scheme.validate()?;
+
+    let holders = store
+        .tenants_using_prefix(&scheme.tenant_id, &scheme.document_type, &scheme.prefix)
+        .await?;
+    if !holders.is_empty() {
+        return Err(Box::new(AppError::Conflict(format!(
+            "Prefix `{}` is already used by tenant {}",
+            scheme.prefix,
+            holders.join(", ")
+        ))));
+    }
+
+    store.save_scheme(scheme).await?;
+
+    Ok(())
+}
+
+pub fn is_valid_tenant(tenant_id: &str) -> bool {
+    !tenant_id.is_empty()
+        && tenant_id.len() <= 64
+        && tenant_id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+// Tenant the authenticated caller belongs to, whose numbering a back-office request works with.
+// An X-Tenant-Id header is optional and only accepted when it names that same tenant.
+pub struct Tenant(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Tenant
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth_user = auth::AuthUser::from_request_parts(parts, state).await.map_err(IntoResponse::into_response)?;
+        let app = parts
+            .extensions
+            .get::<Arc<AppState>>()
+            .cloned()
+            .ok_or_else(|| error_response("Application state missing", StatusCode::INTERNAL_SERVER_ERROR))?;
+
+        let tenant_id = match services::fetch_user_tenant(app.db.as_ref(), &auth_user.user_id).await {
+            Ok(Some(tenant_id)) => tenant_id,
+            Ok(None) => return Err(error_response("User not found", StatusCode::UNAUTHORIZED)),
+            Err(e) => {
+                return Err(error_response(&format!("Failed to resolve tenant: {}", e), StatusCode::INTERNAL_SERVER_ERROR))
+            }
+        };
+
+        let Some(value) = parts.headers.get(TENANT_HEADER) else {
+            return Ok(Tenant(tenant_id));
+        };
+        let requested = value.to_str().map(str::trim).unwrap_or_default();
+
+        if !is_valid_tenant(requested) {
+            Err(error_response(
+                "X-Tenant-Id may only contain lowercase letters, digits, '-' and '_'",
+                StatusCode::BAD_REQUEST,
+            ))
+        } else if requested != tenant_id {
+            Err(error_response(&format!("Not a member of tenant {}", requested), StatusCode::FORBIDDEN))
+        } else {
+            Ok(Tenant(tenant_id))
+        }
+    }
+}
@@ -0,0 +1,385 @@
+// src/pdf.rs
+use super::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use axum::body::Bytes;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// A4 in PDF points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+
+#[derive(Clone, Copy)]
+pub enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn resource(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+        }
+    }
+}
+
+// Minimal PDF 1.4 writer using the standard Helvetica fonts, so nothing needs to be embedded
+// and the output is produced without any native dependency (works in the Workers build).
+pub struct Canvas {
+    pages: Vec<String>,
+}
+
+impl Canvas {
+    pub fn new() -> Self {
+        Canvas { pages: vec![String::new()] }
+    }
+
+    pub fn new_page(&mut self) {
+        self.pages.push(String::new());
+    }
+
+    fn current(&mut self) -> &mut String {
+        self.pages.last_mut().expect("canvas always has a page")
+    }
+
+    pub fn text(&mut self, x: f32, y: f32, size: f32, font: Font, text: &str) {
+        let escaped = escape(text);
+        let _ = writeln!(
+            self.current(),
+            "BT /{} {:.1} Tf {:.2} {:.2} Td ({}) Tj ET",
+            font.resource(),
+            size,
+            x,
+            y,
+            escaped
+        );
+    }
+
+    pub fn text_right(&mut self, right: f32, y: f32, size: f32, font: Font, text: &str) {
+        self.text(right - text_width(text, size), y, size, font, text);
+    }
+
+    pub fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        let _ = writeln!(self.current(), "0.5 w {:.2} {:.2} m {:.2} {:.2} l S", x1, y1, x2, y2);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let page_count = self.pages.len();
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                (0..page_count).map(|i| format!("{} 0 R", 5 + i * 2)).collect::<Vec<_>>().join(" "),
+                page_count
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_string(),
+        ];
+
+        for (i, content) in self.pages.iter().enumerate() {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                6 + i * 2
+            ));
+            objects.push(format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content));
+        }
+
+        let mut out = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+        }
+
+        let xref = out.len();
+        let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(trailer, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            trailer,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        );
+        out.extend_from_slice(trailer.as_bytes());
+
+        out
+    }
+}
+
+// Latin-1 characters map directly onto WinAnsiEncoding; anything else is replaced
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(escaped, "\\{:03o}", c as u32);
+            }
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+// Approximate Helvetica advance widths, good enough for right-aligning amounts
+fn text_width(text: &str, size: f32) -> f32 {
+    text.chars()
+        .map(|c| match c {
+            '0'..='9' => 0.556,
+            '.' | ',' | ' ' => 0.278,
+            'A'..='Z' => 0.667,
+            _ => 0.5,
+        })
+        .sum::<f32>()
+        * size
+}
+
+pub trait InvoiceTemplate: Send + Sync {
+    // Part of the cache key, so bump it whenever the layout changes
+    fn name(&self) -> &'static str;
+
+    fn render(&self, document: &InvoiceDocument, canvas: &mut Canvas);
+}
+
+pub struct DefaultInvoiceTemplate;
+
+impl InvoiceTemplate for DefaultInvoiceTemplate {
+    fn name(&self) -> &'static str {
+        "default-v1"
+    }
+
+    fn render(&self, document: &InvoiceDocument, canvas: &mut Canvas) {
+        let invoice = &document.invoice.invoice;
+        let customer = &document.customer;
+        let right = PAGE_WIDTH - MARGIN;
+        let mut y = PAGE_HEIGHT - MARGIN;
+
+        canvas.text(MARGIN, y, 20.0, Font::Bold, "INVOICE");
+        canvas.text_right(right, y, 12.0, Font::Bold, invoice.number.as_deref().unwrap_or("DRAFT"));
+        y -= 20.0;
+        if let Some(issued_at) = invoice.issued_at {
+            canvas.text_right(right, y, 10.0, Font::Regular, &format!("Issued {}", issued_at.date()));
+            y -= 14.0;
+        }
+        if let Some(due_date) = invoice.due_date {
+            canvas.text_right(right, y, 10.0, Font::Regular, &format!("Due {}", due_date));
+        }
+
+        y = PAGE_HEIGHT - MARGIN - 60.0;
+        canvas.text(MARGIN, y, 10.0, Font::Bold, "Bill to");
+        y -= 14.0;
+
+        let name = [&customer.salutation, &customer.first_name, &customer.middle_name, &customer.last_name]
+            .iter()
+            .filter_map(|part| part.as_deref())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let locality = [&customer.city, &customer.state]
+            .iter()
+            .filter_map(|part| part.as_deref())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let address = [
+            Some(if name.is_empty() { customer.username.clone() } else { name }),
+            customer.address_line_1.clone(),
+            customer.address_line_2.clone(),
+            Some(locality).filter(|locality| !locality.is_empty()),
+            customer.country.clone(),
+            customer.email.clone(),
+        ];
+        for line in address.iter().flatten() {
+            canvas.text(MARGIN, y, 10.0, Font::Regular, line);
+            y -= 14.0;
+        }
+
+        y -= 20.0;
+        let header = |canvas: &mut Canvas, y: f32| {
+            canvas.text(MARGIN, y, 10.0, Font::Bold, "Description");
+            canvas.text_right(right - 200.0, y, 10.0, Font::Bold, "Qty");
+            canvas.text_right(right - 100.0, y, 10.0, Font::Bold, "Unit price");
+            canvas.text_right(right, y, 10.0, Font::Bold, "Amount");
+            canvas.line(MARGIN, y - 4.0, right, y - 4.0);
+        };
+        header(canvas, y);
+        y -= 20.0;
+
+        for line in &document.invoice.lines {
+            if y < MARGIN + 60.0 {
+                canvas.new_page();
+                y = PAGE_HEIGHT - MARGIN;
+                header(canvas, y);
+                y -= 20.0;
+            }
+            canvas.text(MARGIN, y, 10.0, Font::Regular, &line.description);
+            canvas.text_right(right - 200.0, y, 10.0, Font::Regular, &line.quantity.to_string());
+            canvas.text_right(right - 100.0, y, 10.0, Font::Regular, &line.unit_price.to_string());
+            canvas.text_right(right, y, 10.0, Font::Regular, &line.line_total.to_string());
+            y -= 16.0;
+        }
+
+        canvas.line(right - 200.0, y + 8.0, right, y + 8.0);
+        y -= 8.0;
+        let tax = &document.invoice.tax;
+        canvas.text_right(right - 100.0, y, 10.0, Font::Regular, "Subtotal");
+        canvas.text_right(right, y, 10.0, Font::Regular, &tax.subtotal.to_string());
+        y -= 16.0;
+        for rate in &tax.rates {
+            let label = format!("{} ({}.{:02}%)", rate.name, rate.rate_bps / 100, rate.rate_bps % 100);
+            canvas.text_right(right - 100.0, y, 10.0, Font::Regular, &label);
+            canvas.text_right(right, y, 10.0, Font::Regular, &rate.tax.to_string());
+            y -= 16.0;
+        }
+        canvas.text_right(right - 100.0, y, 10.0, Font::Bold, "Total");
+        canvas.text_right(right, y, 10.0, Font::Bold, &invoice.total.to_string());
+        y -= 16.0;
+        canvas.text_right(right - 100.0, y, 10.0, Font::Regular, "Paid");
+        canvas.text_right(right, y, 10.0, Font::Regular, &invoice.amount_paid.to_string());
+        y -= 16.0;
+        for credit_note in &document.invoice.credit_notes {
+            let label = format!("Credit note {}", credit_note.credit_note.number);
+            canvas.text_right(right - 100.0, y, 10.0, Font::Regular, &label);
+            canvas.text_right(right, y, 10.0, Font::Regular, &format!("-{}", credit_note.credit_note.total));
+            y -= 16.0;
+        }
+        canvas.text_right(right - 100.0, y, 10.0, Font::Bold, "Balance due");
+        canvas.text_right(right, y, 10.0, Font::Bold, &invoice.balance().to_string());
+    }
+}
+
+pub fn render(template: &dyn InvoiceTemplate, document: &InvoiceDocument) -> Vec<u8> {
+    let mut canvas = Canvas::new();
+    template.render(document, &mut canvas);
+    canvas.finish()
+}
+
+// Bounds that keep a long-lived isolate or server from holding every invoice it ever rendered
+const CACHE_MAX_ENTRIES: usize = 256;
+const CACHE_MAX_BYTES: usize = 32 * 1024 * 1024;
+
+struct CachedPdf {
+    fingerprint: String,
+    // Shared with every response serving it, so a cache hit never copies the document
+    bytes: Bytes,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    by_invoice: HashMap<String, CachedPdf>,
+    total_bytes: usize,
+    clock: u64,
+}
+
+impl CacheEntries {
+    fn remove(&mut self, invoice_id: &str) {
+        if let Some(entry) = self.by_invoice.remove(invoice_id) {
+            self.total_bytes -= entry.bytes.len();
+        }
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self
+            .by_invoice
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(invoice_id, _)| invoice_id.clone());
+        if let Some(invoice_id) = oldest {
+            self.remove(&invoice_id);
+        }
+    }
+}
+
+// Rendered documents keyed by invoice id, evicting the least recently used once either limit is
+// reached. The fingerprint covers every input of the render, so any change to the invoice, its
+// lines or the billing address produces a fresh PDF.
+pub struct PdfCache {
+    entries: Mutex<CacheEntries>,
+    max_entries: usize,
+    max_bytes: usize,
+}
+
+impl Default for PdfCache {
+    fn default() -> Self {
+        Self::with_limits(CACHE_MAX_ENTRIES, CACHE_MAX_BYTES)
+    }
+}
+
+impl PdfCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(max_entries: usize, max_bytes: usize) -> Self {
+        PdfCache {
+            entries: Mutex::new(CacheEntries::default()),
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    pub fn fingerprint(template: &dyn InvoiceTemplate, document: &InvoiceDocument) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(template.name().as_bytes());
+        hasher.update(serde_json::to_vec(document).unwrap_or_default());
+        hex::encode(hasher.finalize())
+    }
+
+    // A panic elsewhere while holding the lock leaves the map consistent, so a poisoned lock is
+    // taken over rather than propagated
+    fn lock(&self) -> MutexGuard<'_, CacheEntries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get_or_render(&self, template: &dyn InvoiceTemplate, document: &InvoiceDocument) -> Bytes {
+        let invoice_id = &document.invoice.invoice.id;
+        let fingerprint = Self::fingerprint(template, document);
+
+        {
+            let mut entries = self.lock();
+            entries.clock += 1;
+            let now = entries.clock;
+            if let Some(entry) = entries.by_invoice.get_mut(invoice_id) {
+                if entry.fingerprint == fingerprint {
+                    entry.last_used = now;
+                    return entry.bytes.clone();
+                }
+            }
+        }
+
+        // Rendered outside the lock so one large invoice does not stall every other download
+        let bytes = Bytes::from(render(template, document));
+        if self.max_entries == 0 || bytes.len() > self.max_bytes {
+            return bytes;
+        }
+
+        let mut entries = self.lock();
+        entries.remove(invoice_id);
+        while entries.by_invoice.len() >= self.max_entries || entries.total_bytes + bytes.len() > self.max_bytes {
+            entries.evict_least_recently_used();
+        }
+        entries.clock += 1;
+        let last_used = entries.clock;
+        entries.total_bytes += bytes.len();
+        entries.by_invoice.insert(
+            invoice_id.clone(),
+            CachedPdf {
+                fingerprint,
+                bytes: bytes.clone(),
+                last_used,
+            },
+        );
+
+        bytes
+    }
+}
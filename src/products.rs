@@ -0,0 +1,169 @@
+// src/products.rs
+use super::*;
+use chrono::Utc;
+use db::{Database, Statement};
+use models::{CreateProductPayload, Product, ProductSearchQuery, UpdateProductPayload};
+use repository::CatalogRepository;
+use utils::AppError;
+
+pub fn is_valid_sku(sku: &str) -> bool {
+    !sku.is_empty()
+        && sku.len() <= 64
+        && sku.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-' || c == '_' || c == '.')
+}
+
+// `include_inactive` is only set for back-office callers; the public catalog never sees retired products
+pub async fn list_products(
+    repo: &dyn CatalogRepository,
+    query: &ProductSearchQuery,
+    include_inactive: bool,
+) -> Result<Vec<Product>, Box<dyn std::error::Error>> {
+    Ok(repo.search_products(query, include_inactive).await?)
+}
+
+pub async fn fetch_product(
+    repo: &dyn CatalogRepository,
+    product_id: &str,
+    include_inactive: bool,
+) -> Result<Option<Product>, Box<dyn std::error::Error>> {
+    Ok(repo.product(product_id, include_inactive).await?)
+}
+
+pub async fn create_product(
+    repo: &dyn CatalogRepository,
+    payload: &CreateProductPayload,
+) -> Result<Product, Box<dyn std::error::Error>> {
+    let sku = payload.sku.trim().to_uppercase();
+    if !is_valid_sku(&sku) {
+        return Err(Box::new(AppError::Validation(
+            "SKU may only contain letters, digits, '-', '_' and '.'".to_string(),
+        )));
+    }
+    if payload.name.trim().is_empty() {
+        return Err(Box::new(AppError::Validation("Product name cannot be empty".to_string())));
+    }
+    if payload.price.is_negative() {
+        return Err(Box::new(AppError::Validation("Product price cannot be negative".to_string())));
+    }
+
+    let stock_quantity = payload.stock_quantity.unwrap_or(0);
+    if stock_quantity < 0 {
+        return Err(Box::new(AppError::Validation("Stock quantity cannot be negative".to_string())));
+    }
+
+    let now = Utc::now().naive_utc();
+    let product = Product {
+        id: utils::generate_uuid(),
+        sku,
+        name: payload.name.trim().to_string(),
+        description: payload.description.clone(),
+        price: payload.price,
+        tax_class: payload.tax_class.clone().unwrap_or_else(|| tax::STANDARD.to_string()),
+        active: payload.active.unwrap_or(true),
+        stock_quantity,
+        created_at: now,
+        updated_at: now,
+    };
+
+    repo.insert_product(&product).await?;
+
+    Ok(product)
+}
+
+pub async fn update_product(
+    repo: &dyn CatalogRepository,
+    product_id: &str,
+    payload: &UpdateProductPayload,
+) -> Result<Option<Product>, Box<dyn std::error::Error>> {
+    if matches!(&payload.name, Some(name) if name.trim().is_empty()) {
+        return Err(Box::new(AppError::Validation("Product name cannot be empty".to_string())));
+    }
+    if matches!(&payload.price, Some(price) if price.is_negative()) {
+        return Err(Box::new(AppError::Validation("Product price cannot be negative".to_string())));
+    }
+
+    if repo.update_product(product_id, payload, Utc::now().naive_utc()).await? {
+        return fetch_product(repo, product_id, true).await;
+    }
+
+    match fetch_product(repo, product_id, true).await? {
+        Some(current) => Err(Box::new(AppError::Conflict(format!(
+            "Stock adjustment of {} would take {} below zero",
+            payload.stock_adjustment.unwrap_or(0),
+            current.sku
+        )))),
+        None => Ok(None),
+    }
+}
+
+// Products already ordered stay referenced by their order lines; retire them with `active = false` instead
+pub async fn delete_product(
+    repo: &dyn CatalogRepository,
+    product_id: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if repo.delete_product(product_id).await? {
+        return Ok(true);
+    }
+
+    match fetch_product(repo, product_id, true).await? {
+        Some(_) => Err(Box::new(AppError::Conflict(
+            "Product has been ordered; deactivate it instead".to_string(),
+        ))),
+        None => Ok(false),
+    }
+}
+
+// Checks availability and returns the statement that takes the units, for the caller to batch with
+// the rest of its order. The products CHECK keeps stock from going negative if another order took
+// the units in between, and a product deactivated in between sets the quantity to NULL; either
+// aborts the whole batch. `held` is what the order already reserves and is about to release.
+pub async fn reserve_stock(
+    db: &dyn Database,
+    product_id: &str,
+    quantity: i32,
+    held: i32,
+) -> Result<(Product, Statement), Box<dyn std::error::Error>> {
+    let product = db
+        .fetch_optional(
+            Statement::new("SELECT * FROM products WHERE id = ?1 AND active = ?2")
+                .bind(product_id)
+                .bind(true),
+        )
+        .await?;
+
+    let product = match product {
+        Some(row) => Product::from_row(&row)?,
+        None => return Err(Box::new(AppError::Validation(format!("Product {} is not available", product_id)))),
+    };
+
+    let available = product.stock_quantity + held;
+    if available < quantity {
+        return Err(Box::new(AppError::Conflict(format!(
+            "Insufficient stock for {}: {} requested, {} available",
+            product.sku, quantity, available
+        ))));
+    }
+
+    let reservation = Statement::new(
+        "UPDATE products SET stock_quantity = CASE WHEN active THEN stock_quantity - ?2 END, updated_at = ?3
+        WHERE id = ?1",
+    )
+    .bind(product_id)
+    .bind(quantity)
+    .bind(Utc::now().naive_utc());
+
+    Ok((product, reservation))
+}
+
+// Returns every unit held by the order's catalog lines to stock
+pub fn release_stock(order_id: &str) -> Statement {
+    Statement::new(
+        "UPDATE products SET stock_quantity = stock_quantity + (
+            SELECT COALESCE(SUM(quantity), 0) FROM order_lines
+            WHERE order_lines.order_id = ?1 AND order_lines.product_id = products.id
+        ), updated_at = ?2
+        WHERE id IN (SELECT product_id FROM order_lines WHERE order_id = ?1)",
+    )
+    .bind(order_id)
+    .bind(Utc::now().naive_utc())
+}
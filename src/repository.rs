@@ -0,0 +1,920 @@
+// src/repository.rs
+use super::*;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use db::{Database, DbError, Row, Statement};
+use numbering::{NumberingScheme, SequenceStore};
+use models::{Product, ProductSearchQuery, RoleDetails, SettingsResponse, UpdateProductPayload, User};
+use std::sync::Arc;
+
+// Lookups made on every authenticated request and at sign-in
+#[async_trait]
+pub trait IdentityRepository: Send + Sync {
+    async fn user_by_username(&self, username: &str) -> Result<Option<User>, DbError>;
+
+    async fn role_slugs(&self, user_id: &str) -> Result<Vec<String>, DbError>;
+
+    async fn permissions(&self, user_id: &str) -> Result<Vec<String>, DbError>;
+
+    async fn is_token_revoked(&self, jti: &str, user_id: &str, issued_at: NaiveDateTime) -> Result<bool, DbError>;
+
+    async fn settings(&self, user_id: &str) -> Result<Option<SettingsResponse>, DbError>;
+}
+
+#[async_trait]
+pub trait RoleRepository: Send + Sync {
+    async fn list_roles(&self) -> Result<Vec<RoleDetails>, DbError>;
+
+    async fn role(&self, slug: &str) -> Result<Option<RoleDetails>, DbError>;
+}
+
+#[async_trait]
+pub trait CatalogRepository: Send + Sync {
+    async fn search_products(&self, query: &ProductSearchQuery, include_inactive: bool) -> Result<Vec<Product>, DbError>;
+
+    async fn product(&self, product_id: &str, include_inactive: bool) -> Result<Option<Product>, DbError>;
+
+    async fn insert_product(&self, product: &Product) -> Result<(), DbError>;
+
+    // Returns false when the product is missing or the adjustment would take stock below zero
+    async fn update_product(
+        &self,
+        product_id: &str,
+        changes: &UpdateProductPayload,
+        updated_at: NaiveDateTime,
+    ) -> Result<bool, DbError>;
+
+    // Returns false when the product is missing or already referenced by an order line
+    async fn delete_product(&self, product_id: &str) -> Result<bool, DbError>;
+}
+
+// One implementation over any `Database`; the SQL below sticks to what Postgres, SQLite and D1 all accept
+pub struct SqlRepository {
+    db: Arc<dyn Database>,
+}
+
+impl SqlRepository {
+    pub fn new(db: Arc<dyn Database>) -> Self {
+        SqlRepository { db }
+    }
+}
+
+fn user_from_row(row: &Row) -> Result<User, DbError> {
+    Ok(User {
+        id: row.get("id")?,
+        username: row.get("username")?,
+        email: row.get("email")?,
+        password: row.get("password")?,
+        created_at: row.get_datetime("created_at")?,
+        updated_at: row.get_datetime("updated_at")?,
+    })
+}
+
+pub(crate) fn role_from_row(row: &Row) -> Result<RoleDetails, DbError> {
+    Ok(RoleDetails {
+        slug: row.get("slug")?,
+        name: row.get("name")?,
+        description: row.get("description")?,
+    })
+}
+
+#[async_trait]
+impl IdentityRepository for SqlRepository {
+    async fn user_by_username(&self, username: &str) -> Result<Option<User>, DbError> {
+        let statement = Statement::new("SELECT * FROM users WHERE username = ?1").bind(username);
+
+        self.db
+            .fetch_optional(statement)
+            .await?
+            .map(|row| user_from_row(&row))
+            .transpose()
+    }
+
+    async fn role_slugs(&self, user_id: &str) -> Result<Vec<String>, DbError> {
+        let statement = Statement::new("SELECT role_slug FROM users_roles WHERE user_id = ?1").bind(user_id);
+
+        self.db
+            .fetch_all(statement)
+            .await?
+            .iter()
+            .map(|row| row.get("role_slug"))
+            .collect()
+    }
+
+    async fn permissions(&self, user_id: &str) -> Result<Vec<String>, DbError> {
+        let statement = Statement::new(
+            "SELECT DISTINCT rp.permission_slug FROM users_roles ur
+            JOIN role_permissions rp ON rp.role_slug = ur.role_slug
+            WHERE ur.user_id = ?1
+            ORDER BY rp.permission_slug",
+        )
+        .bind(user_id);
+
+        self.db
+            .fetch_all(statement)
+            .await?
+            .iter()
+            .map(|row| row.get("permission_slug"))
+            .collect()
+    }
+
+    async fn is_token_revoked(&self, jti: &str, user_id: &str, issued_at: NaiveDateTime) -> Result<bool, DbError> {
+        let statement = Statement::new(
+            "SELECT
+                EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = ?1)
+                OR EXISTS (SELECT 1 FROM user_token_cutoffs WHERE user_id = ?2 AND revoked_before > ?3)
+                AS revoked",
+        )
+        .bind(jti)
+        .bind(user_id)
+        .bind(issued_at);
+
+        self.db.fetch_one(statement).await?.get_bool("revoked")
+    }
+
+    async fn settings(&self, user_id: &str) -> Result<Option<SettingsResponse>, DbError> {
+        let statement =
+            Statement::new("SELECT theme, language, notifications FROM user_settings WHERE user_id = ?1").bind(user_id);
+
+        self.db
+            .fetch_optional(statement)
+            .await?
+            .map(|row| {
+                Ok(SettingsResponse {
+                    theme: row.get("theme")?,
+                    language: row.get("language")?,
+                    notifications: row.get_bool("notifications")?,
+                })
+            })
+            .transpose()
+    }
+}
+
+#[async_trait]
+impl RoleRepository for SqlRepository {
+    async fn list_roles(&self) -> Result<Vec<RoleDetails>, DbError> {
+        let statement = Statement::new("SELECT slug, name, description FROM roles ORDER BY slug");
+
+        self.db.fetch_all(statement).await?.iter().map(role_from_row).collect()
+    }
+
+    async fn role(&self, slug: &str) -> Result<Option<RoleDetails>, DbError> {
+        let statement = Statement::new("SELECT slug, name, description FROM roles WHERE slug = ?1").bind(slug);
+
+        self.db
+            .fetch_optional(statement)
+            .await?
+            .map(|row| role_from_row(&row))
+            .transpose()
+    }
+}
+
+#[async_trait]
+impl CatalogRepository for SqlRepository {
+    async fn search_products(&self, query: &ProductSearchQuery, include_inactive: bool) -> Result<Vec<Product>, DbError> {
+        let mut statement = Statement::new("SELECT * FROM products WHERE 1 = 1");
+
+        match (include_inactive, query.active) {
+            (false, _) => statement = statement.push_bind(" AND active = ?", true),
+            (true, Some(active)) => statement = statement.push_bind(" AND active = ?", active),
+            (true, None) => {}
+        }
+        if let Some(q) = &query.q {
+            statement = statement.push_bind(
+                " AND (LOWER(sku) LIKE ? ESCAPE '\\' OR LOWER(name) LIKE ? ESCAPE '\\'
+                OR LOWER(COALESCE(description, '')) LIKE ? ESCAPE '\\')",
+                db::contains_pattern(q.trim()),
+            );
+        }
+        if let Some(tax_class) = &query.tax_class {
+            statement = statement.push_bind(" AND tax_class = ?", tax_class.as_str());
+        }
+        match query.in_stock {
+            Some(true) => statement = statement.push(" AND stock_quantity > 0"),
+            Some(false) => statement = statement.push(" AND stock_quantity <= 0"),
+            None => {}
+        }
+
+        let statement = statement
+            .push(" ORDER BY name, sku")
+            .push_bind(" LIMIT ?", query.limit.unwrap_or(25).clamp(1, 100))
+            .push_bind(" OFFSET ?", query.offset.unwrap_or(0).max(0));
+
+        self.db.fetch_all(statement).await?.iter().map(Product::from_row).collect()
+    }
+
+    async fn product(&self, product_id: &str, include_inactive: bool) -> Result<Option<Product>, DbError> {
+        let mut statement = Statement::new("SELECT * FROM products WHERE id = ?1").bind(product_id);
+        if !include_inactive {
+            statement = statement.push_bind(" AND active = ?", true);
+        }
+
+        self.db
+            .fetch_optional(statement)
+            .await?
+            .map(|row| Product::from_row(&row))
+            .transpose()
+    }
+
+    async fn insert_product(&self, product: &Product) -> Result<(), DbError> {
+        let statement = Statement::new(
+            "INSERT INTO products (
+                id,
+                sku,
+                name,
+                description,
+                price,
+                currency,
+                tax_class,
+                active,
+                stock_quantity,
+                created_at,
+                updated_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )
+        .bind(product.id.as_str())
+        .bind(product.sku.as_str())
+        .bind(product.name.as_str())
+        .bind(product.description.clone())
+        .bind(product.price.minor())
+        .bind(product.price.currency().code())
+        .bind(product.tax_class.as_str())
+        .bind(product.active)
+        .bind(product.stock_quantity)
+        .bind(product.created_at)
+        .bind(product.updated_at);
+
+        self.db.execute(statement).await?;
+
+        Ok(())
+    }
+
+    // Only the supplied columns are written, and the stock guard sits in the WHERE clause,
+    // so the check and the adjustment are one atomic statement on every backend
+    async fn update_product(
+        &self,
+        product_id: &str,
+        changes: &UpdateProductPayload,
+        updated_at: NaiveDateTime,
+    ) -> Result<bool, DbError> {
+        let mut statement = Statement::new("UPDATE products SET updated_at = ?1").bind(updated_at);
+
+        if let Some(name) = &changes.name {
+            statement = statement.push_bind(", name = ?", name.trim());
+        }
+        if let Some(description) = &changes.description {
+            statement = statement.push_bind(", description = ?", description.as_str());
+        }
+        if let Some(price) = &changes.price {
+            statement = statement
+                .push_bind(", price = ?", price.minor())
+                .push_bind(", currency = ?", price.currency().code());
+        }
+        if let Some(tax_class) = &changes.tax_class {
+            statement = statement.push_bind(", tax_class = ?", tax_class.as_str());
+        }
+        if let Some(active) = changes.active {
+            statement = statement.push_bind(", active = ?", active);
+        }
+
+        let adjustment = changes.stock_adjustment.unwrap_or(0);
+        let statement = statement
+            .push_bind(", stock_quantity = stock_quantity + ?", adjustment)
+            .push_bind(" WHERE id = ?", product_id)
+            .push_bind(" AND stock_quantity + ? >= 0", adjustment);
+
+        Ok(self.db.execute(statement).await? > 0)
+    }
+
+    async fn delete_product(&self, product_id: &str) -> Result<bool, DbError> {
+        let statement = Statement::new(
+            "DELETE FROM products WHERE id = ?1
+            AND NOT EXISTS (SELECT 1 FROM order_lines WHERE product_id = ?1)",
+        )
+        .bind(product_id);
+
+        Ok(self.db.execute(statement).await? > 0)
+    }
+}
+
+#[async_trait]
+impl SequenceStore for SqlRepository {
+    async fn scheme(&self, tenant_id: &str, document_type: &str) -> Result<Option<NumberingScheme>, DbError> {
+        let statement = Statement::new(
+            "SELECT tenant_id, document_type, prefix, format, reset_yearly FROM numbering_schemes
+            WHERE tenant_id = ?1 AND document_type = ?2",
+        )
+        .bind(tenant_id)
+        .bind(document_type);
+
+        self.db
+            .fetch_optional(statement)
+            .await?
+            .map(|row| {
+                Ok(NumberingScheme {
+                    tenant_id: row.get("tenant_id")?,
+                    document_type: row.get("document_type")?,
+                    prefix: row.get("prefix")?,
+                    format: row.get("format")?,
+                    reset_yearly: row.get_bool("reset_yearly")?,
+                })
+            })
+            .transpose()
+    }
+
+    async fn last_value(&self, tenant_id: &str, document_type: &str, period: &str) -> Result<i64, DbError> {
+        let statement = Statement::new(
+            "SELECT last_value FROM number_sequences WHERE tenant_id = ?1 AND document_type = ?2 AND period = ?3",
+        )
+        .bind(tenant_id)
+        .bind(document_type)
+        .bind(period);
+
+        match self.db.fetch_optional(statement).await? {
+            Some(row) => row.get("last_value"),
+            None => Ok(0),
+        }
+    }
+
+    async fn tenants_using_prefix(
+        &self,
+        tenant_id: &str,
+        document_type: &str,
+        prefix: &str,
+    ) -> Result<Vec<String>, DbError> {
+        let statement = Statement::new(
+            "SELECT tenant_id FROM numbering_schemes
+            WHERE document_type = ?1 AND prefix = ?2 AND tenant_id <> ?3
+            ORDER BY tenant_id",
+        )
+        .bind(document_type)
+        .bind(prefix)
+        .bind(tenant_id);
+
+        self.db
+            .fetch_all(statement)
+            .await?
+            .iter()
+            .map(|row| row.get("tenant_id"))
+            .collect()
+    }
+
+    async fn save_scheme(&self, scheme: &NumberingScheme) -> Result<(), DbError> {
+        let statement = Statement::new(
+            "INSERT INTO numbering_schemes (tenant_id, document_type, prefix, format, reset_yearly)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (tenant_id, document_type)
+            DO UPDATE SET prefix = excluded.prefix, format = excluded.format, reset_yearly = excluded.reset_yearly",
+        )
+        .bind(scheme.tenant_id.as_str())
+        .bind(scheme.document_type.as_str())
+        .bind(scheme.prefix.as_str())
+        .bind(scheme.format.as_str())
+        .bind(scheme.reset_yearly);
+
+        self.db.execute(statement).await?;
+
+        Ok(())
+    }
+}
+
+// The same suite runs against every sqlx backend: SQLite in memory always, and Postgres when
+// TEST_POSTGRES_URL names a database the tests may create throwaway schemas in
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use services::RefreshOutcome;
+    use utils::AppError;
+
+    async fn sqlite() -> Arc<dyn Database> {
+        // Every connection to `sqlite::memory:` opens its own empty database
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        Arc::new(db::Sqlite::new(pool))
+    }
+
+    // Each test gets its own schema, dropped again once the test passes
+    async fn postgres() -> Option<(Arc<dyn Database>, String)> {
+        let Ok(url) = std::env::var("TEST_POSTGRES_URL") else {
+            eprintln!("TEST_POSTGRES_URL is not set; skipping the Postgres run");
+            return None;
+        };
+
+        let schema = format!("test_{}", utils::generate_uuid().replace('-', ""));
+        let admin = sqlx::PgPool::connect(&url).await.unwrap();
+        sqlx::query(&format!("CREATE SCHEMA {}", schema)).execute(&admin).await.unwrap();
+
+        let options = url
+            .parse::<sqlx::postgres::PgConnectOptions>()
+            .unwrap()
+            .options([("search_path", schema.as_str())]);
+        let pool = sqlx::postgres::PgPoolOptions::new().connect_with(options).await.unwrap();
+
+        Some((Arc::new(db::Postgres::new(pool)), schema))
+    }
+
+    async fn drop_schema(schema: &str) {
+        let admin = sqlx::PgPool::connect(&std::env::var("TEST_POSTGRES_URL").unwrap()).await.unwrap();
+        sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema)).execute(&admin).await.unwrap();
+    }
+
+    // Expands each test into one #[tokio::test] per backend, each on a freshly migrated database
+    macro_rules! backend_tests {
+        ($($test:ident),* $(,)?) => {
+            mod sqlite {
+                $(
+                    #[tokio::test]
+                    async fn $test() {
+                        super::run(super::sqlite().await, super::$test).await;
+                    }
+                )*
+            }
+
+            mod postgres {
+                $(
+                    #[tokio::test]
+                    async fn $test() {
+                        if let Some((db, schema)) = super::postgres().await {
+                            super::run(db, super::$test).await;
+                            super::drop_schema(&schema).await;
+                        }
+                    }
+                )*
+            }
+        };
+    }
+
+    backend_tests!(
+        migrations_roll_back_and_reapply,
+        sign_up_is_one_atomic_batch,
+        roles_and_permissions,
+        token_revocation,
+        refresh_rotation_detects_reuse,
+        catalog_crud_and_stock,
+        stale_versions_trip_the_guard,
+        invoice_lifecycle,
+        overdue_invoices_stay_overdue_until_settled,
+        seeding_is_idempotent,
+    );
+
+    async fn run<F, Fut>(db: Arc<dyn Database>, test: F)
+    where
+        F: FnOnce(Arc<dyn Database>, SqlRepository) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        migrations::migrate(db.as_ref()).await.unwrap();
+        test(db.clone(), SqlRepository::new(db)).await;
+    }
+
+    fn payload<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> T {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn config() -> config::Config {
+        config::Config::load(|key| (key == "SECRET_KEY").then(|| "s".repeat(32))).unwrap()
+    }
+
+    fn app_error<T>(result: Result<T, Box<dyn std::error::Error>>) -> AppError {
+        match result {
+            Ok(_) => panic!("expected the call to be rejected"),
+            Err(e) => *e.downcast::<AppError>().unwrap(),
+        }
+    }
+
+    async fn sign_up(db: &dyn Database, username: &str) -> String {
+        let user_id = utils::generate_uuid();
+        let payload: SignUpPayload = payload(json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "password": "correct horse battery staple",
+            "first_name": "Test",
+            "last_name": "User",
+            "country": "DE",
+            "date_of_birth": "1990-01-31",
+        }));
+
+        db.batch(vec![
+            services::create_user_statement(&payload, "hash", &user_id).unwrap(),
+            services::create_profile_statement(&payload, &user_id).unwrap(),
+        ])
+        .await
+        .unwrap();
+
+        user_id
+    }
+
+    async fn create_role(db: &dyn Database, slug: &str) {
+        let role = payload(json!({ "slug": slug, "name": slug }));
+        services::create_role(db, &role).await.unwrap();
+    }
+
+    async fn count(db: &dyn Database, table: &str) -> i64 {
+        let row = db
+            .fetch_one(Statement::new(format!("SELECT COUNT(*) AS n FROM {}", table)))
+            .await
+            .unwrap();
+        row.get("n").unwrap()
+    }
+
+    async fn stock(repo: &SqlRepository, product_id: &str) -> i32 {
+        repo.product(product_id, true).await.unwrap().unwrap().stock_quantity
+    }
+
+    async fn migrations_roll_back_and_reapply(db: Arc<dyn Database>, repo: SqlRepository) {
+        let reverted = migrations::rollback(db.as_ref(), 0).await.unwrap();
+        assert_eq!(reverted.len(), migrations::MIGRATIONS.len());
+
+        let applied = migrations::migrate(db.as_ref()).await.unwrap();
+        assert_eq!(applied.len(), migrations::MIGRATIONS.len());
+        assert!(migrations::migrate(db.as_ref()).await.unwrap().is_empty());
+
+        // Every backend starts with the built-in roles and their grants
+        for slug in ["admin", "staff", "customer"] {
+            assert!(repo.role(slug).await.unwrap().is_some(), "missing built-in role {}", slug);
+        }
+        let granted = services::fetch_role_permissions(db.as_ref(), "staff").await.unwrap();
+        let granted: Vec<_> = granted.iter().map(|permission| permission.slug.as_str()).collect();
+        assert_eq!(granted, ["dashboard:read", "invoices:manage", "payments:manage"]);
+    }
+
+    async fn sign_up_is_one_atomic_batch(db: Arc<dyn Database>, repo: SqlRepository) {
+        // `customer` ships with the schema, so sign-up works on a freshly migrated database
+        let user_id = sign_up(db.as_ref(), "ada").await;
+        db.execute(services::assign_role_statement(&user_id, "customer")).await.unwrap();
+
+        let user = repo.user_by_username("ada").await.unwrap().unwrap();
+        assert_eq!(user.id, user_id);
+        assert_eq!(user.email.as_deref(), Some("ada@example.com"));
+        assert_eq!(repo.role_slugs(&user_id).await.unwrap(), vec!["customer".to_string()]);
+
+        let profile = services::fetch_user_profile(db.as_ref(), &user_id).await.unwrap().unwrap();
+        assert_eq!(profile.country.as_deref(), Some("DE"));
+
+        // A taken username fails the first statement, and the profile after it is never written
+        let duplicate: SignUpPayload = payload(json!({
+            "username": "ada",
+            "password": "another password",
+            "date_of_birth": "1991-02-01",
+        }));
+        let other_id = utils::generate_uuid();
+        let e = db
+            .batch(vec![
+                services::create_user_statement(&duplicate, "hash", &other_id).unwrap(),
+                services::create_profile_statement(&duplicate, &other_id).unwrap(),
+            ])
+            .await
+            .unwrap_err();
+        assert!(e.is_unique_violation(), "{}", e);
+        assert_eq!(count(db.as_ref(), "users").await, 1);
+        assert_eq!(count(db.as_ref(), "profiles").await, 1);
+    }
+
+    async fn roles_and_permissions(db: Arc<dyn Database>, repo: SqlRepository) {
+        create_role(db.as_ref(), "support").await;
+        create_role(db.as_ref(), "reviewer").await;
+        let user_id = sign_up(db.as_ref(), "grace").await;
+
+        services::assign_role(db.as_ref(), &user_id, "support").await.unwrap();
+        assert_eq!(repo.role_slugs(&user_id).await.unwrap(), vec!["support".to_string()]);
+
+        let grant = payload(json!({ "permission": "orders:read" }));
+        services::grant_permission(db.as_ref(), "support", &grant).await.unwrap();
+        assert_eq!(repo.permissions(&user_id).await.unwrap(), vec!["orders:read".to_string()]);
+
+        // Deleting a held role moves its holders to the replacement
+        let outcome = services::delete_role(db.as_ref(), &repo, "support", Some("reviewer")).await.unwrap();
+        assert!(matches!(outcome, services::DeleteRoleOutcome::Deleted));
+        assert_eq!(repo.role_slugs(&user_id).await.unwrap(), vec!["reviewer".to_string()]);
+        assert!(repo.permissions(&user_id).await.unwrap().is_empty());
+        assert!(repo.role("support").await.unwrap().is_none());
+
+        assert!(services::revoke_role(db.as_ref(), &user_id, "reviewer").await.unwrap());
+        assert!(repo.role_slugs(&user_id).await.unwrap().is_empty());
+    }
+
+    async fn token_revocation(db: Arc<dyn Database>, repo: SqlRepository) {
+        let user_id = sign_up(db.as_ref(), "linus").await;
+        let now = chrono::Utc::now().naive_utc();
+        let hour = chrono::Duration::hours(1);
+
+        assert!(!repo.is_token_revoked("jti-1", &user_id, now).await.unwrap());
+        services::revoke_access_token(db.as_ref(), "jti-1", &user_id, now + hour).await.unwrap();
+        assert!(repo.is_token_revoked("jti-1", &user_id, now).await.unwrap());
+        assert!(!repo.is_token_revoked("jti-2", &user_id, now).await.unwrap());
+
+        // Signing out everywhere revokes whatever was issued before the cutoff
+        services::revoke_all_tokens(db.as_ref(), &user_id, now).await.unwrap();
+        assert!(repo.is_token_revoked("jti-2", &user_id, now - hour).await.unwrap());
+        assert!(!repo.is_token_revoked("jti-3", &user_id, now + hour).await.unwrap());
+
+        // The cutoff keeps sub-second precision, so a sign-in just after it is not caught
+        let millisecond = chrono::Duration::milliseconds(1);
+        assert!(repo.is_token_revoked("jti-4", &user_id, now - millisecond).await.unwrap());
+        assert!(!repo.is_token_revoked("jti-5", &user_id, now + millisecond).await.unwrap());
+    }
+
+    async fn refresh_rotation_detects_reuse(db: Arc<dyn Database>, _repo: SqlRepository) {
+        let config = config();
+        let user_id = sign_up(db.as_ref(), "barbara").await;
+        let family_id = utils::generate_uuid();
+
+        let first = services::issue_refresh_token(db.as_ref(), &config, &user_id, &family_id, None).await.unwrap();
+
+        let second = match services::rotate_refresh_token(db.as_ref(), &config, &first).await.unwrap() {
+            RefreshOutcome::Rotated { user_id: rotated_for, refresh_token } => {
+                assert_eq!(rotated_for, user_id);
+                refresh_token
+            }
+            _ => panic!("a fresh refresh token should rotate"),
+        };
+
+        // Replaying the spent token revokes the family, including the token that replaced it
+        assert!(matches!(
+            services::rotate_refresh_token(db.as_ref(), &config, &first).await.unwrap(),
+            RefreshOutcome::Reused
+        ));
+        assert!(matches!(
+            services::rotate_refresh_token(db.as_ref(), &config, &second).await.unwrap(),
+            RefreshOutcome::Invalid
+        ));
+        assert!(matches!(
+            services::rotate_refresh_token(db.as_ref(), &config, "not-a-token").await.unwrap(),
+            RefreshOutcome::Invalid
+        ));
+    }
+
+    async fn catalog_crud_and_stock(db: Arc<dyn Database>, repo: SqlRepository) {
+        let config = config();
+        let user_id = sign_up(db.as_ref(), "edsger").await;
+
+        let widget = products::create_product(
+            &repo,
+            &payload(json!({
+                "sku": "wid-1",
+                "name": "Widget",
+                "price": { "amount": "10.00", "currency": "EUR" },
+                "stock_quantity": 5,
+            })),
+        )
+        .await
+        .unwrap();
+        assert_eq!(widget.sku, "WID-1");
+
+        let found = repo
+            .search_products(&payload(json!({ "q": "widg" })), false)
+            .await
+            .unwrap();
+        assert_eq!(found.iter().map(|product| product.id.as_str()).collect::<Vec<_>>(), vec![widget.id.as_str()]);
+
+        // Wildcards in the search term are matched literally
+        let gadget = products::create_product(
+            &repo,
+            &payload(json!({ "sku": "gad-1", "name": "Gadget 50% off", "price": { "amount": "5.00", "currency": "EUR" } })),
+        )
+        .await
+        .unwrap();
+        let found = repo.search_products(&payload(json!({ "q": "%" })), false).await.unwrap();
+        assert_eq!(found.iter().map(|product| product.id.as_str()).collect::<Vec<_>>(), vec![gadget.id.as_str()]);
+        assert!(repo.search_products(&payload(json!({ "q": "d_1" })), false).await.unwrap().is_empty());
+
+        let order_of = |quantity: i32| -> CreateOrderPayload {
+            payload(json!({
+                "currency": "EUR",
+                "lines": [{ "product_id": widget.id, "quantity": quantity }],
+            }))
+        };
+
+        let order = services::create_order(db.as_ref(), &config, &user_id, &order_of(3)).await.unwrap();
+        assert_eq!(stock(&repo, &widget.id).await, 2);
+
+        let rejected = app_error(services::create_order(db.as_ref(), &config, &user_id, &order_of(3)).await);
+        assert!(matches!(rejected, AppError::Conflict(_)));
+        assert_eq!(count(db.as_ref(), "orders").await, 1);
+
+        // Replacing the lines may reuse the units the order already holds
+        let update: UpdateOrderPayload = payload(json!({ "lines": [{ "product_id": widget.id, "quantity": 5 }] }));
+        services::update_order(db.as_ref(), &order.order.id, None, &update).await.unwrap().unwrap();
+        assert_eq!(stock(&repo, &widget.id).await, 0);
+
+        let adjusted = payload(json!({ "stock_adjustment": -1 }));
+        let rejected = app_error(products::update_product(&repo, &widget.id, &adjusted).await);
+        assert!(matches!(rejected, AppError::Conflict(_)));
+
+        services::cancel_order(db.as_ref(), &order.order.id, None).await.unwrap().unwrap();
+        assert_eq!(stock(&repo, &widget.id).await, 5);
+
+        // Ordered products are retired rather than deleted
+        let rejected = app_error(products::delete_product(&repo, &widget.id).await);
+        assert!(matches!(rejected, AppError::Conflict(_)));
+        let retired = payload(json!({ "active": false }));
+        products::update_product(&repo, &widget.id, &retired).await.unwrap().unwrap();
+        assert!(repo.product(&widget.id, false).await.unwrap().is_none());
+
+        let rejected = app_error(services::create_order(db.as_ref(), &config, &user_id, &order_of(1)).await);
+        assert!(matches!(rejected, AppError::Validation(_)));
+    }
+
+    async fn stale_versions_trip_the_guard(db: Arc<dyn Database>, _repo: SqlRepository) {
+        let config = config();
+        let user_id = sign_up(db.as_ref(), "tony").await;
+        let order: CreateOrderPayload = payload(json!({
+            "currency": "EUR",
+            "lines": [{ "description": "Consulting", "quantity": 1, "unit_price": { "amount": "100.00", "currency": "EUR" } }],
+        }));
+        let order = services::create_order(db.as_ref(), &config, &user_id, &order).await.unwrap();
+
+        let guard = |seen: i64| {
+            Statement::new("UPDATE orders SET version = CASE WHEN version = ?1 THEN version + 1 END WHERE id = ?2")
+                .bind(seen)
+                .bind(order.order.id.as_str())
+        };
+
+        db.batch(vec![guard(order.order.version)]).await.unwrap();
+
+        // The second writer saw the same version; its whole batch must roll back
+        let notes = Statement::new("UPDATE orders SET notes = ?1 WHERE id = ?2")
+            .bind("lost update")
+            .bind(order.order.id.as_str());
+        let e = db.batch(vec![notes, guard(order.order.version)]).await.unwrap_err();
+        assert!(e.is_guard_violation(), "{}", e);
+
+        let stored = services::fetch_order_details(db.as_ref(), &order.order.id, None).await.unwrap().unwrap();
+        assert_eq!(stored.order.version, order.order.version + 1);
+        assert_eq!(stored.order.notes, None);
+    }
+
+    async fn invoice_lifecycle(db: Arc<dyn Database>, repo: SqlRepository) {
+        let config = config();
+        let user_id = sign_up(db.as_ref(), "margaret").await;
+        let rate = payload(json!({ "country": "de", "tax_class": "standard", "name": "VAT", "rate_bps": 1900 }));
+        tax::create_rate(db.as_ref(), &rate).await.unwrap();
+
+        let order: CreateOrderPayload = payload(json!({
+            "currency": "EUR",
+            "lines": [{ "description": "Support", "quantity": 4, "unit_price": { "amount": "25.00", "currency": "EUR" } }],
+        }));
+        let order = services::create_order(db.as_ref(), &config, &user_id, &order).await.unwrap();
+        assert_eq!(order.order.total.minor(), 11_900);
+
+        let invoice = services::create_invoice(db.as_ref(), &payload(json!({ "order_id": order.order.id })))
+            .await
+            .unwrap();
+        let invoice_id = invoice.invoice.id.clone();
+        assert_eq!(invoice.invoice.status, "draft");
+        let rejected = app_error(services::create_invoice(db.as_ref(), &payload(json!({ "order_id": order.order.id }))).await);
+        assert!(matches!(rejected, AppError::Conflict(_)));
+
+        let issued = services::issue_invoice(db.as_ref(), &repo, numbering::DEFAULT_TENANT, &invoice_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(issued.invoice.status, "issued");
+        let number = issued.invoice.number.clone().unwrap();
+
+        // Numbers are never reused, even across documents issued back to back
+        let second = services::create_order(
+            db.as_ref(),
+            &config,
+            &user_id,
+            &payload(json!({
+                "currency": "EUR",
+                "lines": [{ "description": "Training", "quantity": 1, "unit_price": { "amount": "50.00", "currency": "EUR" } }],
+            })),
+        )
+        .await
+        .unwrap();
+        let second = services::create_invoice(db.as_ref(), &payload(json!({ "order_id": second.order.id })))
+            .await
+            .unwrap();
+        let second = services::issue_invoice(db.as_ref(), &repo, numbering::DEFAULT_TENANT, &second.invoice.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(second.invoice.number.as_deref(), Some(number.as_str()));
+
+        let payment = |amount: &str| -> RecordPaymentPayload {
+            payload(json!({ "method": "bank_transfer", "amount": { "amount": amount, "currency": "EUR" } }))
+        };
+        let (_, paid) = services::record_payment(db.as_ref(), &invoice_id, services::PAYMENT, &payment("50.00"), &user_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(paid.status, "partially_paid");
+        assert_eq!(paid.amount_paid.minor(), 5_000);
+
+        let rejected =
+            app_error(services::record_payment(db.as_ref(), &invoice_id, services::PAYMENT, &payment("100.00"), &user_id).await);
+        assert!(matches!(rejected, AppError::Validation(_)));
+
+        // Crediting one of the four units leaves 119.00 - 50.00 - 29.75 = 39.25 to pay
+        let credit: CreateCreditNotePayload = payload(json!({
+            "reason": "Unused hour",
+            "lines": [{ "invoice_line_id": issued.lines[0].id, "quantity": 1 }],
+        }));
+        let credited = services::create_credit_note(db.as_ref(), &repo, numbering::DEFAULT_TENANT, &invoice_id, &credit, &user_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(credited.credit_notes.len(), 1);
+        assert_eq!(credited.invoice.balance().minor(), 3_925);
+
+        let (_, settled) = services::record_payment(db.as_ref(), &invoice_id, services::PAYMENT, &payment("39.25"), &user_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(settled.status, "paid");
+        assert!(settled.balance().is_zero());
+
+        let payments = services::list_invoice_payments(db.as_ref(), &invoice_id, Some(&user_id)).await.unwrap().unwrap();
+        assert_eq!(payments.len(), 2);
+        assert!(services::list_invoice_payments(db.as_ref(), &invoice_id, Some("someone-else")).await.unwrap().is_none());
+
+        // Credit notes that cancel an unpaid invoice outright settle it without calling it paid
+        let cancel: CreateCreditNotePayload = payload(json!({
+            "reason": "Training cancelled",
+            "lines": [{ "invoice_line_id": second.lines[0].id, "quantity": 1 }],
+        }));
+        let cancelled =
+            services::create_credit_note(db.as_ref(), &repo, numbering::DEFAULT_TENANT, &second.invoice.id, &cancel, &user_id)
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(cancelled.invoice.status, "credited");
+        assert!(cancelled.invoice.balance().is_zero());
+        let rejected = app_error(
+            services::record_payment(db.as_ref(), &second.invoice.id, services::PAYMENT, &payment("1.00"), &user_id).await,
+        );
+        assert!(matches!(rejected, AppError::Conflict(_)));
+    }
+
+    async fn overdue_invoices_stay_overdue_until_settled(db: Arc<dyn Database>, repo: SqlRepository) {
+        let user_id = sign_up(db.as_ref(), "olive").await;
+        let order: CreateOrderPayload = payload(json!({
+            "currency": "EUR",
+            "lines": [{ "description": "Support", "quantity": 1, "unit_price": { "amount": "100.00", "currency": "EUR" } }],
+        }));
+        let order = services::create_order(db.as_ref(), &config(), &user_id, &order).await.unwrap();
+        let today = chrono::Utc::now().date_naive();
+        let invoice = payload(json!({ "order_id": order.order.id, "due_date": today - chrono::Duration::days(1) }));
+        let invoice_id = services::create_invoice(db.as_ref(), &invoice).await.unwrap().invoice.id;
+        services::issue_invoice(db.as_ref(), &repo, numbering::DEFAULT_TENANT, &invoice_id).await.unwrap().unwrap();
+
+        assert_eq!(services::mark_overdue_invoices(db.as_ref(), today).await.unwrap(), 1);
+
+        let entry = |amount: &str| -> RecordPaymentPayload {
+            payload(json!({ "method": "bank_transfer", "amount": { "amount": amount, "currency": "EUR" } }))
+        };
+        let record = |kind: &'static str, amount: &'static str| {
+            let (db, user_id, invoice_id) = (db.clone(), user_id.clone(), invoice_id.clone());
+            async move {
+                services::record_payment(db.as_ref(), &invoice_id, kind, &entry(amount), &user_id).await
+            }
+        };
+
+        // Paying part of an overdue invoice leaves it overdue rather than merely partially paid
+        let (_, invoice) = record(services::PAYMENT, "40.00").await.unwrap().unwrap();
+        assert_eq!(invoice.status, "overdue");
+        assert_eq!(services::mark_overdue_invoices(db.as_ref(), today).await.unwrap(), 0);
+
+        // Money already taken has to be refunded before the invoice can be voided
+        let rejected = app_error(services::void_invoice(db.as_ref(), &invoice_id).await);
+        assert!(matches!(rejected, AppError::Conflict(_)), "{:?}", rejected);
+
+        let (_, invoice) = record(services::PAYMENT, "60.00").await.unwrap().unwrap();
+        assert_eq!(invoice.status, "paid");
+        let (_, invoice) = record(services::REFUND, "100.00").await.unwrap().unwrap();
+        assert_eq!(invoice.status, "overdue");
+
+        let voided = services::void_invoice(db.as_ref(), &invoice_id).await.unwrap().unwrap();
+        assert_eq!(voided.invoice.status, "void");
+    }
+
+    async fn seeding_is_idempotent(db: Arc<dyn Database>, repo: SqlRepository) {
+        let state = AppState::new(config(), db.clone());
+        let fixture = seed::parse("dev.yaml", include_str!("../fixtures/dev.yaml")).unwrap();
+
+        // The fixture's roles are the built-in ones the migrations already created
+        let first = seed::run(&state, &fixture).await.unwrap();
+        assert_eq!(first.roles, 0);
+        assert!(first.orders > 0 && first.invoices > 0 && first.payments > 0);
+        let markers = count(db.as_ref(), "seed_records").await;
+
+        // Every order step wrote its marker alongside it, so the second run finds them all
+        let second = seed::run(&state, &fixture).await.unwrap();
+        assert_eq!((second.roles, second.users, second.orders, second.invoices, second.payments), (0, 0, 0, 0, 0));
+        assert_eq!(count(db.as_ref(), "seed_records").await, markers);
+
+        // Built-in roles ship with the permissions the guarded routes check
+        let admin = services::fetch_user_by_username(&repo, "admin").await.unwrap().unwrap();
+        let staff = services::fetch_user_by_username(&repo, "sam.staff").await.unwrap().unwrap();
+        assert!(repo.permissions(&admin.id).await.unwrap().contains(&"roles:manage".to_string()));
+        let staff_permissions = repo.permissions(&staff.id).await.unwrap();
+        assert!(staff_permissions.contains(&"payments:manage".to_string()));
+        assert!(!staff_permissions.contains(&"roles:manage".to_string()));
+    }
+}
@@ -0,0 +1,485 @@
+// src/seed.rs
+use super::*;
+use chrono::{Duration, Utc};
+use db::Statement;
+use money::{Currency, Money};
+use repository::RoleRepository;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utils::AppError;
+
+// Created with their grants by migration 0012, so fixtures may assign them without defining them
+const BUILT_IN_ROLES: &[&str] = &["admin", "staff", "customer"];
+
+const ADMIN_ROLE: &str = "admin";
+
+// A declarative dataset, read from YAML or JSON. Every section is optional; records are matched
+// on their natural key (role slug, username, SKU, jurisdiction) or, for orders, on `key`, so
+// loading the same fixture twice changes nothing the second time.
+#[derive(Deserialize)]
+pub struct Fixture {
+    #[serde(default)]
+    pub roles: Vec<CreateRolePayload>,
+    // Applied to every user without settings of their own, including users that signed up
+    pub settings: Option<SettingsFixture>,
+    #[serde(default)]
+    pub users: Vec<UserFixture>,
+    #[serde(default)]
+    pub tax_rates: Vec<tax::TaxRatePayload>,
+    #[serde(default)]
+    pub products: Vec<CreateProductPayload>,
+    #[serde(default)]
+    pub orders: Vec<OrderFixture>,
+}
+
+#[derive(Deserialize)]
+pub struct SettingsFixture {
+    pub theme: String,
+    pub language: String,
+    pub notifications: bool,
+}
+
+#[derive(Deserialize)]
+pub struct UserFixture {
+    #[serde(flatten)]
+    pub account: SignUpPayload,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub settings: Option<SettingsFixture>,
+}
+
+#[derive(Deserialize)]
+pub struct OrderFixture {
+    pub key: String,
+    // Username of a fixture user
+    pub customer: String,
+    pub currency: Currency,
+    pub prices_include_tax: Option<bool>,
+    pub notes: Option<String>,
+    pub lines: Vec<OrderLineFixture>,
+    pub invoice: Option<InvoiceFixture>,
+}
+
+// Catalog lines name a fixture product by SKU; free-text lines carry their own description and price
+#[derive(Deserialize)]
+pub struct OrderLineFixture {
+    pub sku: Option<String>,
+    pub description: Option<String>,
+    pub quantity: i32,
+    pub unit_price: Option<Money>,
+    pub tax_class: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct InvoiceFixture {
+    pub due_in_days: Option<i64>,
+    #[serde(default)]
+    pub issue: bool,
+    #[serde(default)]
+    pub payments: Vec<PaymentFixture>,
+}
+
+#[derive(Deserialize)]
+pub struct PaymentFixture {
+    pub method: String,
+    pub reference: Option<String>,
+    // Omitted to settle whatever is outstanding, which keeps the fixture valid under any tax settings
+    pub amount: Option<Money>,
+}
+
+// Counts of what this run created; everything already present is left alone and not counted
+#[derive(Debug, Default, Serialize)]
+pub struct SeedReport {
+    pub roles: usize,
+    pub users: usize,
+    pub role_assignments: usize,
+    pub settings: u64,
+    pub tax_rates: usize,
+    pub products: usize,
+    pub orders: usize,
+    pub invoices: usize,
+    pub payments: usize,
+}
+
+pub fn parse(path: &str, contents: &str) -> Result<Fixture, Box<dyn std::error::Error>> {
+    if path.ends_with(".json") {
+        Ok(serde_json::from_str(contents)?)
+    } else if path.ends_with(".yaml") || path.ends_with(".yml") {
+        Ok(serde_yaml::from_str(contents)?)
+    } else {
+        Err(Box::new(AppError::Validation(format!(
+            "Fixture `{}` must be a .json, .yaml or .yml file",
+            path
+        ))))
+    }
+}
+
+fn normalize_sku(sku: &str) -> String {
+    sku.trim().to_uppercase()
+}
+
+// Cross-references are checked before anything is written, and every broken one is reported
+fn validate(fixture: &Fixture) -> Result<(), AppError> {
+    let mut problems = Vec::new();
+
+    let roles: Vec<&str> = BUILT_IN_ROLES
+        .iter()
+        .copied()
+        .chain(fixture.roles.iter().map(|role| role.slug.as_str()))
+        .collect();
+    for role in &fixture.roles {
+        if !services::is_valid_role_slug(&role.slug) {
+            problems.push(format!("role `{}` is not a valid slug", role.slug));
+        }
+    }
+    for user in &fixture.users {
+        if let Err(e) = services::validate_sign_up(&user.account) {
+            problems.push(format!("user `{}`: {}", user.account.username, e));
+        }
+        for role in &user.roles {
+            if !roles.contains(&role.as_str()) {
+                problems.push(format!("user `{}` has undefined role `{}`", user.account.username, role));
+            }
+        }
+    }
+
+    let has_admin = fixture
+        .users
+        .iter()
+        .any(|user| user.roles.iter().any(|role| role == ADMIN_ROLE));
+    for order in &fixture.orders {
+        if !fixture.users.iter().any(|user| user.account.username == order.customer) {
+            problems.push(format!("order `{}` has undefined customer `{}`", order.key, order.customer));
+        }
+        for sku in order.lines.iter().filter_map(|line| line.sku.as_deref()) {
+            if !fixture
+                .products
+                .iter()
+                .any(|product| normalize_sku(&product.sku) == normalize_sku(sku))
+            {
+                problems.push(format!("order `{}` has undefined product `{}`", order.key, sku));
+            }
+        }
+        let has_payments = order.invoice.as_ref().is_some_and(|invoice| !invoice.payments.is_empty());
+        if has_payments && !has_admin {
+            problems.push(format!("order `{}` records payments but no user has the admin role", order.key));
+        }
+        if has_payments && !order.invoice.as_ref().is_some_and(|invoice| invoice.issue) {
+            problems.push(format!("order `{}` records payments against an invoice it does not issue", order.key));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Validation(format!("Invalid fixture: {}", problems.join("; "))))
+    }
+}
+
+async fn recorded(state: &AppState, seed_key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let statement = Statement::new("SELECT entity_id FROM seed_records WHERE seed_key = ?1").bind(seed_key);
+
+    match state.db.fetch_optional(statement).await? {
+        Some(row) => Ok(Some(row.get("entity_id")?)),
+        None => Ok(None),
+    }
+}
+
+// Goes in the same batch as the write it marks, so a step is either done and recorded or neither
+fn record(seed_key: &str, entity: &str, entity_id: &str) -> Statement {
+    Statement::new("INSERT INTO seed_records (seed_key, entity, entity_id, created_at) VALUES (?1, ?2, ?3, ?4)")
+        .bind(seed_key)
+        .bind(entity)
+        .bind(entity_id)
+        .bind(Utc::now().naive_utc())
+}
+
+async fn seed_roles(
+    state: &AppState,
+    fixture: &Fixture,
+    report: &mut SeedReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for role in &fixture.roles {
+        if state.repo.role(&role.slug).await?.is_none() {
+            services::create_role(state.db.as_ref(), role).await?;
+            report.roles += 1;
+        }
+    }
+
+    Ok(())
+}
+
+// Accounts are written with the same user, profile and role statements as sign-up.
+// Returns username → user id for every fixture user, new or existing.
+async fn seed_users(
+    state: &AppState,
+    fixture: &Fixture,
+    report: &mut SeedReport,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let mut user_ids = HashMap::new();
+
+    for user in &fixture.users {
+        let username = &user.account.username;
+        let existing = services::fetch_user_by_username(state.repo.as_ref(), username).await?;
+
+        let user_id = match existing {
+            Some(existing) => {
+                let held = services::fetch_user_role_slugs(state.repo.as_ref(), &existing.id).await?;
+                for role in user.roles.iter().filter(|role| !held.contains(role)) {
+                    services::assign_role(state.db.as_ref(), &existing.id, role).await?;
+                    report.role_assignments += 1;
+                }
+                existing.id
+            }
+            None => {
+                let user_id = utils::generate_uuid();
+                let hashed_password = services::hash_password(&user.account.password)
+                    .await
+                    .map_err(|e| AppError::InternalServerError(format!("Failed to hash password: {}", e)))?;
+
+                let mut statements = vec![
+                    services::create_user_statement(&user.account, &hashed_password, &user_id)?,
+                    services::create_profile_statement(&user.account, &user_id)?,
+                ];
+                statements.extend(user.roles.iter().map(|role| services::assign_role_statement(&user_id, role)));
+                state.db.batch(statements).await?;
+                report.role_assignments += user.roles.len();
+
+                report.users += 1;
+                user_id
+            }
+        };
+
+        user_ids.insert(username.clone(), user_id);
+    }
+
+    Ok(user_ids)
+}
+
+async fn seed_settings(
+    state: &AppState,
+    fixture: &Fixture,
+    user_ids: &HashMap<String, String>,
+    report: &mut SeedReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for user in &fixture.users {
+        let (Some(settings), Some(user_id)) = (&user.settings, user_ids.get(&user.account.username)) else {
+            continue;
+        };
+        let statement = Statement::new(
+            "INSERT INTO user_settings (user_id, theme, language, notifications) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (user_id) DO NOTHING",
+        )
+        .bind(user_id.as_str())
+        .bind(settings.theme.as_str())
+        .bind(settings.language.as_str())
+        .bind(settings.notifications);
+
+        report.settings += state.db.execute(statement).await?;
+    }
+
+    if let Some(defaults) = &fixture.settings {
+        let statement = Statement::new(
+            "INSERT INTO user_settings (user_id, theme, language, notifications)
+            SELECT id, ?1, ?2, ?3 FROM users u
+            WHERE NOT EXISTS (SELECT 1 FROM user_settings s WHERE s.user_id = u.id)",
+        )
+        .bind(defaults.theme.as_str())
+        .bind(defaults.language.as_str())
+        .bind(defaults.notifications);
+
+        report.settings += state.db.execute(statement).await?;
+    }
+
+    Ok(())
+}
+
+async fn seed_tax_rates(
+    state: &AppState,
+    fixture: &Fixture,
+    report: &mut SeedReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for rate in &fixture.tax_rates {
+        let statement = Statement::new(
+            "SELECT id FROM tax_rates
+            WHERE UPPER(country) = UPPER(?1)
+              AND COALESCE(UPPER(state), '') = COALESCE(UPPER(?2), '')
+              AND tax_class = ?3",
+        )
+        .bind(rate.country.as_str())
+        .bind(rate.state.clone())
+        .bind(rate.tax_class.as_str());
+
+        if state.db.fetch_optional(statement).await?.is_none() {
+            tax::create_rate(state.db.as_ref(), rate).await?;
+            report.tax_rates += 1;
+        }
+    }
+
+    Ok(())
+}
+
+// Returns SKU → product id for every fixture product, new or existing
+async fn seed_products(
+    state: &AppState,
+    fixture: &Fixture,
+    report: &mut SeedReport,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let mut product_ids = HashMap::new();
+
+    for product in &fixture.products {
+        let sku = normalize_sku(&product.sku);
+        let statement = Statement::new("SELECT id FROM products WHERE sku = ?1").bind(sku.as_str());
+
+        let product_id = match state.db.fetch_optional(statement).await? {
+            Some(row) => row.get("id")?,
+            None => {
+                let created = products::create_product(state.repo.as_ref(), product).await?;
+                report.products += 1;
+                created.id
+            }
+        };
+
+        product_ids.insert(sku, product_id);
+    }
+
+    Ok(product_ids)
+}
+
+// Each step of an order's lifecycle is recorded in the batch that performs it, so a run interrupted
+// half way through an order resumes where it stopped instead of placing the order again
+async fn seed_order(
+    state: &AppState,
+    order: &OrderFixture,
+    user_ids: &HashMap<String, String>,
+    product_ids: &HashMap<String, String>,
+    recorded_by: Option<&str>,
+    report: &mut SeedReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let order_key = format!("order:{}", order.key);
+    let order_id = match recorded(state, &order_key).await? {
+        Some(order_id) => order_id,
+        None => {
+            let payload = CreateOrderPayload {
+                currency: order.currency,
+                prices_include_tax: order.prices_include_tax,
+                notes: order.notes.clone(),
+                lines: order
+                    .lines
+                    .iter()
+                    .map(|line| OrderLinePayload {
+                        product_id: line.sku.as_deref().and_then(|sku| product_ids.get(&normalize_sku(sku)).cloned()),
+                        description: line.description.clone(),
+                        quantity: line.quantity,
+                        unit_price: line.unit_price,
+                        tax_class: line.tax_class.clone(),
+                    })
+                    .collect(),
+            };
+            let customer_id = &user_ids[&order.customer];
+
+            let (order_id, mut statements) =
+                services::create_order_statements(state.db.as_ref(), &state.config, customer_id, &payload).await?;
+            statements.push(record(&order_key, "order", &order_id));
+            state.db.batch(statements).await?;
+            report.orders += 1;
+            order_id
+        }
+    };
+
+    let Some(invoice) = &order.invoice else {
+        return Ok(());
+    };
+
+    let invoice_key = format!("invoice:{}", order.key);
+    let invoice_id = match recorded(state, &invoice_key).await? {
+        Some(invoice_id) => invoice_id,
+        None => {
+            let payload = CreateInvoicePayload {
+                order_id: order_id.clone(),
+                due_date: invoice
+                    .due_in_days
+                    .map(|days| Utc::now().date_naive() + Duration::days(days)),
+            };
+
+            let (invoice_id, mut statements) = services::create_invoice_statements(state.db.as_ref(), &payload).await?;
+            statements.push(record(&invoice_key, "invoice", &invoice_id));
+            state.db.batch(statements).await?;
+            report.invoices += 1;
+            invoice_id
+        }
+    };
+
+    if !invoice.issue {
+        return Ok(());
+    }
+
+    let issue_key = format!("invoice-issued:{}", order.key);
+    if recorded(state, &issue_key).await?.is_none() {
+        let mut statements =
+            services::issue_invoice_statements(state.db.as_ref(), state.repo.as_ref(), numbering::DEFAULT_TENANT, &invoice_id)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("Seeded invoice {} has disappeared", invoice_id)))?;
+        statements.push(record(&issue_key, "invoice", &invoice_id));
+        state.db.batch(statements).await?;
+    }
+
+    for (index, payment) in invoice.payments.iter().enumerate() {
+        let payment_key = format!("payment:{}:{}", order.key, index);
+        if recorded(state, &payment_key).await?.is_some() {
+            continue;
+        }
+
+        let amount = match payment.amount {
+            Some(amount) => amount,
+            None => services::fetch_invoice_details(state.db.as_ref(), &invoice_id, None)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("Seeded invoice {} has disappeared", invoice_id)))?
+                .invoice
+                .balance(),
+        };
+        let payload = RecordPaymentPayload {
+            method: payment.method.clone(),
+            reference: payment.reference.clone(),
+            amount,
+            received_at: None,
+        };
+        let recorded_by = recorded_by.unwrap_or_default();
+
+        let (payment_id, mut statements) =
+            services::record_payment_statements(state.db.as_ref(), &invoice_id, services::PAYMENT, &payload, recorded_by)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("Seeded invoice {} has disappeared", invoice_id)))?;
+        statements.push(record(&payment_key, "payment", &payment_id));
+        state.db.batch(statements).await?;
+        report.payments += 1;
+    }
+
+    Ok(())
+}
+
+pub async fn run(state: &AppState, fixture: &Fixture) -> Result<SeedReport, Box<dyn std::error::Error>> {
+    validate(fixture)?;
+
+    let mut report = SeedReport::default();
+
+    seed_roles(state, fixture, &mut report).await?;
+    let user_ids = seed_users(state, fixture, &mut report).await?;
+    seed_settings(state, fixture, &user_ids, &mut report).await?;
+    seed_tax_rates(state, fixture, &mut report).await?;
+    let product_ids = seed_products(state, fixture, &mut report).await?;
+
+    // Payments are recorded in the name of the first fixture admin
+    let recorded_by = fixture
+        .users
+        .iter()
+        .find(|user| user.roles.iter().any(|role| role == ADMIN_ROLE))
+        .and_then(|user| user_ids.get(&user.account.username))
+        .map(String::as_str);
+
+    for order in &fixture.orders {
+        seed_order(state, order, &user_ids, &product_ids, recorded_by, &mut report).await?;
+    }
+
+    Ok(report)
+}
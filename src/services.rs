@@ -1,73 +1,79 @@
 // src/services.rs
 use super::*;
-use sqlx::{PgPool};
+use db::{Database, DbError, Row, Statement};
+use utils::AppError;
+use money::{Currency, Money};
+use numbering::SequenceStore;
+use repository::{IdentityRepository, RoleRepository};
+use serde::{Deserialize, Serialize};
+use tax::{LineTax, TaxBreakdown, TaxRounding, TaxableLine};
 use uuid::Uuid;
 use chrono::{Utc, NaiveDate};
-use argon2::{Argon2, PasswordHasher};
+use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 
-pub async fn create_user<'a, E>(
-    tx: &mut sqlx::Transaction<'a, sqlx::Postgres>,
+// Writes that must land together go out as one `Database::batch`. D1 cannot hold a lock between
+// reading a row and writing it back, so a batch carries its own guards instead (see
+// `DbError::is_guard_violation`); a tripped guard becomes a conflict the client can retry.
+async fn commit(db: &dyn Database, statements: Vec<Statement>, conflict: &str) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+    match db.batch(statements).await {
+        Ok(affected) => Ok(affected),
+        Err(e) if e.is_guard_violation() => Err(Box::new(AppError::Conflict(conflict.to_string()))),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+fn optional_owner(statement: Statement, owner_id: Option<&str>) -> Statement {
+    match owner_id {
+        Some(owner_id) => statement.push_bind(" AND user_id = ?", owner_id),
+        None => statement,
+    }
+}
+
+// Checked before anything is hashed or written, so a malformed sign-up is a 400 naming the field
+pub fn validate_sign_up(payload: &SignUpPayload) -> Result<(), AppError> {
+    if payload.username.trim().is_empty() {
+        return Err(AppError::Validation("`username` is required".to_string()));
+    }
+    if payload.password.chars().count() < 8 {
+        return Err(AppError::Validation("`password` must be at least 8 characters".to_string()));
+    }
+    if payload.email.as_deref().is_some_and(|email| !email.contains('@')) {
+        return Err(AppError::Validation("`email` must be an email address".to_string()));
+    }
+    if NaiveDate::parse_from_str(&payload.date_of_birth, "%Y-%m-%d").is_err() {
+        return Err(AppError::Validation("`date_of_birth` must be formatted as YYYY-MM-DD".to_string()));
+    }
+
+    Ok(())
+}
+
+// Account credentials only; everything else about the person lives on the profile
+pub fn create_user_statement(
     payload: &SignUpPayload,
     hashed_password: &str,
     user_id: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let date_of_birth = NaiveDate::parse_from_str(&payload.date_of_birth, "%Y-%m-%d")?;
+) -> Result<Statement, Box<dyn std::error::Error>> {
+    let now = Utc::now().naive_utc();
 
-    sqlx::query!(
-        r#"INSERT INTO users (
-            id,
-            username,
-            email,
-            password,
-            telephone,
-            salutation,
-            first_name,
-            middle_name,
-            last_name,
-            gender,
-            address_line_1,
-            address_line_2,
-            city,
-            state,
-            country,
-            date_of_birth,
-            configuration,
-            created_at,
-            updated_at
-        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)"#,
-        user_id,
-        payload.username,
-        payload.email.as_ref(),
-        hashed_password,
-        payload.telephone.as_ref(),
-        payload.salutation.as_ref(),
-        payload.first_name.as_ref(),
-        payload.middle_name.as_ref(),
-        payload.last_name.as_ref(),
-        payload.gender.as_ref(),
-        payload.address_line_1.as_ref(),
-        payload.address_line_2.as_ref(),
-        payload.city.as_ref(),
-        payload.state.as_ref(),
-        payload.country.as_ref(),
-        date_of_birth,
-        payload.configuration.as_ref(),
-        Utc::now().naive_utc(),
-        Utc::now().naive_utc(),
+    Ok(Statement::new(
+        "INSERT INTO users (id, username, email, password, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
     )
-    .execute(tx)
-    .await?;
-
-    Ok(())
+    .bind(user_id)
+    .bind(payload.username.as_str())
+    .bind(payload.email.clone())
+    .bind(hashed_password)
+    .bind(now))
 }
 
-pub async fn create_profile(
-    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+pub fn create_profile_statement(
     payload: &SignUpPayload,
     user_id: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    sqlx::query!(
-        r#"INSERT INTO profiles (
+) -> Result<Statement, Box<dyn std::error::Error>> {
+    let date_of_birth = NaiveDate::parse_from_str(&payload.date_of_birth, "%Y-%m-%d")?;
+    let now = Utc::now().naive_utc();
+
+    Ok(Statement::new(
+        "INSERT INTO profiles (
             id,
             user_id,
             telephone,
@@ -85,126 +91,2369 @@ pub async fn create_profile(
             configuration,
             created_at,
             updated_at
-        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)"#,
-        Uuid::new_v4().to_string(),
-        user_id,
-        payload.telephone.as_ref(),
-        payload.salutation.as_ref(),
-        payload.first_name.as_ref(),
-        payload.middle_name.as_ref(),
-        payload.last_name.as_ref(),
-        payload.gender.as_ref(),
-        payload.address_line_1.as_ref(),
-        payload.address_line_2.as_ref(),
-        payload.city.as_ref(),
-        payload.state.as_ref(),
-        payload.country.as_ref(),
-        NaiveDate::parse_from_str(&payload.date_of_birth, "%Y-%m-%d")?,
-        payload.configuration.as_ref(),
-        Utc::now().naive_utc(),
-        Utc::now().naive_utc(),
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?16)",
     )
-    .execute(tx)
-    .await?;
+    .bind(Uuid::new_v4().to_string())
+    .bind(user_id)
+    .bind(payload.telephone.clone())
+    .bind(payload.salutation.clone())
+    .bind(payload.first_name.clone())
+    .bind(payload.middle_name.clone())
+    .bind(payload.last_name.clone())
+    .bind(payload.gender.clone())
+    .bind(payload.address_line_1.clone())
+    .bind(payload.address_line_2.clone())
+    .bind(payload.city.clone())
+    .bind(payload.state.clone())
+    .bind(payload.country.clone())
+    .bind(date_of_birth)
+    .bind(payload.configuration.clone())
+    .bind(now))
+}
 
-    Ok(())
+pub fn assign_role_statement(user_id: &str, role_slug: &str) -> Statement {
+    Statement::new("INSERT INTO users_roles (user_id, role_slug) VALUES (?1, ?2)")
+        .bind(user_id)
+        .bind(role_slug)
 }
 
 pub async fn assign_role(
+    db: &dyn Database,
     user_id: &str,
     role_slug: &str,
-    pool: &PgPool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    sqlx::query!(
-        r#"INSERT INTO users_roles (user_id, role_slug)
-         VALUES ($1, $2)"#,
-        user_id,
-        role_slug,
+    db.execute(assign_role_statement(user_id, role_slug)).await?;
+
+    Ok(())
+}
+
+pub async fn fetch_user_role_slugs(
+    repo: &dyn IdentityRepository,
+    user_id: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    Ok(repo.role_slugs(user_id).await?)
+}
+
+pub async fn fetch_user_permissions(
+    repo: &dyn IdentityRepository,
+    user_id: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    Ok(repo.permissions(user_id).await?)
+}
+
+pub async fn fetch_role_permissions(
+    db: &dyn Database,
+    role_slug: &str,
+) -> Result<Vec<models::Permission>, Box<dyn std::error::Error>> {
+    let statement = Statement::new(
+        "SELECT p.slug, p.description, p.created_at FROM permissions p
+        JOIN role_permissions rp ON rp.permission_slug = p.slug
+        WHERE rp.role_slug = ?1
+        ORDER BY p.slug",
     )
-    .execute(pool)
-    .await?;
+    .bind(role_slug);
+
+    let permissions = db
+        .fetch_all(statement)
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(models::Permission {
+                slug: row.get("slug")?,
+                description: row.get("description")?,
+                created_at: row.get_datetime("created_at")?,
+            })
+        })
+        .collect::<Result<_, DbError>>()?;
+
+    Ok(permissions)
+}
+
+// Permission slugs take the form `resource:action`, e.g. `orders:read`
+pub fn is_valid_permission_slug(slug: &str) -> bool {
+    let valid_part = |part: &str| {
+        !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    };
+
+    match slug.split_once(':') {
+        Some((resource, action)) => valid_part(resource) && valid_part(action),
+        None => false,
+    }
+}
+
+pub async fn grant_permission(
+    db: &dyn Database,
+    role_slug: &str,
+    payload: &GrantPermissionPayload,
+) -> Result<(), Box<dyn std::error::Error>> {
+    db.batch(grant_permission_statements(role_slug, payload)).await?;
+
+    Ok(())
+}
+
+// Creates the permission on first use, then grants it; both inserts are no-ops when already present
+pub fn grant_permission_statements(role_slug: &str, payload: &GrantPermissionPayload) -> Vec<Statement> {
+    vec![
+        Statement::new(
+            "INSERT INTO permissions (slug, description, created_at)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT (slug) DO NOTHING",
+        )
+        .bind(payload.permission.as_str())
+        .bind(payload.description.clone())
+        .bind(Utc::now().naive_utc()),
+        Statement::new(
+            "INSERT INTO role_permissions (role_slug, permission_slug)
+            VALUES (?1, ?2)
+            ON CONFLICT (role_slug, permission_slug) DO NOTHING",
+        )
+        .bind(role_slug)
+        .bind(payload.permission.as_str()),
+    ]
+}
+
+pub async fn revoke_permission(
+    db: &dyn Database,
+    role_slug: &str,
+    permission_slug: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let statement = Statement::new("DELETE FROM role_permissions WHERE role_slug = ?1 AND permission_slug = ?2")
+        .bind(role_slug)
+        .bind(permission_slug);
+
+    Ok(db.execute(statement).await? > 0)
+}
+
+pub async fn role_exists(
+    repo: &dyn RoleRepository,
+    role_slug: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    Ok(repo.role(role_slug).await?.is_some())
+}
+
+pub enum DeleteRoleOutcome {
+    Deleted,
+    NotFound,
+    InUse(i64),
+    ReassignTargetNotFound,
+}
+
+pub fn is_valid_role_slug(slug: &str) -> bool {
+    !slug.is_empty() && slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+pub async fn list_roles(repo: &dyn RoleRepository) -> Result<Vec<RoleDetails>, Box<dyn std::error::Error>> {
+    Ok(repo.list_roles().await?)
+}
+
+pub async fn fetch_role(
+    repo: &dyn RoleRepository,
+    slug: &str,
+) -> Result<Option<RoleDetails>, Box<dyn std::error::Error>> {
+    Ok(repo.role(slug).await?)
+}
+
+pub async fn create_role(
+    db: &dyn Database,
+    payload: &CreateRolePayload,
+) -> Result<(), Box<dyn std::error::Error>> {
+    db.execute(create_role_statement(payload)).await?;
 
     Ok(())
 }
 
+pub fn create_role_statement(payload: &CreateRolePayload) -> Statement {
+    Statement::new("INSERT INTO roles (slug, name, description, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?4)")
+        .bind(payload.slug.as_str())
+        .bind(payload.name.as_str())
+        .bind(payload.description.clone())
+        .bind(Utc::now().naive_utc())
+}
+
+pub async fn update_role(
+    db: &dyn Database,
+    repo: &dyn RoleRepository,
+    slug: &str,
+    payload: &UpdateRolePayload,
+) -> Result<Option<RoleDetails>, Box<dyn std::error::Error>> {
+    let mut statement = Statement::new("UPDATE roles SET updated_at = ?1").bind(Utc::now().naive_utc());
+    if let Some(name) = &payload.name {
+        statement = statement.push_bind(", name = ?", name.as_str());
+    }
+    if let Some(description) = &payload.description {
+        statement = statement.push_bind(", description = ?", description.as_str());
+    }
+
+    if db.execute(statement.push_bind(" WHERE slug = ?", slug)).await? == 0 {
+        return Ok(None);
+    }
+
+    Ok(repo.role(slug).await?)
+}
+
+// A holder granted the role between the count and the batch still blocks the delete: the
+// users_roles foreign key fails the batch rather than leaving an orphaned assignment
+pub async fn delete_role(
+    db: &dyn Database,
+    repo: &dyn RoleRepository,
+    slug: &str,
+    reassign_to: Option<&str>,
+) -> Result<DeleteRoleOutcome, Box<dyn std::error::Error>> {
+    if repo.role(slug).await?.is_none() {
+        return Ok(DeleteRoleOutcome::NotFound);
+    }
+
+    let holders: i64 = db
+        .fetch_one(Statement::new("SELECT COUNT(*) AS holders FROM users_roles WHERE role_slug = ?1").bind(slug))
+        .await?
+        .get("holders")?;
+
+    let mut statements = Vec::new();
+    if holders > 0 {
+        let target = match reassign_to {
+            Some(target) if target != slug => target,
+            _ => return Ok(DeleteRoleOutcome::InUse(holders)),
+        };
+
+        if repo.role(target).await?.is_none() {
+            return Ok(DeleteRoleOutcome::ReassignTargetNotFound);
+        }
+
+        statements.push(
+            Statement::new(
+                "INSERT INTO users_roles (user_id, role_slug)
+                SELECT user_id, ?1 FROM users_roles WHERE role_slug = ?2
+                ON CONFLICT (user_id, role_slug) DO NOTHING",
+            )
+            .bind(target)
+            .bind(slug),
+        );
+        statements.push(Statement::new("DELETE FROM users_roles WHERE role_slug = ?1").bind(slug));
+    }
+
+    statements.push(Statement::new("DELETE FROM role_permissions WHERE role_slug = ?1").bind(slug));
+    statements.push(Statement::new("DELETE FROM roles WHERE slug = ?1").bind(slug));
+
+    db.batch(statements).await?;
+
+    Ok(DeleteRoleOutcome::Deleted)
+}
+
+pub async fn revoke_role(
+    db: &dyn Database,
+    user_id: &str,
+    role_slug: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let statement = Statement::new("DELETE FROM users_roles WHERE user_id = ?1 AND role_slug = ?2")
+        .bind(user_id)
+        .bind(role_slug);
+
+    Ok(db.execute(statement).await? > 0)
+}
+
+pub async fn fetch_user_with_roles(
+    db: &dyn Database,
+    user_id: &str,
+) -> Result<Option<UserWithRoles>, Box<dyn std::error::Error>> {
+    let user = db
+        .fetch_optional(Statement::new("SELECT id, username, email FROM users WHERE id = ?1").bind(user_id))
+        .await?;
+
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(None),
+    };
+
+    let roles = db
+        .fetch_all(
+            Statement::new(
+                "SELECT r.slug, r.name, r.description FROM roles r
+                JOIN users_roles ur ON ur.role_slug = r.slug
+                WHERE ur.user_id = ?1
+                ORDER BY r.slug",
+            )
+            .bind(user_id),
+        )
+        .await?
+        .iter()
+        .map(repository::role_from_row)
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some(UserWithRoles {
+        id: user.get("id")?,
+        username: user.get("username")?,
+        email: user.get("email")?,
+        roles,
+    }))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DirectorySort {
+    Username,
+    Email,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl DirectorySort {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "username" => Some(DirectorySort::Username),
+            "email" => Some(DirectorySort::Email),
+            "created_at" => Some(DirectorySort::CreatedAt),
+            "updated_at" => Some(DirectorySort::UpdatedAt),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            DirectorySort::Username => "username",
+            DirectorySort::Email => "email",
+            DirectorySort::CreatedAt => "created_at",
+            DirectorySort::UpdatedAt => "updated_at",
+        }
+    }
+
+    // Only expressions backed by an index on users are sortable; email sorts through
+    // idx_users_email_sort, which indexes this exact COALESCE
+    fn expression(self) -> &'static str {
+        match self {
+            DirectorySort::Username => "u.username",
+            DirectorySort::Email => "COALESCE(u.email, '')",
+            DirectorySort::CreatedAt => "u.created_at",
+            DirectorySort::UpdatedAt => "u.updated_at",
+        }
+    }
+
+    fn key(self, row: &DirectoryRow) -> String {
+        match self {
+            DirectorySort::Username => row.username.clone(),
+            DirectorySort::Email => row.email.clone().unwrap_or_default(),
+            DirectorySort::CreatedAt => row.created_at.format("%Y-%m-%dT%H:%M:%S%.f").to_string(),
+            DirectorySort::UpdatedAt => row.updated_at.format("%Y-%m-%dT%H:%M:%S%.f").to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct DirectoryCursor {
+    sort: String,
+    descending: bool,
+    key: String,
+    id: String,
+    backwards: bool,
+}
+
+impl DirectoryCursor {
+    fn encode(&self) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        use base64::Engine;
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(value).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+struct DirectoryRow {
+    id: String,
+    username: String,
+    email: Option<String>,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+}
+
+impl DirectoryRow {
+    fn from_row(row: &Row) -> Result<Self, DbError> {
+        Ok(DirectoryRow {
+            id: row.get("id")?,
+            username: row.get("username")?,
+            email: row.get("email")?,
+            created_at: row.get_datetime("created_at")?,
+            updated_at: row.get_datetime("updated_at")?,
+        })
+    }
+}
+
+pub struct DirectoryParams {
+    query: UserDirectoryQuery,
+    sort: DirectorySort,
+    descending: bool,
+    limit: i64,
+    cursor: Option<DirectoryCursor>,
+}
+
+pub fn directory_params(query: UserDirectoryQuery) -> Result<DirectoryParams, String> {
+    let sort = match query.sort.as_deref() {
+        Some(sort) => DirectorySort::parse(sort).ok_or_else(|| format!("Cannot sort by `{}`", sort))?,
+        None => DirectorySort::CreatedAt,
+    };
+
+    let descending = match query.order.as_deref() {
+        None | Some("desc") => true,
+        Some("asc") => false,
+        Some(order) => return Err(format!("Invalid sort order `{}`", order)),
+    };
+
+    let cursor = match query.cursor.as_deref() {
+        Some(cursor) => {
+            let cursor = DirectoryCursor::decode(cursor).ok_or("Invalid cursor")?;
+            if cursor.sort != sort.name() || cursor.descending != descending {
+                return Err("Cursor does not match the requested sort".to_string());
+            }
+            if matches!(sort, DirectorySort::CreatedAt | DirectorySort::UpdatedAt)
+                && chrono::NaiveDateTime::parse_from_str(&cursor.key, "%Y-%m-%dT%H:%M:%S%.f").is_err()
+            {
+                return Err("Invalid cursor".to_string());
+            }
+            Some(cursor)
+        }
+        None => None,
+    };
+
+    Ok(DirectoryParams {
+        limit: query.limit.unwrap_or(25).clamp(1, 100),
+        query,
+        sort,
+        descending,
+        cursor,
+    })
+}
+
+fn push_directory_filters(mut statement: Statement, query: &UserDirectoryQuery) -> Statement {
+    statement = statement.push(" WHERE 1 = 1");
+
+    if let Some(role) = &query.role {
+        statement = statement.push_bind(
+            " AND EXISTS (SELECT 1 FROM users_roles ur WHERE ur.user_id = u.id AND ur.role_slug = ?)",
+            role.as_str(),
+        );
+    }
+
+    if let Some(country) = &query.country {
+        statement = statement.push_bind(" AND p.country = ?", country.as_str());
+    }
+
+    if let Some(city) = &query.city {
+        statement = statement.push_bind(" AND p.city = ?", city.as_str());
+    }
+
+    if let Some(gender) = &query.gender {
+        statement = statement.push_bind(" AND p.gender = ?", gender.as_str());
+    }
+
+    if let Some(created_from) = query.created_from {
+        statement = statement.push_bind(" AND u.created_at >= ?", created_from);
+    }
+
+    if let Some(created_to) = query.created_to {
+        statement = statement.push_bind(" AND u.created_at < ?", created_to);
+    }
+
+    if let Some(q) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        let columns = ["u.username", "u.email", "p.first_name", "p.middle_name", "p.last_name"];
+        let clause = columns
+            .iter()
+            .map(|column| format!("LOWER({}) LIKE ? ESCAPE '\\'", column))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+
+        statement = statement.push_bind(&format!(" AND ({})", clause), db::contains_pattern(q));
+    }
+
+    statement
+}
+
+pub async fn list_users(
+    db: &dyn Database,
+    params: &DirectoryParams,
+) -> Result<UserDirectoryPage, Box<dyn std::error::Error>> {
+    let count = push_directory_filters(
+        Statement::new("SELECT COUNT(*) AS total FROM users u LEFT JOIN profiles p ON p.user_id = u.id"),
+        &params.query,
+    );
+    let total: i64 = db.fetch_one(count).await?.get("total")?;
+
+    let expression = params.sort.expression();
+    let backwards = params.cursor.as_ref().is_some_and(|cursor| cursor.backwards);
+    // Walking backwards flips the comparison and ordering; the page is reversed afterwards
+    let descending = params.descending != backwards;
+
+    let mut select = push_directory_filters(
+        Statement::new(
+            "SELECT u.id, u.username, u.email, u.created_at, u.updated_at FROM users u LEFT JOIN profiles p ON p.user_id = u.id",
+        ),
+        &params.query,
+    );
+
+    if let Some(cursor) = &params.cursor {
+        let key: db::Value = match params.sort {
+            DirectorySort::CreatedAt | DirectorySort::UpdatedAt => {
+                chrono::NaiveDateTime::parse_from_str(&cursor.key, "%Y-%m-%dT%H:%M:%S%.f")?.into()
+            }
+            _ => cursor.key.clone().into(),
+        };
+        // Spelled out rather than as a row-value comparison, which not every backend accepts
+        let operator = if descending { "<" } else { ">" };
+        select = select
+            .push_bind(&format!(" AND ({} {} ?", expression, operator), key.clone())
+            .push_bind(&format!(" OR ({} = ?", expression), key)
+            .push_bind(&format!(" AND u.id {} ?))", operator), cursor.id.as_str());
+    }
+
+    let direction = if descending { "DESC" } else { "ASC" };
+    let select = select
+        .push(&format!(" ORDER BY {} {}, u.id {}", expression, direction, direction))
+        .push_bind(" LIMIT ?", params.limit + 1);
+
+    let mut rows = db
+        .fetch_all(select)
+        .await?
+        .iter()
+        .map(DirectoryRow::from_row)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let has_more = rows.len() as i64 > params.limit;
+    rows.truncate(params.limit as usize);
+    if backwards {
+        rows.reverse();
+    }
+
+    let cursor_for = |row: &DirectoryRow, backwards: bool| {
+        DirectoryCursor {
+            sort: params.sort.name().to_string(),
+            descending: params.descending,
+            key: params.sort.key(row),
+            id: row.id.clone(),
+            backwards,
+        }
+        .encode()
+    };
+
+    let has_next = if backwards { true } else { has_more };
+    let has_prev = if backwards { has_more } else { params.cursor.is_some() };
+
+    let next_cursor = rows.last().filter(|_| has_next).map(|row| cursor_for(row, false));
+    let prev_cursor = rows.first().filter(|_| has_prev).map(|row| cursor_for(row, true));
+
+    // One bound placeholder per user on the page, since array parameters are Postgres-only
+    let mut roles = Vec::new();
+    if !rows.is_empty() {
+        let mut statement = Statement::new(
+            "SELECT ur.user_id, r.slug, r.name, r.description FROM users_roles ur
+            JOIN roles r ON r.slug = ur.role_slug
+            WHERE ur.user_id IN (",
+        );
+        for (i, row) in rows.iter().enumerate() {
+            statement = statement.push_bind(if i == 0 { "?" } else { ", ?" }, row.id.as_str());
+        }
+        for row in db.fetch_all(statement.push(") ORDER BY r.slug")).await? {
+            roles.push((row.get::<String>("user_id")?, repository::role_from_row(&row)?));
+        }
+    }
+
+    let items = rows
+        .into_iter()
+        .map(|row| {
+            let (held, rest): (Vec<_>, Vec<_>) = roles.drain(..).partition(|(user_id, _)| *user_id == row.id);
+            roles = rest;
+            UserWithRoles {
+                id: row.id,
+                username: row.username,
+                email: row.email,
+                roles: held.into_iter().map(|(_, role)| role).collect(),
+            }
+        })
+        .collect();
+
+    Ok(UserDirectoryPage {
+        items,
+        total,
+        next_cursor,
+        prev_cursor,
+    })
+}
+
+pub async fn fetch_user_by_username(
+    repo: &dyn IdentityRepository,
+    username: &str,
+) -> Result<Option<models::User>, Box<dyn std::error::Error>> {
+    Ok(repo.user_by_username(username).await?)
+}
+
+pub async fn fetch_user_tenant(
+    db: &dyn Database,
+    user_id: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let statement = Statement::new("SELECT tenant_id FROM users WHERE id = ?1").bind(user_id);
+
+    Ok(db.fetch_optional(statement).await?.map(|row| row.get("tenant_id")).transpose()?)
+}
+
 pub async fn fetch_user_profile(
-    pool: &PgPool,
+    db: &dyn Database,
     user_id: &str,
-) -> Result<UserProfile, Box<dyn std::error::Error>> {
-    let profile = sqlx::query_as!(
-        UserProfile,
-        r#"SELECT u.id, u.username, u.email, p.telephone, p.salutation, p.first_name, p.middle_name, p.last_name, p.gender, p.address_line_1, p.address_line_2, p.city, p.state, p.country, p.date_of_birth, p.configuration FROM users u
+) -> Result<Option<UserProfile>, Box<dyn std::error::Error>> {
+    let statement = Statement::new(
+        "SELECT u.id, u.username, u.email, p.telephone, p.salutation, p.first_name, p.middle_name, p.last_name, p.gender, p.address_line_1, p.address_line_2, p.city, p.state, p.country, p.date_of_birth, p.configuration FROM users u
         LEFT JOIN profiles p ON u.id = p.user_id
-        WHERE u.id = $1"#,
-        user_id,
+        WHERE u.id = ?1",
     )
-    .fetch_one(pool)
-    .await?;
+    .bind(user_id);
+
+    let row = match db.fetch_optional(statement).await? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    Ok(Some(UserProfile {
+        id: row.get("id")?,
+        username: row.get("username")?,
+        email: row.get("email")?,
+        telephone: row.get("telephone")?,
+        salutation: row.get("salutation")?,
+        first_name: row.get("first_name")?,
+        middle_name: row.get("middle_name")?,
+        last_name: row.get("last_name")?,
+        gender: row.get("gender")?,
+        address_line_1: row.get("address_line_1")?,
+        address_line_2: row.get("address_line_2")?,
+        city: row.get("city")?,
+        state: row.get("state")?,
+        country: row.get("country")?,
+        date_of_birth: row.get_date("date_of_birth")?,
+        configuration: row.get_json("configuration")?,
+    }))
+}
+
+const PROFILE_TEXT_FIELDS: [&str; 11] = [
+    "telephone",
+    "salutation",
+    "first_name",
+    "middle_name",
+    "last_name",
+    "gender",
+    "address_line_1",
+    "address_line_2",
+    "city",
+    "state",
+    "country",
+];
+
+const IMMUTABLE_PROFILE_FIELDS: [&str; 3] = ["id", "created_at", "updated_at"];
+
+// Outer None leaves a column untouched, Some(None) sets it to NULL
+#[derive(Default)]
+struct ProfileChanges {
+    username: Option<String>,
+    email: Option<Option<String>>,
+    text: Vec<(&'static str, Option<String>)>,
+    date_of_birth: Option<NaiveDate>,
+    configuration: Option<Option<serde_json::Value>>,
+}
+
+impl ProfileChanges {
+    fn from_payload(payload: &UpdateProfilePayload) -> Result<Self, chrono::ParseError> {
+        let text_values = [
+            &payload.telephone,
+            &payload.salutation,
+            &payload.first_name,
+            &payload.middle_name,
+            &payload.last_name,
+            &payload.gender,
+            &payload.address_line_1,
+            &payload.address_line_2,
+            &payload.city,
+            &payload.state,
+            &payload.country,
+        ];
+
+        Ok(ProfileChanges {
+            username: payload.username.clone(),
+            email: payload.email.clone().map(Some),
+            text: PROFILE_TEXT_FIELDS
+                .iter()
+                .zip(text_values)
+                .filter_map(|(column, value)| value.clone().map(|value| (*column, Some(value))))
+                .collect(),
+            date_of_birth: payload
+                .date_of_birth
+                .as_deref()
+                .map(|date_of_birth| NaiveDate::parse_from_str(date_of_birth, "%Y-%m-%d"))
+                .transpose()?,
+            configuration: payload.configuration.clone().map(Some),
+        })
+    }
+
+    // Diffs the stored profile document against its patched form
+    fn from_documents(original: &serde_json::Value, patched: &serde_json::Value) -> Result<Self, PatchError> {
+        let patched_fields = patched
+            .as_object()
+            .ok_or_else(|| PatchError("Patched profile must remain a JSON object".to_string()))?;
+
+        for field in patched_fields.keys() {
+            if original.get(field).is_none() {
+                return Err(PatchError(format!("Unknown profile field `{}`", field)));
+            }
+        }
+
+        let null = serde_json::Value::Null;
+        let changed = |field: &str| {
+            let after = patched.get(field).unwrap_or(&null);
+            (original.get(field).unwrap_or(&null) != after).then_some(after)
+        };
+        let as_text = |field: &str, value: &serde_json::Value| match value {
+            serde_json::Value::Null => Ok(None),
+            serde_json::Value::String(value) => Ok(Some(value.clone())),
+            _ => Err(PatchError(format!("`{}` must be a string or null", field))),
+        };
+
+        let mut changes = ProfileChanges::default();
+
+        if let Some(value) = changed("username") {
+            changes.username = Some(
+                as_text("username", value)?.ok_or_else(|| PatchError("`username` cannot be null".to_string()))?,
+            );
+        }
+
+        if let Some(value) = changed("email") {
+            changes.email = Some(as_text("email", value)?);
+        }
+
+        for field in PROFILE_TEXT_FIELDS {
+            if let Some(value) = changed(field) {
+                changes.text.push((field, as_text(field, value)?));
+            }
+        }
+
+        if let Some(value) = changed("date_of_birth") {
+            let date_of_birth = as_text("date_of_birth", value)?
+                .ok_or_else(|| PatchError("`date_of_birth` cannot be null".to_string()))?;
+            changes.date_of_birth = Some(
+                NaiveDate::parse_from_str(&date_of_birth, "%Y-%m-%d")
+                    .map_err(|_| PatchError("`date_of_birth` must be formatted as YYYY-MM-DD".to_string()))?,
+            );
+        }
+
+        if let Some(value) = changed("configuration") {
+            changes.configuration = Some((!value.is_null()).then(|| value.clone()));
+        }
+
+        Ok(changes)
+    }
+}
+
+// Account identity lives on users; everything else belongs to profiles
+fn profile_change_statements(user_id: &str, changes: &ProfileChanges) -> Vec<Statement> {
+    let now = Utc::now().naive_utc();
+
+    let mut users = Statement::new("UPDATE users SET updated_at = ?1").bind(now);
+    if let Some(username) = &changes.username {
+        users = users.push_bind(", username = ?", username.as_str());
+    }
+    if let Some(email) = &changes.email {
+        users = users.push_bind(", email = ?", email.clone());
+    }
+
+    let mut profiles = Statement::new("UPDATE profiles SET updated_at = ?1").bind(now);
+    for (column, value) in &changes.text {
+        profiles = profiles.push_bind(&format!(", {} = ?", column), value.clone());
+    }
+    if let Some(date_of_birth) = changes.date_of_birth {
+        profiles = profiles.push_bind(", date_of_birth = ?", date_of_birth);
+    }
+    if let Some(configuration) = &changes.configuration {
+        profiles = profiles.push_bind(", configuration = ?", configuration.clone());
+    }
+
+    vec![
+        users.push_bind(" WHERE id = ?", user_id),
+        profiles.push_bind(" WHERE user_id = ?", user_id),
+    ]
+}
+
+// Returns false when the user does not exist
+async fn write_profile_changes(
+    db: &dyn Database,
+    user_id: &str,
+    changes: &ProfileChanges,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let affected = db.batch(profile_change_statements(user_id, changes)).await?;
 
-    Ok(profile)
+    Ok(affected[0] > 0)
 }
 
 pub async fn update_user_profile(
-    pool: &PgPool,
+    db: &dyn Database,
     user_id: &str,
     payload: &UpdateProfilePayload,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut query = String::from("UPDATE users SET ");
-    let mut values = Vec::new();
-    let mut params = Vec::new();
+) -> Result<Option<UserProfile>, Box<dyn std::error::Error>> {
+    let changes = ProfileChanges::from_payload(payload)?;
 
-    if let Some(username) = &payload.username {
-        query.push_str("username = $1, ");
-        values.push(username);
-        params.push(sqlx::types::Json(&username));
+    if !write_profile_changes(db, user_id, &changes).await? {
+        return Ok(None);
     }
 
-    if let Some(email) = &payload.email {
-        query.push_str("email = $2, ");
-        values.push(email);
-        params.push(sqlx::types::Json(&email));
+    fetch_user_profile(db, user_id).await
+}
+
+#[derive(Debug)]
+pub struct PatchError(pub String);
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
     }
+}
 
-    // Add more fields as needed...
+impl std::error::Error for PatchError {}
 
-    query.pop(); // Remove trailing comma and space
-    query.push_str(" WHERE id = $3");
+pub enum ProfilePatch {
+    // RFC 7396, application/merge-patch+json
+    Merge(serde_json::Value),
+    // RFC 6902, application/json-patch+json
+    Json(json_patch::Patch),
+}
+
+fn patched_field(pointer: &str) -> String {
+    pointer
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .replace("~1", "/")
+        .replace("~0", "~")
+}
 
-    values.push(user_id);
-    params.push(sqlx::types::Json(&user_id));
+fn check_patch_targets(patch: &ProfilePatch) -> Result<(), PatchError> {
+    use json_patch::PatchOperation;
 
-    sqlx::query_as!(models::User, &query, &params)
-        .execute(pool)
-        .await?;
+    let targets: Vec<&str> = match patch {
+        ProfilePatch::Merge(serde_json::Value::Object(fields)) => fields.keys().map(String::as_str).collect(),
+        ProfilePatch::Merge(_) => return Err(PatchError("Merge patch must be a JSON object".to_string())),
+        ProfilePatch::Json(patch) => {
+            let mut targets = Vec::new();
+            for operation in &patch.0 {
+                match operation {
+                    PatchOperation::Add(op) => targets.push(op.path.as_str()),
+                    PatchOperation::Remove(op) => targets.push(op.path.as_str()),
+                    PatchOperation::Replace(op) => targets.push(op.path.as_str()),
+                    PatchOperation::Move(op) => {
+                        targets.push(op.path.as_str());
+                        targets.push(op.from.as_str());
+                    }
+                    PatchOperation::Copy(op) => targets.push(op.path.as_str()),
+                    PatchOperation::Test(_) => {}
+                }
+            }
+            if targets.iter().any(|path| path.is_empty()) {
+                return Err(PatchError("Patch operations cannot replace the whole profile".to_string()));
+            }
+            return check_fields(targets.iter().map(|path| patched_field(path)));
+        }
+    };
+
+    check_fields(targets.into_iter().map(str::to_string))
+}
+
+fn check_fields(fields: impl Iterator<Item = String>) -> Result<(), PatchError> {
+    for field in fields {
+        if IMMUTABLE_PROFILE_FIELDS.contains(&field.as_str()) {
+            return Err(PatchError(format!("`{}` is immutable", field)));
+        }
+    }
 
     Ok(())
 }
 
-pub async fn fetch_user_settings(
-    pool: &PgPool,
+pub async fn patch_user_profile(
+    db: &dyn Database,
     user_id: &str,
-) -> Result<SettingsResponse, Box<dyn std::error::Error>> {
-    let settings = sqlx::query_as!(
-        SettingsResponse,
-        r#"SELECT theme, language, notifications FROM user_settings WHERE user_id = $1"#,
-        user_id,
-    )
-    .fetch_one(pool)
-    .await?;
+    patch: &ProfilePatch,
+) -> Result<Option<UserProfile>, Box<dyn std::error::Error>> {
+    check_patch_targets(patch)?;
+
+    let current = match fetch_user_profile(db, user_id).await? {
+        Some(profile) => profile,
+        None => return Ok(None),
+    };
+
+    let original = serde_json::to_value(&current)?;
+    let mut patched = original.clone();
+    match patch {
+        ProfilePatch::Merge(merge) => json_patch::merge(&mut patched, merge),
+        ProfilePatch::Json(operations) => {
+            json_patch::patch(&mut patched, operations).map_err(|e| PatchError(e.to_string()))?
+        }
+    }
+
+    let changes = ProfileChanges::from_documents(&original, &patched)?;
+    if !write_profile_changes(db, user_id, &changes).await? {
+        return Ok(None);
+    }
 
-    Ok(settings)
+    fetch_user_profile(db, user_id).await
 }
 
-pub async fn hash_password(password: &str) -> Result<String, argon2::Error> {
-    let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, argon2::Params::default());
-    argon2.hash_password(password.as_bytes(), &argon2::Salt::generate(argon2::salt_size!()))
-        .map_err(Into::into)
-        .map(|hash_result| hash_result.to_string())
+pub async fn fetch_user_settings(
+    repo: &dyn IdentityRepository,
+    user_id: &str,
+) -> Result<Option<SettingsResponse>, Box<dyn std::error::Error>> {
+    Ok(repo.settings(user_id).await?)
 }
 
-pub async fn verify_password(password: &str, hashed_password: &str) -> Result<bool, argon2::Error> {
-    let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, argon2::Params::default());
-    argon2.verify_password(password.as_bytes(), &argon2::PasswordHash::from_string(hashed_password)?)
-        .map_err(Into::into)
-        .map(|is_valid| is_valid)
+pub enum RefreshOutcome {
+    Rotated { user_id: String, refresh_token: String },
+    Reused,
+    Expired,
+    Invalid,
+}
+
+// Returns the new opaque token together with the statement that stores its digest; `now`
+// is bound as ?7 so a caller can extend the statement with a condition on it
+pub fn refresh_token_statement(
+    config: &config::Config,
+    user_id: &str,
+    family_id: &str,
+    device_id: Option<&str>,
+    now: chrono::NaiveDateTime,
+) -> (String, Statement) {
+    let refresh_token = utils::generate_refresh_token();
+
+    let statement = Statement::new(
+        "INSERT INTO refresh_tokens (
+            id,
+            user_id,
+            family_id,
+            device_id,
+            token_hash,
+            expires_at,
+            created_at
+        ) SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(user_id)
+    .bind(family_id)
+    .bind(device_id)
+    .bind(utils::hash_refresh_token(&refresh_token))
+    .bind(utils::refresh_token_expires_at(config))
+    .bind(now);
+
+    (refresh_token, statement)
+}
+
+pub async fn issue_refresh_token(
+    db: &dyn Database,
+    config: &config::Config,
+    user_id: &str,
+    family_id: &str,
+    device_id: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (refresh_token, statement) =
+        refresh_token_statement(config, user_id, family_id, device_id, Utc::now().naive_utc());
+    db.execute(statement).await?;
+
+    Ok(refresh_token)
+}
+
+pub async fn rotate_refresh_token(
+    db: &dyn Database,
+    config: &config::Config,
+    refresh_token: &str,
+) -> Result<RefreshOutcome, Box<dyn std::error::Error>> {
+    let now = Utc::now().naive_utc();
+
+    let stored = db
+        .fetch_optional(
+            Statement::new("SELECT * FROM refresh_tokens WHERE token_hash = ?1")
+                .bind(utils::hash_refresh_token(refresh_token)),
+        )
+        .await?
+        .map(|row| models::RefreshToken::from_row(&row))
+        .transpose()?;
+
+    let stored = match stored {
+        Some(stored) if stored.revoked_at.is_none() => stored,
+        _ => return Ok(RefreshOutcome::Invalid),
+    };
+
+    if stored.expires_at < now {
+        return Ok(RefreshOutcome::Expired);
+    }
+
+    // The claim and its replacement commit together. The replacement is only stored if this
+    // batch's claim is the one that marked the token used, so of two concurrent refreshes
+    // exactly one gets a new token.
+    let (rotated, insert) =
+        refresh_token_statement(config, &stored.user_id, &stored.family_id, stored.device_id.as_deref(), now);
+    let insert = insert
+        .push(" WHERE EXISTS (SELECT 1 FROM refresh_tokens WHERE id = ?8 AND used_at = ?7)")
+        .bind(stored.id.as_str());
+
+    let affected = db
+        .batch(vec![
+            Statement::new("UPDATE refresh_tokens SET used_at = ?1 WHERE id = ?2 AND used_at IS NULL")
+                .bind(now)
+                .bind(stored.id.as_str()),
+            insert,
+        ])
+        .await?;
+
+    if affected[1] == 0 {
+        // A used token was replayed: assume it leaked and kill the whole family
+        db.execute(
+            Statement::new("UPDATE refresh_tokens SET revoked_at = ?1 WHERE family_id = ?2 AND revoked_at IS NULL")
+                .bind(now)
+                .bind(stored.family_id.as_str()),
+        )
+        .await?;
+
+        return Ok(RefreshOutcome::Reused);
+    }
+
+    Ok(RefreshOutcome::Rotated {
+        user_id: stored.user_id,
+        refresh_token: rotated,
+    })
+}
+
+pub async fn revoke_access_token(
+    db: &dyn Database,
+    jti: &str,
+    user_id: &str,
+    expires_at: chrono::NaiveDateTime,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let statement = Statement::new(
+        "INSERT INTO revoked_tokens (jti, user_id, expires_at, revoked_at)
+        VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (jti) DO NOTHING",
+    )
+    .bind(jti)
+    .bind(user_id)
+    .bind(expires_at)
+    .bind(Utc::now().naive_utc());
+
+    db.execute(statement).await?;
+
+    Ok(())
+}
+
+pub async fn revoke_all_tokens(
+    db: &dyn Database,
+    user_id: &str,
+    revoked_before: chrono::NaiveDateTime,
+) -> Result<(), Box<dyn std::error::Error>> {
+    db.batch(vec![
+        Statement::new(
+            "INSERT INTO user_token_cutoffs (user_id, revoked_before)
+            VALUES (?1, ?2)
+            ON CONFLICT (user_id) DO UPDATE SET revoked_before = excluded.revoked_before",
+        )
+        .bind(user_id)
+        .bind(revoked_before),
+        Statement::new("UPDATE refresh_tokens SET revoked_at = ?1 WHERE user_id = ?2 AND revoked_at IS NULL")
+            .bind(revoked_before)
+            .bind(user_id),
+    ])
+    .await?;
+
+    Ok(())
+}
+
+pub async fn is_token_revoked(
+    repo: &dyn IdentityRepository,
+    jti: &str,
+    user_id: &str,
+    issued_at: chrono::NaiveDateTime,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    Ok(repo.is_token_revoked(jti, user_id, issued_at).await?)
+}
+
+pub async fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, argon2::Params::default());
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    argon2
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash_result| hash_result.to_string())
+}
+
+// A wrong password is Ok(false); only a stored hash that cannot be parsed is an error
+pub async fn verify_password(password: &str, hashed_password: &str) -> Result<bool, argon2::password_hash::Error> {
+    let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, argon2::Params::default());
+    match argon2.verify_password(password.as_bytes(), &PasswordHash::new(hashed_password)?) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+pub const ORDER_PENDING: &str = "pending";
+pub const ORDER_INVOICED: &str = "invoiced";
+pub const ORDER_CANCELLED: &str = "cancelled";
+
+// An order line once catalog products have been looked up
+struct OrderLineInput {
+    product_id: Option<String>,
+    description: String,
+    quantity: i32,
+    unit_price: Money,
+    tax_class: String,
+}
+
+// Catalog lines take their name, price and tax class from the product, and yield the statements
+// that reserve its stock. Quantities are summed per product and reserved in id order so concurrent
+// orders touch rows in a consistent order. `held` is stock the order's current lines already
+// reserve, which their replacements may reuse.
+async fn resolve_order_lines(
+    db: &dyn Database,
+    currency: Currency,
+    lines: &[OrderLinePayload],
+    held: &std::collections::HashMap<String, i32>,
+) -> Result<(Vec<OrderLineInput>, Vec<Statement>), Box<dyn std::error::Error>> {
+    let mut wanted: std::collections::BTreeMap<&str, i32> = std::collections::BTreeMap::new();
+    for line in lines {
+        if let Some(product_id) = &line.product_id {
+            if line.quantity <= 0 {
+                return Err(Box::new(AppError::Validation("Order line quantity must be positive".to_string())));
+            }
+            let quantity = wanted.entry(product_id.as_str()).or_insert(0);
+            *quantity = quantity
+                .checked_add(line.quantity)
+                .ok_or_else(|| AppError::Validation("Order line quantity is too large".to_string()))?;
+        }
+    }
+
+    let mut catalog = std::collections::HashMap::new();
+    let mut reservations = Vec::with_capacity(wanted.len());
+    for (product_id, quantity) in wanted {
+        let already_held = held.get(product_id).copied().unwrap_or(0);
+        let (product, reservation) = products::reserve_stock(db, product_id, quantity, already_held).await?;
+        if product.price.currency() != currency {
+            return Err(Box::new(AppError::Validation(format!(
+                "Product {} is priced in {}, not {}",
+                product.sku,
+                product.price.currency(),
+                currency
+            ))));
+        }
+        catalog.insert(product_id, product);
+        reservations.push(reservation);
+    }
+
+    let lines = lines
+        .iter()
+        .map(|line| match &line.product_id {
+            Some(product_id) => {
+                let product = &catalog[product_id.as_str()];
+                Ok(OrderLineInput {
+                    product_id: Some(product.id.clone()),
+                    description: line.description.clone().unwrap_or_else(|| product.name.clone()),
+                    quantity: line.quantity,
+                    unit_price: product.price,
+                    tax_class: product.tax_class.clone(),
+                })
+            }
+            None => match (&line.description, line.unit_price) {
+                (Some(description), Some(unit_price)) => Ok(OrderLineInput {
+                    product_id: None,
+                    description: description.clone(),
+                    quantity: line.quantity,
+                    unit_price,
+                    tax_class: line.tax_class.clone().unwrap_or_else(|| tax::STANDARD.to_string()),
+                }),
+                _ => Err(Box::new(AppError::Validation(
+                    "Order lines need a product_id or a description and unit_price".to_string(),
+                )) as Box<dyn std::error::Error>),
+            },
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok((lines, reservations))
+}
+
+// Totals are always derived from the submitted lines, never trusted from the client
+fn price_order_lines(currency: Currency, lines: &[OrderLineInput]) -> Result<(Vec<Money>, Money), AppError> {
+    if lines.is_empty() {
+        return Err(AppError::Validation("An order needs at least one line".to_string()));
+    }
+
+    let mut line_totals = Vec::with_capacity(lines.len());
+    let mut total = Money::zero(currency);
+
+    for line in lines {
+        if line.description.trim().is_empty() {
+            return Err(AppError::Validation("Order line description cannot be empty".to_string()));
+        }
+        if line.quantity <= 0 {
+            return Err(AppError::Validation("Order line quantity must be positive".to_string()));
+        }
+        if line.unit_price.is_negative() {
+            return Err(AppError::Validation("Order line unit price cannot be negative".to_string()));
+        }
+
+        let line_total = line.unit_price.checked_mul(line.quantity as i64)?;
+        total = total.checked_add(line_total)?;
+        line_totals.push(line_total);
+    }
+
+    Ok((line_totals, total))
+}
+
+struct PricedOrder {
+    lines: Vec<OrderLineInput>,
+    line_totals: Vec<Money>,
+    taxes: Vec<LineTax>,
+    breakdown: TaxBreakdown,
+    reservations: Vec<Statement>,
+}
+
+// Tax follows the customer's profile address, priced with the order's own inclusive/rounding settings
+async fn price_order(
+    db: &dyn Database,
+    user_id: &str,
+    currency: Currency,
+    lines: &[OrderLinePayload],
+    prices_include_tax: bool,
+    rounding: TaxRounding,
+    held: &std::collections::HashMap<String, i32>,
+) -> Result<PricedOrder, Box<dyn std::error::Error>> {
+    let (lines, reservations) = resolve_order_lines(db, currency, lines, held).await?;
+    let (line_totals, _) = price_order_lines(currency, &lines)?;
+
+    let jurisdiction = tax::fetch_jurisdiction(db, user_id).await?;
+    let rates = tax::fetch_rates(db, &jurisdiction).await?;
+
+    let taxable: Vec<TaxableLine> = lines
+        .iter()
+        .zip(&line_totals)
+        .map(|(line, line_total)| TaxableLine {
+            amount: *line_total,
+            tax_class: &line.tax_class,
+        })
+        .collect();
+
+    let taxes = tax::calculate(currency, &taxable, &rates, &jurisdiction, prices_include_tax, rounding)
+        .map_err(AppError::from)?;
+    let breakdown = TaxBreakdown::from_lines(currency, prices_include_tax, rounding, &taxes).map_err(AppError::from)?;
+
+    Ok(PricedOrder {
+        lines,
+        line_totals,
+        taxes,
+        breakdown,
+        reservations,
+    })
+}
+
+fn order_line_statements(order_id: &str, priced: &PricedOrder) -> Vec<Statement> {
+    let priced_lines = priced.line_totals.iter().zip(&priced.taxes);
+
+    priced
+        .lines
+        .iter()
+        .zip(priced_lines)
+        .enumerate()
+        .map(|(position, (line, (line_total, line_tax)))| {
+            Statement::new(
+                "INSERT INTO order_lines (
+                    id,
+                    order_id,
+                    product_id,
+                    position,
+                    description,
+                    quantity,
+                    unit_price,
+                    line_total,
+                    currency,
+                    tax_class,
+                    tax_name,
+                    tax_rate_bps,
+                    net_amount,
+                    tax_amount,
+                    gross_amount
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            )
+            .bind(Uuid::new_v4().to_string())
+            .bind(order_id)
+            .bind(line.product_id.clone())
+            .bind(position as i32)
+            .bind(line.description.as_str())
+            .bind(line.quantity)
+            .bind(line.unit_price.minor())
+            .bind(line_total.minor())
+            .bind(line_total.currency().code())
+            .bind(line.tax_class.as_str())
+            .bind(line_tax.tax_name.as_str())
+            .bind(line_tax.rate_bps)
+            .bind(line_tax.net.minor())
+            .bind(line_tax.tax.minor())
+            .bind(line_tax.gross.minor())
+        })
+        .collect()
+}
+
+const STOCK_CHANGED: &str = "Stock changed while the order was being placed; try again";
+const ORDER_CHANGED: &str = "Order was changed by another request; reload it and try again";
+const INVOICE_CHANGED: &str = "Invoice was changed by another request; reload it and try again";
+
+pub async fn create_order(
+    db: &dyn Database,
+    config: &config::Config,
+    user_id: &str,
+    payload: &CreateOrderPayload,
+) -> Result<OrderWithLines, Box<dyn std::error::Error>> {
+    let (order_id, statements) = create_order_statements(db, config, user_id, payload).await?;
+
+    commit(db, statements, STOCK_CHANGED).await?;
+
+    let order = fetch_order_details(db, &order_id, None).await?.ok_or("Order vanished after insert")?;
+
+    Ok(order)
+}
+
+// The new order's id and the batch that places it: stock reservations, the order row and its lines
+pub async fn create_order_statements(
+    db: &dyn Database,
+    config: &config::Config,
+    user_id: &str,
+    payload: &CreateOrderPayload,
+) -> Result<(String, Vec<Statement>), Box<dyn std::error::Error>> {
+    let order_id = Uuid::new_v4().to_string();
+    let now = Utc::now().naive_utc();
+    let prices_include_tax = payload.prices_include_tax.unwrap_or(config.prices_include_tax);
+    let rounding = config.tax_rounding;
+
+    let mut priced = price_order(
+        db,
+        user_id,
+        payload.currency,
+        &payload.lines,
+        prices_include_tax,
+        rounding,
+        &std::collections::HashMap::new(),
+    )
+    .await?;
+
+    let mut statements = std::mem::take(&mut priced.reservations);
+    statements.push(
+        Statement::new(
+            "INSERT INTO orders (
+                id,
+                user_id,
+                status,
+                currency,
+                subtotal,
+                tax_total,
+                total,
+                prices_include_tax,
+                tax_rounding,
+                notes,
+                created_at,
+                updated_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?11)",
+        )
+        .bind(order_id.as_str())
+        .bind(user_id)
+        .bind(ORDER_PENDING)
+        .bind(payload.currency.code())
+        .bind(priced.breakdown.subtotal.minor())
+        .bind(priced.breakdown.tax_total.minor())
+        .bind(priced.breakdown.total.minor())
+        .bind(prices_include_tax)
+        .bind(rounding.as_str())
+        .bind(payload.notes.clone())
+        .bind(now),
+    );
+    statements.extend(order_line_statements(&order_id, &priced));
+
+    Ok((order_id, statements))
+}
+
+// `owner_id` restricts the lookup to a single customer's orders; back-office callers pass None
+pub async fn fetch_order_details(
+    db: &dyn Database,
+    order_id: &str,
+    owner_id: Option<&str>,
+) -> Result<Option<OrderWithLines>, Box<dyn std::error::Error>> {
+    let statement = optional_owner(Statement::new("SELECT * FROM orders WHERE id = ?1").bind(order_id), owner_id);
+
+    let order = match db.fetch_optional(statement).await? {
+        Some(row) => Order::from_row(&row)?,
+        None => return Ok(None),
+    };
+
+    let lines = db
+        .fetch_all(Statement::new("SELECT * FROM order_lines WHERE order_id = ?1 ORDER BY position").bind(order_id))
+        .await?
+        .iter()
+        .map(OrderLine::from_row)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tax = TaxBreakdown::from_lines(
+        order.currency,
+        order.prices_include_tax,
+        order.tax_rounding,
+        &lines.iter().map(OrderLine::line_tax).collect::<Vec<_>>(),
+    )
+    .map_err(AppError::from)?;
+
+    Ok(Some(OrderWithLines { order, lines, tax }))
+}
+
+pub async fn list_orders(
+    db: &dyn Database,
+    owner_id: Option<&str>,
+    query: &OrderListQuery,
+) -> Result<Vec<Order>, Box<dyn std::error::Error>> {
+    let mut statement = optional_owner(
+        Statement::new("SELECT * FROM orders WHERE 1 = 1"),
+        owner_id.or(query.user_id.as_deref()),
+    );
+    if let Some(status) = &query.status {
+        statement = statement.push_bind(" AND status = ?", status.as_str());
+    }
+    let statement = statement
+        .push(" ORDER BY created_at DESC, id DESC")
+        .push_bind(" LIMIT ?", query.limit.unwrap_or(25).clamp(1, 100))
+        .push_bind(" OFFSET ?", query.offset.unwrap_or(0).max(0));
+
+    let orders = db.fetch_all(statement).await?.iter().map(Order::from_row).collect::<Result<_, _>>()?;
+
+    Ok(orders)
+}
+
+// Reads the order and checks it can still change. The caller's batch then starts with
+// `order_guard`, which fails it if anything else wrote the order after this read.
+async fn fetch_pending_order(
+    db: &dyn Database,
+    order_id: &str,
+    owner_id: Option<&str>,
+) -> Result<Option<Order>, Box<dyn std::error::Error>> {
+    let statement = optional_owner(Statement::new("SELECT * FROM orders WHERE id = ?1").bind(order_id), owner_id);
+    let order = db.fetch_optional(statement).await?.map(|row| Order::from_row(&row)).transpose()?;
+
+    match order {
+        Some(order) if order.status != ORDER_PENDING => Err(Box::new(AppError::Conflict(format!(
+            "Order is {} and can no longer be changed",
+            order.status
+        )))),
+        order => Ok(order),
+    }
+}
+
+// Bumps the version read with the order, or writes NULL into the NOT NULL column when another
+// write got there first. The returned statement still needs its WHERE clause; ?1 is the order id.
+fn order_guard(order: &Order, now: chrono::NaiveDateTime) -> Statement {
+    Statement::new("UPDATE orders SET version = CASE WHEN version = ?2 THEN version + 1 END, updated_at = ?3")
+        .bind(order.id.as_str())
+        .bind(order.version)
+        .bind(now)
+}
+
+// Units each catalog product currently has reserved by the order's lines
+async fn held_stock(
+    db: &dyn Database,
+    order_id: &str,
+) -> Result<std::collections::HashMap<String, i32>, Box<dyn std::error::Error>> {
+    let statement = Statement::new(
+        "SELECT product_id, SUM(quantity) AS quantity FROM order_lines
+        WHERE order_id = ?1 AND product_id IS NOT NULL
+        GROUP BY product_id",
+    )
+    .bind(order_id);
+
+    let mut held = std::collections::HashMap::new();
+    for row in db.fetch_all(statement).await? {
+        held.insert(row.get("product_id")?, row.get::<i64>("quantity")? as i32);
+    }
+
+    Ok(held)
+}
+
+pub async fn update_order(
+    db: &dyn Database,
+    order_id: &str,
+    owner_id: Option<&str>,
+    payload: &UpdateOrderPayload,
+) -> Result<Option<OrderWithLines>, Box<dyn std::error::Error>> {
+    let order = match fetch_pending_order(db, order_id, owner_id).await? {
+        Some(order) => order,
+        None => return Ok(None),
+    };
+
+    let mut update = order_guard(&order, Utc::now().naive_utc());
+    let mut statements = Vec::new();
+
+    if let Some(lines) = &payload.lines {
+        let held = held_stock(db, order_id).await?;
+        let mut priced = price_order(
+            db,
+            &order.user_id,
+            order.currency,
+            lines,
+            order.prices_include_tax,
+            order.tax_rounding,
+            &held,
+        )
+        .await?;
+
+        // Hand back what the old lines held before the replacement lines reserve theirs
+        statements.push(products::release_stock(order_id));
+        statements.append(&mut priced.reservations);
+        statements.push(Statement::new("DELETE FROM order_lines WHERE order_id = ?1").bind(order_id));
+        statements.extend(order_line_statements(order_id, &priced));
+
+        update = update
+            .push_bind(", subtotal = ?", priced.breakdown.subtotal.minor())
+            .push_bind(", tax_total = ?", priced.breakdown.tax_total.minor())
+            .push_bind(", total = ?", priced.breakdown.total.minor());
+    }
+
+    if let Some(notes) = &payload.notes {
+        update = update.push_bind(", notes = ?", notes.as_str());
+    }
+
+    statements.insert(0, update.push(" WHERE id = ?1"));
+    commit(db, statements, ORDER_CHANGED).await?;
+
+    fetch_order_details(db, order_id, None).await
+}
+
+pub async fn cancel_order(
+    db: &dyn Database,
+    order_id: &str,
+    owner_id: Option<&str>,
+) -> Result<Option<OrderWithLines>, Box<dyn std::error::Error>> {
+    let order = match fetch_pending_order(db, order_id, owner_id).await? {
+        Some(order) => order,
+        None => return Ok(None),
+    };
+
+    commit(
+        db,
+        vec![
+            order_guard(&order, Utc::now().naive_utc())
+                .push_bind(", status = ?", ORDER_CANCELLED)
+                .push(" WHERE id = ?1"),
+            products::release_stock(order_id),
+        ],
+        ORDER_CHANGED,
+    )
+    .await?;
+
+    fetch_order_details(db, order_id, None).await
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InvoiceStatus {
+    Draft,
+    Issued,
+    PartiallyPaid,
+    Paid,
+    // Settled entirely by credit notes, with nothing paid
+    Credited,
+    Overdue,
+    Void,
+}
+
+impl InvoiceStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            InvoiceStatus::Draft => "draft",
+            InvoiceStatus::Issued => "issued",
+            InvoiceStatus::PartiallyPaid => "partially_paid",
+            InvoiceStatus::Paid => "paid",
+            InvoiceStatus::Credited => "credited",
+            InvoiceStatus::Overdue => "overdue",
+            InvoiceStatus::Void => "void",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "draft" => Some(InvoiceStatus::Draft),
+            "issued" => Some(InvoiceStatus::Issued),
+            "partially_paid" => Some(InvoiceStatus::PartiallyPaid),
+            "paid" => Some(InvoiceStatus::Paid),
+            "credited" => Some(InvoiceStatus::Credited),
+            "overdue" => Some(InvoiceStatus::Overdue),
+            "void" => Some(InvoiceStatus::Void),
+            _ => None,
+        }
+    }
+
+    // draft → issued → partially_paid → paid, with void and overdue as side exits and credited
+    // for an invoice its credit notes cancel outright
+    pub fn can_transition_to(self, next: InvoiceStatus) -> bool {
+        use InvoiceStatus::*;
+
+        matches!(
+            (self, next),
+            (Draft, Issued)
+                | (Draft, Void)
+                | (Issued, PartiallyPaid)
+                | (Issued, Paid)
+                | (Issued, Overdue)
+                | (Issued, Credited)
+                | (Issued, Void)
+                | (PartiallyPaid, Paid)
+                | (PartiallyPaid, Overdue)
+                | (Overdue, PartiallyPaid)
+                | (Overdue, Paid)
+                | (Overdue, Credited)
+                | (Overdue, Void)
+                // Refunds walk a settled invoice back towards unpaid
+                | (Paid, PartiallyPaid)
+                | (Paid, Issued)
+                | (Paid, Overdue)
+                | (Paid, Credited)
+                | (PartiallyPaid, Issued)
+        )
+    }
+}
+
+// Numbers are drawn from the invoice's own tenant, and only by someone who belongs to it
+fn check_invoice_tenant(invoice: &Invoice, tenant_id: &str) -> Result<(), AppError> {
+    if invoice.tenant_id == tenant_id {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden(format!("Invoice belongs to tenant {}, not {}", invoice.tenant_id, tenant_id)))
+    }
+}
+
+fn check_invoice_transition(current: &str, next: InvoiceStatus) -> Result<(), AppError> {
+    let allowed = InvoiceStatus::parse(current).is_some_and(|current| current.can_transition_to(next));
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(AppError::InvalidTransition {
+            from: current.to_string(),
+            to: next.as_str().to_string(),
+        })
+    }
+}
+
+pub async fn create_invoice(
+    db: &dyn Database,
+    payload: &CreateInvoicePayload,
+) -> Result<InvoiceWithLines, Box<dyn std::error::Error>> {
+    let (invoice_id, statements) = create_invoice_statements(db, payload).await?;
+
+    commit(db, statements, ORDER_CHANGED).await?;
+
+    let invoice = fetch_invoice_details(db, &invoice_id, None).await?.ok_or("Invoice vanished after insert")?;
+
+    Ok(invoice)
+}
+
+// The new invoice's id and the batch that drafts it from the order and marks the order invoiced
+pub async fn create_invoice_statements(
+    db: &dyn Database,
+    payload: &CreateInvoicePayload,
+) -> Result<(String, Vec<Statement>), Box<dyn std::error::Error>> {
+    let order = match fetch_pending_order(db, &payload.order_id, None).await? {
+        Some(order) => order,
+        None => return Err(Box::new(AppError::NotFound("Order not found".to_string()))),
+    };
+
+    let invoice_id = Uuid::new_v4().to_string();
+    let now = Utc::now().naive_utc();
+
+    let statements = vec![
+        order_guard(&order, now)
+            .push_bind(", status = ?", ORDER_INVOICED)
+            .push(" WHERE id = ?1"),
+        Statement::new(
+            "INSERT INTO invoices (
+                id,
+                order_id,
+                user_id,
+                status,
+                currency,
+                subtotal,
+                tax_total,
+                total,
+                amount_paid,
+                amount_credited,
+                prices_include_tax,
+                tax_rounding,
+                due_date,
+                tenant_id,
+                created_at,
+                updated_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 0, 0, ?9, ?10, ?11, (SELECT tenant_id FROM users WHERE id = ?3), ?12, ?12)",
+        )
+        .bind(invoice_id.as_str())
+        .bind(order.id.as_str())
+        .bind(order.user_id.as_str())
+        .bind(InvoiceStatus::Draft.as_str())
+        .bind(order.currency.code())
+        .bind(order.subtotal.minor())
+        .bind(order.tax_total.minor())
+        .bind(order.total.minor())
+        .bind(order.prices_include_tax)
+        .bind(order.tax_rounding.as_str())
+        .bind(payload.due_date)
+        .bind(now),
+        // Lines are copied so later order edits never rewrite an invoice
+        Statement::new(
+            "INSERT INTO invoice_lines (
+                id, invoice_id, position, description, quantity, unit_price, line_total, currency,
+                tax_class, tax_name, tax_rate_bps, net_amount, tax_amount, gross_amount
+            )
+            SELECT ?1 || '-' || position, ?1, position, description, quantity, unit_price, line_total, currency,
+                tax_class, tax_name, tax_rate_bps, net_amount, tax_amount, gross_amount
+            FROM order_lines WHERE order_id = ?2",
+        )
+        .bind(invoice_id.as_str())
+        .bind(order.id.as_str()),
+    ];
+
+    Ok((invoice_id, statements))
+}
+
+pub async fn fetch_invoice_details(
+    db: &dyn Database,
+    invoice_id: &str,
+    owner_id: Option<&str>,
+) -> Result<Option<InvoiceWithLines>, Box<dyn std::error::Error>> {
+    let invoice = match fetch_invoice(db, invoice_id, owner_id).await? {
+        Some(invoice) => invoice,
+        None => return Ok(None),
+    };
+
+    let lines = fetch_invoice_lines(db, invoice_id).await?;
+
+    let tax = TaxBreakdown::from_lines(
+        invoice.currency,
+        invoice.prices_include_tax,
+        invoice.tax_rounding,
+        &lines.iter().map(InvoiceLine::line_tax).collect::<Vec<_>>(),
+    )
+    .map_err(AppError::from)?;
+
+    let credit_notes = fetch_credit_notes(db, invoice_id).await?;
+
+    Ok(Some(InvoiceWithLines {
+        invoice,
+        lines,
+        tax,
+        credit_notes,
+    }))
+}
+
+pub async fn fetch_invoice_document(
+    db: &dyn Database,
+    invoice_id: &str,
+    owner_id: Option<&str>,
+) -> Result<Option<InvoiceDocument>, Box<dyn std::error::Error>> {
+    let invoice = match fetch_invoice_details(db, invoice_id, owner_id).await? {
+        Some(invoice) => invoice,
+        None => return Ok(None),
+    };
+    let customer = fetch_user_profile(db, &invoice.invoice.user_id)
+        .await?
+        .ok_or("Invoice customer no longer exists")?;
+
+    Ok(Some(InvoiceDocument { invoice, customer }))
+}
+
+pub async fn list_invoices(
+    db: &dyn Database,
+    owner_id: Option<&str>,
+    query: &InvoiceListQuery,
+) -> Result<Vec<Invoice>, Box<dyn std::error::Error>> {
+    let mut statement = optional_owner(
+        Statement::new("SELECT * FROM invoices WHERE 1 = 1"),
+        owner_id.or(query.user_id.as_deref()),
+    );
+    if let Some(status) = &query.status {
+        statement = statement.push_bind(" AND status = ?", status.as_str());
+    }
+    let statement = statement
+        .push(" ORDER BY created_at DESC, id DESC")
+        .push_bind(" LIMIT ?", query.limit.unwrap_or(25).clamp(1, 100))
+        .push_bind(" OFFSET ?", query.offset.unwrap_or(0).max(0));
+
+    let invoices = db.fetch_all(statement).await?.iter().map(Invoice::from_row).collect::<Result<_, _>>()?;
+
+    Ok(invoices)
+}
+
+async fn fetch_invoice(
+    db: &dyn Database,
+    invoice_id: &str,
+    owner_id: Option<&str>,
+) -> Result<Option<Invoice>, Box<dyn std::error::Error>> {
+    let statement = optional_owner(Statement::new("SELECT * FROM invoices WHERE id = ?1").bind(invoice_id), owner_id);
+
+    Ok(db.fetch_optional(statement).await?.map(|row| Invoice::from_row(&row)).transpose()?)
+}
+
+async fn fetch_invoice_lines(db: &dyn Database, invoice_id: &str) -> Result<Vec<InvoiceLine>, Box<dyn std::error::Error>> {
+    let statement = Statement::new("SELECT * FROM invoice_lines WHERE invoice_id = ?1 ORDER BY position").bind(invoice_id);
+
+    Ok(db.fetch_all(statement).await?.iter().map(InvoiceLine::from_row).collect::<Result<_, _>>()?)
+}
+
+// The invoice counterpart of `order_guard`; ?1 is the invoice id
+fn invoice_guard(invoice: &Invoice, now: chrono::NaiveDateTime) -> Statement {
+    Statement::new("UPDATE invoices SET version = CASE WHEN version = ?2 THEN version + 1 END, updated_at = ?3")
+        .bind(invoice.id.as_str())
+        .bind(invoice.version)
+        .bind(now)
+}
+
+// How many times a numbered write is retried when another one claims the same number first
+const NUMBERING_ATTEMPTS: usize = 3;
+
+// Whether a failed numbered batch lost a race for its number or its row and may be retried
+fn lost_race(e: &DbError) -> bool {
+    e.is_guard_violation() || e.is_unique_violation()
+}
+
+pub async fn issue_invoice(
+    db: &dyn Database,
+    store: &dyn SequenceStore,
+    tenant_id: &str,
+    invoice_id: &str,
+) -> Result<Option<InvoiceWithLines>, Box<dyn std::error::Error>> {
+    for attempt in 1..=NUMBERING_ATTEMPTS {
+        let Some(statements) = issue_invoice_statements(db, store, tenant_id, invoice_id).await? else {
+            return Ok(None);
+        };
+
+        match db.batch(statements).await {
+            Ok(_) => return fetch_invoice_details(db, invoice_id, None).await,
+            Err(e) if lost_race(&e) && attempt < NUMBERING_ATTEMPTS => continue,
+            Err(e) if lost_race(&e) => break,
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+
+    Err(Box::new(AppError::Conflict(INVOICE_CHANGED.to_string())))
+}
+
+// One attempt at issuing: claims the next number and stamps it on the invoice. The batch fails
+// with `lost_race` if another request took the number or touched the invoice in the meantime.
+pub async fn issue_invoice_statements(
+    db: &dyn Database,
+    store: &dyn SequenceStore,
+    tenant_id: &str,
+    invoice_id: &str,
+) -> Result<Option<Vec<Statement>>, Box<dyn std::error::Error>> {
+    let invoice = match fetch_invoice(db, invoice_id, None).await? {
+        Some(invoice) => invoice,
+        None => return Ok(None),
+    };
+    check_invoice_tenant(&invoice, tenant_id)?;
+    check_invoice_transition(&invoice.status, InvoiceStatus::Issued)?;
+
+    let now = Utc::now().naive_utc();
+    let claim = numbering::next_number(store, &invoice.tenant_id, numbering::INVOICE, now.date()).await?;
+
+    Ok(Some(vec![
+        claim.statement,
+        invoice_guard(&invoice, now)
+            .push_bind(", status = ?", InvoiceStatus::Issued.as_str())
+            .push_bind(", number = ?", claim.number)
+            .push_bind(", issued_at = ?", now)
+            .push(" WHERE id = ?1"),
+    ]))
+}
+
+pub async fn void_invoice(
+    db: &dyn Database,
+    invoice_id: &str,
+) -> Result<Option<InvoiceWithLines>, Box<dyn std::error::Error>> {
+    let invoice = match fetch_invoice(db, invoice_id, None).await? {
+        Some(invoice) => invoice,
+        None => return Ok(None),
+    };
+    check_invoice_transition(&invoice.status, InvoiceStatus::Void)?;
+    // Voiding would strand the money; it has to be refunded through the ledger first
+    if invoice.amount_paid.is_positive() {
+        return Err(Box::new(AppError::Conflict(format!(
+            "Refund the {} paid before voiding this invoice",
+            invoice.amount_paid
+        ))));
+    }
+
+    let now = Utc::now().naive_utc();
+    let statements = vec![
+        invoice_guard(&invoice, now)
+            .push_bind(", status = ?", InvoiceStatus::Void.as_str())
+            .push_bind(", voided_at = ?", now)
+            .push(" WHERE id = ?1"),
+        // A voided invoice releases its order so it can be amended and invoiced again
+        Statement::new(
+            "UPDATE orders SET status = ?1, version = version + 1, updated_at = ?2 WHERE id = ?3 AND status = ?4",
+        )
+        .bind(ORDER_PENDING)
+        .bind(now)
+        .bind(invoice.order_id.as_str())
+        .bind(ORDER_INVOICED),
+    ];
+
+    commit(db, statements, INVOICE_CHANGED).await?;
+
+    fetch_invoice_details(db, invoice_id, None).await
+}
+
+// Moves every unpaid invoice whose due date has passed to overdue
+pub async fn mark_overdue_invoices(
+    db: &dyn Database,
+    today: NaiveDate,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let statement = Statement::new(
+        "UPDATE invoices SET status = ?1, version = version + 1, updated_at = ?2
+        WHERE status IN (?3, ?4) AND due_date < ?5",
+    )
+    .bind(InvoiceStatus::Overdue.as_str())
+    .bind(Utc::now().naive_utc())
+    .bind(InvoiceStatus::Issued.as_str())
+    .bind(InvoiceStatus::PartiallyPaid.as_str())
+    .bind(today);
+
+    Ok(db.execute(statement).await?)
+}
+
+pub const PAYMENT: &str = "payment";
+pub const REFUND: &str = "refund";
+
+// The status an issued invoice settles into once payments and credits have been applied. An
+// invoice past its due date stays overdue until nothing is left to pay, however much has come in.
+fn settlement_status(invoice: &Invoice, amount_paid: i64, amount_credited: i64, today: NaiveDate) -> InvoiceStatus {
+    let past_due = invoice.due_date.is_some_and(|due_date| due_date < today);
+
+    if amount_paid + amount_credited >= invoice.total.minor() {
+        if amount_paid > 0 {
+            InvoiceStatus::Paid
+        } else {
+            InvoiceStatus::Credited
+        }
+    } else if past_due {
+        InvoiceStatus::Overdue
+    } else if amount_paid > 0 {
+        InvoiceStatus::PartiallyPaid
+    } else {
+        InvoiceStatus::Issued
+    }
+}
+
+pub async fn record_payment(
+    db: &dyn Database,
+    invoice_id: &str,
+    kind: &str,
+    payload: &RecordPaymentPayload,
+    recorded_by: &str,
+) -> Result<Option<(Payment, Invoice)>, Box<dyn std::error::Error>> {
+    let Some((payment_id, statements)) = record_payment_statements(db, invoice_id, kind, payload, recorded_by).await? else {
+        return Ok(None);
+    };
+
+    commit(db, statements, INVOICE_CHANGED).await?;
+
+    let payment = db
+        .fetch_one(Statement::new("SELECT * FROM payments WHERE id = ?1").bind(payment_id.as_str()))
+        .await?;
+    let payment = Payment::from_row(&payment)?;
+    let invoice = fetch_invoice(db, invoice_id, None).await?.ok_or("Invoice vanished during payment")?;
+
+    Ok(Some((payment, invoice)))
+}
+
+// The new ledger entry's id and the batch that writes it and resettles the invoice
+pub async fn record_payment_statements(
+    db: &dyn Database,
+    invoice_id: &str,
+    kind: &str,
+    payload: &RecordPaymentPayload,
+    recorded_by: &str,
+) -> Result<Option<(String, Vec<Statement>)>, Box<dyn std::error::Error>> {
+    if !payload.amount.is_positive() {
+        return Err(Box::new(AppError::Validation("Amount must be positive".to_string())));
+    }
+    if payload.method.trim().is_empty() {
+        return Err(Box::new(AppError::Validation("Payment method is required".to_string())));
+    }
+
+    let invoice = match fetch_invoice(db, invoice_id, None).await? {
+        Some(invoice) => invoice,
+        None => return Ok(None),
+    };
+
+    if payload.amount.currency() != invoice.currency {
+        return Err(Box::new(AppError::Validation(format!(
+            "Invoice is billed in {}, not {}",
+            invoice.currency,
+            payload.amount.currency()
+        ))));
+    }
+
+    let status = InvoiceStatus::parse(&invoice.status);
+    let balance = invoice.balance();
+    if kind == PAYMENT {
+        if !matches!(
+            status,
+            Some(InvoiceStatus::Issued | InvoiceStatus::PartiallyPaid | InvoiceStatus::Overdue)
+        ) {
+            return Err(Box::new(AppError::Conflict(format!("Cannot take payments on a {} invoice", invoice.status))));
+        }
+        if payload.amount.minor() > balance.minor() {
+            return Err(Box::new(AppError::Validation(format!(
+                "Payment exceeds the outstanding balance of {}",
+                balance
+            ))));
+        }
+    } else if payload.amount.minor() > invoice.amount_paid.minor() {
+        return Err(Box::new(AppError::Validation(format!(
+            "Refund exceeds the {} paid so far",
+            invoice.amount_paid
+        ))));
+    }
+
+    let payment_id = Uuid::new_v4().to_string();
+    let now = Utc::now().naive_utc();
+
+    // The invoice guard means no other payment landed since the read, so the ledger total the
+    // UPDATE recomputes is the one predicted here for the status
+    let signed = if kind == PAYMENT { payload.amount.minor() } else { -payload.amount.minor() };
+    let next = settlement_status(&invoice, invoice.amount_paid.minor() + signed, invoice.amount_credited.minor(), now.date());
+
+    if status != Some(next) {
+        check_invoice_transition(&invoice.status, next)?;
+    }
+
+    let statements = vec![
+        Statement::new(
+            "INSERT INTO payments (
+                id,
+                invoice_id,
+                user_id,
+                kind,
+                method,
+                reference,
+                amount,
+                currency,
+                received_at,
+                recorded_by,
+                created_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )
+        .bind(payment_id.as_str())
+        .bind(invoice.id.as_str())
+        .bind(invoice.user_id.as_str())
+        .bind(kind)
+        .bind(payload.method.as_str())
+        .bind(payload.reference.clone())
+        .bind(payload.amount.minor())
+        .bind(invoice.currency.code())
+        .bind(payload.received_at.unwrap_or(now))
+        .bind(recorded_by)
+        .bind(now),
+        // The balance is always recomputed from the ledger rather than adjusted incrementally
+        invoice_guard(&invoice, now)
+            .push(
+                ", amount_paid = (SELECT CAST(COALESCE(SUM(CASE WHEN kind = ?4 THEN amount ELSE -amount END), 0) AS BIGINT)
+                FROM payments WHERE invoice_id = ?1)",
+            )
+            .bind(PAYMENT)
+            .push_bind(", status = ?", next.as_str())
+            .push(" WHERE id = ?1"),
+    ];
+
+    Ok(Some((payment_id, statements)))
+}
+
+pub async fn list_invoice_payments(
+    db: &dyn Database,
+    invoice_id: &str,
+    owner_id: Option<&str>,
+) -> Result<Option<Vec<Payment>>, Box<dyn std::error::Error>> {
+    if fetch_invoice(db, invoice_id, owner_id).await?.is_none() {
+        return Ok(None);
+    }
+
+    let statement =
+        Statement::new("SELECT * FROM payments WHERE invoice_id = ?1 ORDER BY received_at, created_at").bind(invoice_id);
+    let payments = db.fetch_all(statement).await?.iter().map(Payment::from_row).collect::<Result<_, _>>()?;
+
+    Ok(Some(payments))
+}
+
+pub async fn list_payments(
+    db: &dyn Database,
+    owner_id: Option<&str>,
+    query: &PaymentListQuery,
+) -> Result<Vec<Payment>, Box<dyn std::error::Error>> {
+    let statement = optional_owner(
+        Statement::new("SELECT * FROM payments WHERE 1 = 1"),
+        owner_id.or(query.user_id.as_deref()),
+    )
+    .push(" ORDER BY received_at DESC, created_at DESC")
+    .push_bind(" LIMIT ?", query.limit.unwrap_or(25).clamp(1, 100))
+    .push_bind(" OFFSET ?", query.offset.unwrap_or(0).max(0));
+
+    let payments = db.fetch_all(statement).await?.iter().map(Payment::from_row).collect::<Result<_, _>>()?;
+
+    Ok(payments)
+}
+
+async fn fetch_credit_notes(
+    db: &dyn Database,
+    invoice_id: &str,
+) -> Result<Vec<CreditNoteWithLines>, Box<dyn std::error::Error>> {
+    let credit_notes = db
+        .fetch_all(
+            Statement::new("SELECT * FROM credit_notes WHERE invoice_id = ?1 ORDER BY issued_at, number").bind(invoice_id),
+        )
+        .await?
+        .iter()
+        .map(CreditNote::from_row)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let lines = db
+        .fetch_all(
+            Statement::new(
+                "SELECT l.* FROM credit_note_lines l
+                JOIN credit_notes c ON c.id = l.credit_note_id
+                WHERE c.invoice_id = ?1
+                ORDER BY l.credit_note_id, l.position",
+            )
+            .bind(invoice_id),
+        )
+        .await?
+        .iter()
+        .map(CreditNoteLine::from_row)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut lines_by_note: std::collections::HashMap<String, Vec<CreditNoteLine>> = std::collections::HashMap::new();
+    for line in lines {
+        lines_by_note.entry(line.credit_note_id.clone()).or_default().push(line);
+    }
+
+    Ok(credit_notes
+        .into_iter()
+        .map(|credit_note| CreditNoteWithLines {
+            lines: lines_by_note.remove(&credit_note.id).unwrap_or_default(),
+            credit_note,
+        })
+        .collect())
+}
+
+// Quantity and amounts already credited against one invoice line
+async fn credited_so_far(
+    db: &dyn Database,
+    invoice_line_id: &str,
+) -> Result<(i64, i64, i64), Box<dyn std::error::Error>> {
+    let row = db
+        .fetch_one(
+            Statement::new(
+                "SELECT
+                    CAST(COALESCE(SUM(quantity), 0) AS BIGINT) AS quantity,
+                    CAST(COALESCE(SUM(net_amount), 0) AS BIGINT) AS net_amount,
+                    CAST(COALESCE(SUM(tax_amount), 0) AS BIGINT) AS tax_amount
+                FROM credit_note_lines WHERE invoice_line_id = ?1",
+            )
+            .bind(invoice_line_id),
+        )
+        .await?;
+
+    Ok((row.get("quantity")?, row.get("net_amount")?, row.get("tax_amount")?))
+}
+
+// Issued invoices are never edited; a credit note takes back some quantity of selected lines at the
+// invoiced price and tax. Crediting the last remaining units takes exactly what is left of the line,
+// so repeated partial credits never drift from the invoice by a rounding penny.
+pub async fn create_credit_note(
+    db: &dyn Database,
+    store: &dyn SequenceStore,
+    tenant_id: &str,
+    invoice_id: &str,
+    payload: &CreateCreditNotePayload,
+    created_by: &str,
+) -> Result<Option<InvoiceWithLines>, Box<dyn std::error::Error>> {
+    if payload.lines.is_empty() {
+        return Err(Box::new(AppError::Validation("A credit note needs at least one line".to_string())));
+    }
+
+    let reason = match payload.reason.as_deref().map(str::trim) {
+        Some(reason) if !reason.is_empty() => reason,
+        _ => return Err(Box::new(AppError::Validation("A credit note needs a reason".to_string()))),
+    };
+
+    let mut seen = std::collections::HashSet::new();
+    if !payload.lines.iter().all(|line| seen.insert(line.invoice_line_id.as_str())) {
+        return Err(Box::new(AppError::Validation(
+            "An invoice line may only appear once per credit note".to_string(),
+        )));
+    }
+
+    for attempt in 1..=NUMBERING_ATTEMPTS {
+        let invoice = match fetch_invoice(db, invoice_id, None).await? {
+            Some(invoice) => invoice,
+            None => return Ok(None),
+        };
+
+        check_invoice_tenant(&invoice, tenant_id)?;
+
+        let status = InvoiceStatus::parse(&invoice.status);
+        if !matches!(
+            status,
+            Some(InvoiceStatus::Issued | InvoiceStatus::PartiallyPaid | InvoiceStatus::Paid | InvoiceStatus::Overdue)
+        ) {
+            return Err(Box::new(AppError::Conflict(format!("Cannot credit a {} invoice", invoice.status))));
+        }
+
+        let invoice_lines = fetch_invoice_lines(db, invoice_id).await?;
+
+        let mut credited = Vec::with_capacity(payload.lines.len());
+        for requested in &payload.lines {
+            let line = invoice_lines
+                .iter()
+                .find(|line| line.id == requested.invoice_line_id)
+                .ok_or_else(|| AppError::Validation(format!("Line {} is not on this invoice", requested.invoice_line_id)))?;
+
+            if requested.quantity <= 0 {
+                return Err(Box::new(AppError::Validation("Credited quantity must be positive".to_string())));
+            }
+
+            let (quantity_so_far, net_so_far, tax_so_far) = credited_so_far(db, &line.id).await?;
+            let remaining = line.quantity as i64 - quantity_so_far;
+            if requested.quantity as i64 > remaining {
+                return Err(Box::new(AppError::Validation(format!(
+                    "Only {} of line {} remain to be credited",
+                    remaining, line.id
+                ))));
+            }
+
+            let (net, tax) = if requested.quantity as i64 == remaining {
+                (
+                    Money::from_minor(line.net_amount.minor() - net_so_far, invoice.currency),
+                    Money::from_minor(line.tax_amount.minor() - tax_so_far, invoice.currency),
+                )
+            } else {
+                let quantity = requested.quantity as i64;
+                let of = line.quantity as i64;
+                (
+                    line.net_amount.scale(quantity, of, money::RoundingMode::HalfUp).map_err(AppError::from)?,
+                    line.tax_amount.scale(quantity, of, money::RoundingMode::HalfUp).map_err(AppError::from)?,
+                )
+            };
+
+            credited.push((line, requested.quantity, net, tax));
+        }
+
+        let subtotal =
+            Money::sum(invoice.currency, credited.iter().map(|(_, _, net, _)| *net)).map_err(AppError::from)?;
+        let tax_total =
+            Money::sum(invoice.currency, credited.iter().map(|(_, _, _, tax)| *tax)).map_err(AppError::from)?;
+        let total = subtotal.checked_add(tax_total).map_err(AppError::from)?;
+
+        let credit_note_id = Uuid::new_v4().to_string();
+        let now = Utc::now().naive_utc();
+        let claim = numbering::next_number(store, &invoice.tenant_id, numbering::CREDIT_NOTE, now.date()).await?;
+
+        let next = settlement_status(
+            &invoice,
+            invoice.amount_paid.minor(),
+            invoice.amount_credited.minor() + total.minor(),
+            now.date(),
+        );
+        if status != Some(next) {
+            check_invoice_transition(&invoice.status, next)?;
+        }
+
+        let mut statements = vec![
+            claim.statement,
+            Statement::new(
+                "INSERT INTO credit_notes (
+                    id,
+                    number,
+                    invoice_id,
+                    user_id,
+                    reason,
+                    currency,
+                    subtotal,
+                    tax_total,
+                    total,
+                    issued_at,
+                    created_by,
+                    created_at
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?10)",
+            )
+            .bind(credit_note_id.as_str())
+            .bind(claim.number)
+            .bind(invoice.id.as_str())
+            .bind(invoice.user_id.as_str())
+            .bind(reason)
+            .bind(invoice.currency.code())
+            .bind(subtotal.minor())
+            .bind(tax_total.minor())
+            .bind(total.minor())
+            .bind(now)
+            .bind(created_by),
+        ];
+
+        for (position, (line, quantity, net, tax)) in credited.iter().enumerate() {
+            statements.push(
+                Statement::new(
+                    "INSERT INTO credit_note_lines (
+                        id,
+                        credit_note_id,
+                        invoice_line_id,
+                        position,
+                        description,
+                        quantity,
+                        unit_price,
+                        tax_name,
+                        tax_rate_bps,
+                        net_amount,
+                        tax_amount,
+                        gross_amount,
+                        currency
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                )
+                .bind(Uuid::new_v4().to_string())
+                .bind(credit_note_id.as_str())
+                .bind(line.id.as_str())
+                .bind(position as i32)
+                .bind(line.description.as_str())
+                .bind(*quantity)
+                .bind(line.unit_price.minor())
+                .bind(line.tax_name.as_str())
+                .bind(line.tax_rate_bps)
+                .bind(net.minor())
+                .bind(tax.minor())
+                .bind(net.minor() + tax.minor())
+                .bind(invoice.currency.code()),
+            );
+        }
+
+        // Like amount_paid, the credited amount is recomputed from the credit notes themselves; the
+        // invoice guard also covers the per-line totals read above, since every credit note bumps it
+        statements.push(
+            invoice_guard(&invoice, now)
+                .push(
+                    ", amount_credited = (SELECT CAST(COALESCE(SUM(total), 0) AS BIGINT)
+                    FROM credit_notes WHERE invoice_id = ?1)",
+                )
+                .push_bind(", status = ?", next.as_str())
+                .push(" WHERE id = ?1"),
+        );
+
+        match db.batch(statements).await {
+            Ok(_) => return fetch_invoice_details(db, invoice_id, None).await,
+            Err(e) if lost_race(&e) && attempt < NUMBERING_ATTEMPTS => continue,
+            Err(e) if lost_race(&e) => break,
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+
+    Err(Box::new(AppError::Conflict(INVOICE_CHANGED.to_string())))
 }
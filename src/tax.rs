@@ -0,0 +1,315 @@
+// src/tax.rs
+use super::*;
+use money::{Currency, Money, MoneyError, RoundingMode};
+use db::{Database, DbError, Row, Statement};
+use serde::{Deserialize, Serialize};
+
+pub const STANDARD: &str = "standard";
+
+const BASIS_POINTS: i64 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaxRounding {
+    // Round every line's tax, the document tax is the sum of the rounded lines
+    PerLine,
+    // Round once per rate over the whole document, then spread the pennies back over the lines
+    PerDocument,
+}
+
+impl TaxRounding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TaxRounding::PerLine => "per_line",
+            TaxRounding::PerDocument => "per_document",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "per_line" => Some(TaxRounding::PerLine),
+            "per_document" => Some(TaxRounding::PerDocument),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct TaxRate {
+    pub id: String,
+    pub country: String,
+    pub state: Option<String>,
+    pub tax_class: String,
+    pub name: String,
+    pub rate_bps: i32,
+}
+
+impl TaxRate {
+    pub fn from_row(row: &Row) -> Result<Self, DbError> {
+        Ok(TaxRate {
+            id: row.get("id")?,
+            country: row.get("country")?,
+            state: row.get("state")?,
+            tax_class: row.get("tax_class")?,
+            name: row.get("name")?,
+            rate_bps: row.get("rate_bps")?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TaxRatePayload {
+    pub country: String,
+    pub state: Option<String>,
+    pub tax_class: String,
+    pub name: String,
+    pub rate_bps: i32,
+}
+
+pub struct Jurisdiction {
+    pub country: Option<String>,
+    pub state: Option<String>,
+}
+
+pub struct TaxableLine<'a> {
+    pub amount: Money,
+    pub tax_class: &'a str,
+}
+
+#[derive(Clone, Debug)]
+pub struct LineTax {
+    pub tax_name: String,
+    pub rate_bps: i32,
+    pub net: Money,
+    pub tax: Money,
+    pub gross: Money,
+}
+
+// A state-level rate wins over the country-wide rate for the same class
+fn rate_for<'a>(rates: &'a [TaxRate], jurisdiction: &Jurisdiction, tax_class: &str) -> Option<&'a TaxRate> {
+    let country = jurisdiction.country.as_deref()?;
+    let candidates = rates
+        .iter()
+        .filter(|rate| rate.tax_class == tax_class && rate.country.eq_ignore_ascii_case(country));
+
+    let mut country_wide = None;
+    for rate in candidates {
+        match (&rate.state, &jurisdiction.state) {
+            (Some(rate_state), Some(state)) if rate_state.eq_ignore_ascii_case(state) => return Some(rate),
+            (None, _) => country_wide = Some(rate),
+            _ => {}
+        }
+    }
+
+    country_wide
+}
+
+// Share of `amount` that is tax: rate on top for exclusive prices, rate inside for inclusive ones
+fn tax_on(amount: Money, rate_bps: i32, inclusive: bool) -> Result<Money, MoneyError> {
+    let rate = rate_bps as i64;
+    let denominator = if inclusive { BASIS_POINTS + rate } else { BASIS_POINTS };
+    amount.scale(rate, denominator, RoundingMode::HalfUp)
+}
+
+pub fn calculate(
+    currency: Currency,
+    lines: &[TaxableLine],
+    rates: &[TaxRate],
+    jurisdiction: &Jurisdiction,
+    inclusive: bool,
+    rounding: TaxRounding,
+) -> Result<Vec<LineTax>, MoneyError> {
+    let applied: Vec<(String, i32)> = lines
+        .iter()
+        .map(|line| match rate_for(rates, jurisdiction, line.tax_class) {
+            Some(rate) => (rate.name.clone(), rate.rate_bps),
+            None => ("No tax".to_string(), 0),
+        })
+        .collect();
+
+    let mut taxes = lines
+        .iter()
+        .zip(&applied)
+        .map(|(line, (_, rate_bps))| tax_on(line.amount, *rate_bps, inclusive))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if rounding == TaxRounding::PerDocument {
+        let mut groups: Vec<i32> = applied.iter().map(|(_, rate_bps)| *rate_bps).collect();
+        groups.sort_unstable();
+        groups.dedup();
+
+        for rate_bps in groups {
+            let members: Vec<usize> = (0..lines.len()).filter(|i| applied[*i].1 == rate_bps).collect();
+            let group_amount = Money::sum(currency, members.iter().map(|i| lines[*i].amount))?;
+            let group_tax = tax_on(group_amount, rate_bps, inclusive)?;
+            let line_tax = Money::sum(currency, members.iter().map(|i| taxes[*i]))?;
+
+            // Whatever the per-line rounding lost or gained lands on the largest line
+            let difference = group_tax.checked_sub(line_tax)?;
+            if let Some(largest) = members.iter().copied().max_by_key(|i| lines[*i].amount.minor()) {
+                taxes[largest] = taxes[largest].checked_add(difference)?;
+            }
+        }
+    }
+
+    lines
+        .iter()
+        .zip(applied)
+        .zip(taxes)
+        .map(|((line, (tax_name, rate_bps)), tax)| {
+            let (net, gross) = if inclusive {
+                (line.amount.checked_sub(tax)?, line.amount)
+            } else {
+                (line.amount, line.amount.checked_add(tax)?)
+            };
+            Ok(LineTax {
+                tax_name,
+                rate_bps,
+                net,
+                tax,
+                gross,
+            })
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct TaxSummary {
+    pub name: String,
+    pub rate_bps: i32,
+    pub taxable: Money,
+    pub tax: Money,
+}
+
+#[derive(Serialize)]
+pub struct TaxBreakdown {
+    pub prices_include_tax: bool,
+    pub rounding: TaxRounding,
+    pub subtotal: Money,
+    pub tax_total: Money,
+    pub total: Money,
+    pub rates: Vec<TaxSummary>,
+}
+
+impl TaxBreakdown {
+    pub fn from_lines<'a>(
+        currency: Currency,
+        prices_include_tax: bool,
+        rounding: TaxRounding,
+        lines: impl IntoIterator<Item = &'a LineTax>,
+    ) -> Result<Self, MoneyError> {
+        let mut rates: Vec<TaxSummary> = Vec::new();
+        let mut subtotal = Money::zero(currency);
+        let mut tax_total = Money::zero(currency);
+
+        for line in lines {
+            subtotal = subtotal.checked_add(line.net)?;
+            tax_total = tax_total.checked_add(line.tax)?;
+
+            match rates
+                .iter_mut()
+                .find(|summary| summary.rate_bps == line.rate_bps && summary.name == line.tax_name)
+            {
+                Some(summary) => {
+                    summary.taxable = summary.taxable.checked_add(line.net)?;
+                    summary.tax = summary.tax.checked_add(line.tax)?;
+                }
+                None => rates.push(TaxSummary {
+                    name: line.tax_name.clone(),
+                    rate_bps: line.rate_bps,
+                    taxable: line.net,
+                    tax: line.tax,
+                }),
+            }
+        }
+
+        Ok(TaxBreakdown {
+            prices_include_tax,
+            rounding,
+            total: subtotal.checked_add(tax_total)?,
+            subtotal,
+            tax_total,
+            rates,
+        })
+    }
+}
+
+pub async fn fetch_jurisdiction(
+    db: &dyn Database,
+    user_id: &str,
+) -> Result<Jurisdiction, Box<dyn std::error::Error>> {
+    let statement = Statement::new("SELECT country, state FROM profiles WHERE user_id = ?1").bind(user_id);
+
+    let jurisdiction = match db.fetch_optional(statement).await? {
+        Some(row) => Jurisdiction {
+            country: row.get("country")?,
+            state: row.get("state")?,
+        },
+        None => Jurisdiction { country: None, state: None },
+    };
+
+    Ok(jurisdiction)
+}
+
+pub async fn fetch_rates(
+    db: &dyn Database,
+    jurisdiction: &Jurisdiction,
+) -> Result<Vec<TaxRate>, Box<dyn std::error::Error>> {
+    let country = match &jurisdiction.country {
+        Some(country) => country,
+        None => return Ok(Vec::new()),
+    };
+
+    let statement = Statement::new("SELECT * FROM tax_rates WHERE UPPER(country) = UPPER(?1)").bind(country.as_str());
+    let rates = db.fetch_all(statement).await?.iter().map(TaxRate::from_row).collect::<Result<_, _>>()?;
+
+    Ok(rates)
+}
+
+pub async fn list_rates(db: &dyn Database) -> Result<Vec<TaxRate>, Box<dyn std::error::Error>> {
+    let statement = Statement::new("SELECT * FROM tax_rates ORDER BY country, state, tax_class");
+    let rates = db.fetch_all(statement).await?.iter().map(TaxRate::from_row).collect::<Result<_, _>>()?;
+
+    Ok(rates)
+}
+
+pub async fn create_rate(
+    db: &dyn Database,
+    payload: &TaxRatePayload,
+) -> Result<TaxRate, Box<dyn std::error::Error>> {
+    if !(0..=BASIS_POINTS as i32).contains(&payload.rate_bps) {
+        return Err(Box::new(utils::AppError::Validation(
+            "rate_bps must be between 0 and 10000".to_string(),
+        )));
+    }
+
+    let rate = TaxRate {
+        id: utils::generate_uuid(),
+        country: payload.country.to_uppercase(),
+        state: payload.state.clone(),
+        tax_class: payload.tax_class.clone(),
+        name: payload.name.clone(),
+        rate_bps: payload.rate_bps,
+    };
+
+    let statement = Statement::new(
+        "INSERT INTO tax_rates (id, country, state, tax_class, name, rate_bps)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )
+    .bind(rate.id.as_str())
+    .bind(rate.country.as_str())
+    .bind(rate.state.clone())
+    .bind(rate.tax_class.as_str())
+    .bind(rate.name.as_str())
+    .bind(rate.rate_bps);
+
+    db.execute(statement).await?;
+
+    Ok(rate)
+}
+
+pub async fn delete_rate(db: &dyn Database, rate_id: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let deleted = db.execute(Statement::new("DELETE FROM tax_rates WHERE id = ?1").bind(rate_id)).await?;
+
+    Ok(deleted > 0)
+}
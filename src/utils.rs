@@ -1,118 +1,158 @@
 // src/utils.rs
-use actix_web::{web, HttpResponse, ResponseError};
-use actix_web::http::header::{AUTHORIZATION, BEARER};
-use actix_web::dev::ServiceRequest;
-use actix_web::error::ErrorBadRequest;
-use futures_util::future::{err, ok, Ready};
+use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
 use jsonwebtoken::{encode, Header, EncodingKey};
 use serde::{Serialize, Deserialize};
+use serde_json::json;
 use chrono::{Duration, Utc};
-use sqlx::{PgPool};
 use uuid::Uuid;
-use lazy_static::lazy_static;
-use dotenv::dotenv;
-use std::env;
-
-// Load environment variables
-lazy_static! {
-    static ref SECRET_KEY: String = env::var("SECRET_KEY").expect("SECRET_KEY must be set");
-    static ref JWT_EXPIRY: i64 = env::var("JWT_EXPIRY")
-        .unwrap_or_else(|_| "3600".to_string()) // Default to 1 hour if not set
-        .parse()
-        .expect("JWT_EXPIRY must be a valid integer");
-}
+use crate::config::Config;
+use crate::db::DbError;
 
 #[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: i64,
+pub struct Claims {
+    pub sub: String,
+    pub jti: String,
+    pub iat: i64,
+    pub exp: i64,
+    // `iat` to the millisecond, so a sign-out-everywhere cutoff can tell apart tokens issued in
+    // the same second as it; tokens minted before this claim existed fall back to `iat`
+    #[serde(default)]
+    pub iat_ms: Option<i64>,
 }
 
 impl Claims {
-    fn new(user_id: &str) -> Self {
+    fn new(config: &Config, user_id: &str) -> Self {
+        let now = Utc::now();
         Claims {
             sub: user_id.to_string(),
-            exp: Utc::now().timestamp() + *JWT_EXPIRY,
+            jti: generate_uuid(),
+            iat: now.timestamp(),
+            exp: now.timestamp() + config.jwt_expiry,
+            iat_ms: Some(now.timestamp_millis()),
         }
     }
-}
 
-pub type Bearer = String;
-
-pub fn extract_bearer(req: ServiceRequest) -> impl Future<Item=Bearer, Error=ErrorBadRequest> {
-    let auth_header = req.headers().get(AUTHORIZATION);
-    match auth_header {
-        Some(auth_header) => {
-            let auth_header = auth_header.to_str().unwrap();
-            if auth_header.starts_with(BEARER) {
-                let bearer_token = auth_header.trim_start_matches(BEARER).trim();
-                ok(bearer_token.to_owned())
-            } else {
-                err(ErrorBadRequest("Invalid Authorization header"))
-            }
-        },
-        None => err(ErrorBadRequest("Authorization header missing")),
+    pub fn issued_at_ms(&self) -> i64 {
+        self.iat_ms.unwrap_or(self.iat * 1000)
     }
 }
 
-pub fn generate_jwt(user_id: &str) -> String {
-    let claims = Claims::new(user_id);
-    encode(&Header::default(), &claims, &EncodingKey::from_secret(SECRET_KEY.as_ref())).unwrap()
+pub fn generate_jwt(config: &Config, user_id: &str) -> String {
+    let claims = Claims::new(config, user_id);
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(config.secret_key.as_ref())).unwrap()
 }
 
-pub fn validate_jwt(token: &str) -> Result<Claims, jsonwebtoken::errors::ErrorKind> {
-    let secret_key = EncodingKey::from_secret(SECKET_KEY.as_ref());
-    jsonwebtoken::decode::<Claims>(token, &secret_key, &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256))
+// Refresh tokens are opaque random strings; only their SHA-256 digest is persisted
+pub fn generate_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut bytes);
+    hex::encode(bytes)
 }
 
-pub fn generate_uuid() -> String {
-    Uuid::new_v4().to_string()
+pub fn hash_refresh_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
-pub async fn fetch_user_by_id(pool: &PgPool, user_id: &str) -> Result<models::User, Box<dyn std::error::Error>> {
-    let user = sqlx::query_as!(
-        models::User,
-        r#"SELECT * FROM users WHERE id = $1"#,
-        user_id,
-    )
-    .fetch_one(pool)
-    .await?;
+pub fn refresh_token_expires_at(config: &Config) -> chrono::NaiveDateTime {
+    (Utc::now() + Duration::seconds(config.refresh_token_expiry)).naive_utc()
+}
 
-    Ok(user)
+pub fn validate_jwt(config: &Config, token: &str) -> Result<Claims, jsonwebtoken::errors::ErrorKind> {
+    let secret_key = jsonwebtoken::DecodingKey::from_secret(config.secret_key.as_ref());
+    jsonwebtoken::decode::<Claims>(token, &secret_key, &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256))
+        .map(|decoded| decoded.claims)
+        .map_err(|e| e.into_kind())
+}
+
+pub fn is_unique_violation(e: &(dyn std::error::Error + 'static)) -> bool {
+    match e.downcast_ref::<DbError>() {
+        Some(db_err) => db_err.is_unique_violation(),
+        None => matches!(e.downcast_ref::<AppError>(), Some(AppError::Database(db_err)) if db_err.is_unique_violation()),
+    }
 }
 
-pub fn success_response<T>(data: Option<T>, message: &str, status_code: actix_web::http::StatusCode) -> HttpResponse
+pub fn generate_uuid() -> String {
+    Uuid::new_v4().to_string()
+}
+
+pub fn success_response<T>(data: Option<T>, message: &str, status_code: StatusCode) -> Response
 where
     T: Serialize,
 {
-    HttpResponse::build(status_code)
-        .json(json!({
+    (
+        status_code,
+        Json(json!({
             "status": "success",
             "message": message,
             "data": data
-        }))
+        })),
+    )
+        .into_response()
 }
 
-pub fn error_response(message: &str, status_code: actix_web::http::StatusCode) -> HttpResponse {
-    HttpResponse::build(status_code)
-        .json(json!({
+pub fn error_response(message: &str, status_code: StatusCode) -> Response {
+    (
+        status_code,
+        Json(json!({
             "status": "error",
             "message": message,
             "data": null
-        }))
+        })),
+    )
+        .into_response()
+}
+
+// Maps a boxed service error onto a response, surfacing typed AppError variants to the client
+pub fn service_error_response(e: &(dyn std::error::Error + 'static), fallback: &str) -> Response {
+    match e.downcast_ref::<AppError>() {
+        Some(
+            app_error @ (AppError::Validation(_)
+            | AppError::NotFound(_)
+            | AppError::Forbidden(_)
+            | AppError::Conflict(_)
+            | AppError::InvalidTransition { .. }),
+        ) => {
+            error_response(&app_error.to_string(), app_error.status_code())
+        }
+        _ => error_response(&format!("{}: {}", fallback, e), StatusCode::INTERNAL_SERVER_ERROR),
+    }
 }
 
 // Custom error types and implementations
 #[derive(Debug)]
 pub enum AppError {
-    Database(sqlx::Error),
+    Database(DbError),
     Jwt(jsonwebtoken::errors::ErrorKind),
     Validation(String),
+    NotFound(String),
+    Forbidden(String),
+    Conflict(String),
+    InvalidTransition { from: String, to: String },
+    // Only the native seeder raises this today
+    #[cfg_attr(not(feature = "native"), allow(dead_code))]
     InternalServerError(String),
 }
 
-impl From<sqlx::Error> for AppError {
-    fn from(e: sqlx::Error) -> Self {
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Database(e) => write!(f, "{}", e),
+            AppError::Jwt(e) => write!(f, "{:?}", e),
+            AppError::Validation(message)
+            | AppError::NotFound(message)
+            | AppError::Forbidden(message)
+            | AppError::Conflict(message)
+            | AppError::InternalServerError(message) => f.write_str(message),
+            AppError::InvalidTransition { from, to } => write!(f, "Illegal status transition from {} to {}", from, to),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<DbError> for AppError {
+    fn from(e: DbError) -> Self {
         AppError::Database(e)
     }
 }
@@ -123,22 +163,23 @@ impl From<jsonwebtoken::errors::ErrorKind> for AppError {
     }
 }
 
-impl ResponseError for AppError {
-    fn status_code(&self) -> actix_web::http::StatusCode {
+impl AppError {
+    pub fn status_code(&self) -> StatusCode {
         match self {
             AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Jwt(_) => StatusCode::UNAUTHORIZED,
             AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::InvalidTransition { .. } => StatusCode::CONFLICT,
             AppError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+}
 
-    fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .json(json!({
-                "status": "error",
-                "message": format!("{:?}", self),
-                "data": null
-            }))
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        error_response(&format!("{:?}", self), self.status_code())
     }
 }
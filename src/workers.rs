@@ -0,0 +1,56 @@
+// src/workers.rs
+use super::*;
+use config::Config;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use tower_service::Service;
+use worker::{event, Context, Env, HttpRequest};
+
+// An isolate serves many requests; keeping the state here lets the revocation and PDF caches
+// outlive a single request just as they do in the native server
+static STATE: OnceLock<Arc<AppState>> = OnceLock::new();
+static MIGRATED: AtomicBool = AtomicBool::new(false);
+
+fn state(env: &Env) -> worker::Result<Arc<AppState>> {
+    if let Some(state) = STATE.get() {
+        return Ok(state.clone());
+    }
+
+    // Secrets (`wrangler secret put`) take precedence over plain [vars] of the same name
+    let config = Config::load(|key| {
+        env.secret(key)
+            .map(|secret| secret.to_string())
+            .or_else(|_| env.var(key).map(|var| var.to_string()))
+            .ok()
+    })
+    .map_err(|e| worker::Error::RustError(e.to_string()))?;
+
+    let db: Arc<dyn db::Database> = Arc::new(db::D1::new(env.d1("DB")?));
+
+    Ok(STATE.get_or_init(|| Arc::new(AppState::new(config, db))).clone())
+}
+
+// D1 is migrated by the first request each isolate serves; set AUTO_MIGRATE = "false" in
+// wrangler.toml [vars] to apply them only through POST /admin/migrations/apply
+async fn migrate_once(state: &AppState) -> worker::Result<()> {
+    if !state.config.auto_migrate || MIGRATED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    if let Err(e) = migrations::migrate(state.db.as_ref()).await {
+        MIGRATED.store(false, Ordering::SeqCst);
+        return Err(worker::Error::RustError(format!("Failed to apply migrations: {}", e)));
+    }
+
+    Ok(())
+}
+
+#[event(fetch)]
+async fn fetch(req: HttpRequest, env: Env, _ctx: Context) -> worker::Result<axum::http::Response<axum::body::Body>> {
+    console_error_panic_hook::set_once();
+
+    let state = state(&env)?;
+    migrate_once(&state).await?;
+
+    Ok(router(state).call(req).await?)
+}